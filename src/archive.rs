@@ -0,0 +1,97 @@
+use crate::models::{Execution, ExecutionJob};
+use anyhow::Result;
+use sqlx::postgres::{PgPool, PgPoolOptions};
+
+const SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS executions (
+    id UUID PRIMARY KEY,
+    user_id TEXT NOT NULL,
+    workspace_id TEXT,
+    code TEXT NOT NULL,
+    language TEXT NOT NULL,
+    args TEXT[],
+    environment TEXT,
+    timeout_seconds INTEGER,
+    status TEXT NOT NULL,
+    exit_code INTEGER,
+    stdout TEXT,
+    stderr TEXT,
+    termination TEXT,
+    created_at TIMESTAMPTZ NOT NULL,
+    started_at TIMESTAMPTZ,
+    completed_at TIMESTAMPTZ
+);
+CREATE INDEX IF NOT EXISTS executions_user_id_idx ON executions (user_id);
+"#;
+
+/// Durable store for finished executions. Redis holds the hot copy with a
+/// TTL; this keeps the record after it expires there.
+pub struct ExecutionArchive {
+    pool: PgPool,
+}
+
+impl ExecutionArchive {
+    pub async fn connect(database_url: &str, max_connections: u32) -> Result<Self> {
+        let pool = PgPoolOptions::new()
+            .max_connections(max_connections)
+            .connect(database_url)
+            .await?;
+        sqlx::raw_sql(SCHEMA).execute(&pool).await?;
+        Ok(Self { pool })
+    }
+
+    /// Check that the database answers.
+    pub async fn ping(&self) -> Result<()> {
+        sqlx::query("SELECT 1").execute(&self.pool).await?;
+        Ok(())
+    }
+
+    pub async fn archive(&self, job: &ExecutionJob) -> Result<()> {
+        let execution = Execution::from(job);
+        sqlx::query(
+            r#"
+            INSERT INTO executions (
+                id, user_id, workspace_id, code, language, args, environment,
+                timeout_seconds, status, exit_code, stdout, stderr, termination,
+                created_at, started_at, completed_at
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16)
+            ON CONFLICT (id) DO UPDATE SET
+                status = EXCLUDED.status,
+                exit_code = EXCLUDED.exit_code,
+                stdout = EXCLUDED.stdout,
+                stderr = EXCLUDED.stderr,
+                termination = EXCLUDED.termination,
+                started_at = EXCLUDED.started_at,
+                completed_at = EXCLUDED.completed_at
+            "#,
+        )
+        .bind(execution.id)
+        .bind(&execution.user_id)
+        .bind(&execution.workspace_id)
+        .bind(&execution.code)
+        .bind(&execution.language)
+        .bind(&execution.args)
+        .bind(execution.environment.as_ref().map(serde_json::to_string).transpose()?)
+        .bind(execution.timeout_seconds)
+        .bind(execution.status.as_str())
+        .bind(execution.exit_code)
+        .bind(&execution.stdout)
+        .bind(&execution.stderr)
+        .bind(execution.termination.as_ref().map(serde_json::to_string).transpose()?)
+        .bind(execution.created_at)
+        .bind(execution.started_at)
+        .bind(execution.completed_at)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn purge_user(&self, user_id: &str) -> Result<u64> {
+        let result = sqlx::query("DELETE FROM executions WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+}
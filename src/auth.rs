@@ -0,0 +1,726 @@
+use crate::config::Sensitive;
+use crate::error::{ErrorBody, ServiceError};
+use crate::models::{ExecutionJob, Priority};
+use anyhow::{Context, Result};
+use axum::{
+    extract::{Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use jsonwebtoken::jwk::{AlgorithmParameters, EllipticCurve, Jwk, JwkSet};
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+
+// Minimum time between JWKS refetches triggered by an unknown key id
+const JWKS_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+// Roles allowed to act on other users' executions
+pub const ADMIN_ROLE: &str = "admin";
+pub const SERVICE_ROLE: &str = "service";
+
+/// The authenticated caller, attached to request extensions.
+#[derive(Debug, Clone)]
+pub struct Principal {
+    pub subject: String,
+    pub roles: Vec<String>,
+    pub workspace_id: Option<String>,
+}
+
+impl Principal {
+    // With auth disabled every caller is this one user. It only gets the
+    // admin role when the operator asks for it with `anonymous_admin`.
+    fn anonymous(admin: bool) -> Self {
+        Self {
+            subject: "anonymous".to_string(),
+            roles: if admin { vec![ADMIN_ROLE.to_string()] } else { vec![] },
+            workspace_id: None,
+        }
+    }
+
+    pub fn has_role(&self, role: &str) -> bool {
+        self.roles.iter().any(|r| r == role)
+    }
+
+    pub fn is_admin(&self) -> bool {
+        self.has_role(ADMIN_ROLE)
+    }
+
+    pub fn require_admin(&self) -> Result<(), ServiceError> {
+        if self.is_admin() {
+            Ok(())
+        } else {
+            Err(ServiceError::Forbidden("Admin role required".to_string()))
+        }
+    }
+
+    /// Admins and service accounts may act across users.
+    pub fn is_privileged(&self) -> bool {
+        self.is_admin() || self.has_role(SERVICE_ROLE)
+    }
+
+    /// Service accounts submit background work; everyone else is assumed to
+    /// be waiting on the result.
+    pub fn default_priority(&self) -> Priority {
+        if self.has_role(SERVICE_ROLE) {
+            Priority::Batch
+        } else {
+            Priority::Interactive
+        }
+    }
+
+    /// Whether the caller may see or change something owned by `user_id`.
+    pub fn owns(&self, user_id: Option<&str>) -> bool {
+        self.is_privileged() || user_id == Some(self.subject.as_str())
+    }
+
+    /// Whether the caller may manage what belongs to a whole workspace:
+    /// their own workspace, or any one when privileged.
+    pub fn in_workspace(&self, workspace_id: &str) -> bool {
+        self.is_privileged() || self.workspace_id.as_deref() == Some(workspace_id)
+    }
+
+    pub fn can_access(&self, job: &ExecutionJob) -> bool {
+        self.owns(job.user_id.as_deref())
+    }
+
+    /// Resolve who a new execution belongs to. Callers own what they submit;
+    /// only privileged callers may submit on behalf of another user, and a
+    /// workspace other than the token's own can only be targeted by
+    /// privileged callers.
+    pub fn resolve_owner(
+        &self,
+        requested_user: Option<String>,
+        requested_workspace: Option<String>,
+    ) -> Result<(String, Option<String>), ServiceError> {
+        let user_id = match requested_user {
+            Some(user) if user != self.subject => {
+                if !self.is_privileged() {
+                    return Err(ServiceError::Forbidden("Cannot submit executions for another user".to_string()));
+                }
+                user
+            }
+            _ => self.subject.clone(),
+        };
+
+        let workspace_id = match (&self.workspace_id, requested_workspace) {
+            (_, Some(requested)) if self.is_privileged() => Some(requested),
+            (Some(own), Some(requested)) if *own == requested => Some(requested),
+            (_, Some(_)) => {
+                return Err(ServiceError::Forbidden("Cannot act in another workspace".to_string()));
+            }
+            (own, None) => own.clone(),
+        };
+
+        Ok((user_id, workspace_id))
+    }
+
+    /// Secrets are looked up in the scope of the job's owner, so a job may
+    /// only use them when it runs as the caller in the caller's own
+    /// workspace, whatever the caller's role.
+    pub fn check_secret_use(&self, user_id: &str, workspace_id: Option<&str>) -> Result<(), ServiceError> {
+        if user_id == self.subject && workspace_id == self.workspace_id.as_deref() {
+            Ok(())
+        } else {
+            Err(ServiceError::Forbidden(
+                "Secrets can only be used by executions run as yourself in your own workspace".to_string(),
+            ))
+        }
+    }
+
+    /// The user whose executions a listing may cover. Unprivileged callers
+    /// only ever see their own.
+    pub fn resolve_list_filter(&self, requested_user: Option<String>) -> Result<Option<String>, ServiceError> {
+        if self.is_privileged() {
+            return Ok(requested_user);
+        }
+        match requested_user {
+            Some(user) if user != self.subject => {
+                Err(ServiceError::Forbidden("Cannot list another user's executions".to_string()))
+            }
+            _ => Ok(Some(self.subject.clone())),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum AuthError {
+    #[error("Missing authorization header")]
+    Missing,
+
+    #[error("Invalid token: {0}")]
+    Invalid(String),
+
+    #[error("Insufficient scope: {0}")]
+    Forbidden(String),
+
+    #[error("Authentication unavailable: {0}")]
+    Unavailable(String),
+}
+
+impl IntoResponse for AuthError {
+    fn into_response(self) -> Response {
+        let (status, code, retryable) = match self {
+            AuthError::Missing | AuthError::Invalid(_) => (StatusCode::UNAUTHORIZED, "unauthenticated", false),
+            AuthError::Forbidden(_) => (StatusCode::FORBIDDEN, "forbidden", false),
+            AuthError::Unavailable(_) => (StatusCode::SERVICE_UNAVAILABLE, "auth_unavailable", true),
+        };
+        (status, Json(ErrorBody::new(code, self.to_string(), Vec::new(), retryable))).into_response()
+    }
+}
+
+impl From<AuthError> for tonic::Status {
+    fn from(error: AuthError) -> Self {
+        match error {
+            AuthError::Missing | AuthError::Invalid(_) => tonic::Status::unauthenticated(error.to_string()),
+            AuthError::Forbidden(_) => tonic::Status::permission_denied(error.to_string()),
+            AuthError::Unavailable(_) => tonic::Status::unavailable(error.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum KeySource {
+    File(String),
+    Url(String),
+}
+
+#[derive(Debug, Clone)]
+pub enum AuthMode {
+    /// Every request is accepted as an anonymous principal
+    Disabled,
+    /// Bearer tokens are JWTs verified against a JWKS
+    Jwt(KeySource),
+    /// Bearer tokens are checked with an RFC 7662 introspection endpoint
+    Introspection(String),
+}
+
+/// Where bearer tokens are checked. Setting none of `jwks_path`,
+/// `jwks_url` and `introspection_url` disables auth.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    pub jwks_path: Option<String>,
+    pub jwks_url: Option<String>,
+    pub introspection_url: Option<String>,
+    pub issuer: Option<String>,
+    pub audience: Option<String>,
+    pub required_scopes: Vec<String>,
+    // Credentials for the introspection endpoint
+    pub client_id: Option<String>,
+    pub client_secret: Option<Sensitive>,
+    // With auth disabled, give the anonymous caller the admin role
+    pub anonymous_admin: bool,
+}
+
+impl AuthConfig {
+    pub fn mode(&self) -> AuthMode {
+        if let Some(path) = &self.jwks_path {
+            AuthMode::Jwt(KeySource::File(path.clone()))
+        } else if let Some(url) = &self.jwks_url {
+            AuthMode::Jwt(KeySource::Url(url.clone()))
+        } else if let Some(url) = &self.introspection_url {
+            AuthMode::Introspection(url.clone())
+        } else {
+            AuthMode::Disabled
+        }
+    }
+}
+
+pub fn split_scopes(scopes: &str) -> Vec<String> {
+    scopes
+        .split([' ', ','])
+        .filter(|s| !s.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+// Claims shared by JWTs and introspection responses
+#[derive(Debug, Deserialize)]
+struct Claims {
+    sub: Option<String>,
+    #[serde(default)]
+    scope: Option<String>,
+    #[serde(default)]
+    scp: Option<Vec<String>>,
+    #[serde(default)]
+    roles: Vec<String>,
+    #[serde(default)]
+    workspace_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct IntrospectionResponse {
+    active: bool,
+    #[serde(default)]
+    iss: Option<String>,
+    #[serde(default)]
+    aud: Option<serde_json::Value>,
+    #[serde(flatten)]
+    claims: Claims,
+}
+
+struct CachedJwks {
+    keys: JwkSet,
+    fetched_at: Instant,
+}
+
+/// Validates bearer tokens according to the configured `AuthMode`.
+pub struct Authenticator {
+    config: AuthConfig,
+    mode: AuthMode,
+    jwks: RwLock<Option<CachedJwks>>,
+    http: reqwest::Client,
+}
+
+impl Authenticator {
+    pub async fn new(config: AuthConfig) -> Result<Self> {
+        let authenticator = Self {
+            mode: config.mode(),
+            config,
+            jwks: RwLock::new(None),
+            http: reqwest::Client::builder()
+                .timeout(Duration::from_secs(5))
+                .build()?,
+        };
+
+        match &authenticator.mode {
+            AuthMode::Disabled if authenticator.config.anonymous_admin => {
+                warn!("Authentication is disabled; all requests are accepted with the admin role")
+            }
+            AuthMode::Disabled => warn!("Authentication is disabled; all requests are accepted as one user"),
+            AuthMode::Jwt(source) => {
+                authenticator.refresh_jwks().await?;
+                info!("Validating JWTs against {:?}", source);
+            }
+            AuthMode::Introspection(url) => info!("Validating tokens via introspection at {}", url),
+        }
+
+        Ok(authenticator)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !matches!(self.mode, AuthMode::Disabled)
+    }
+
+    /// Authenticate the value of an `authorization` header.
+    pub async fn authenticate(&self, authorization: Option<&str>) -> Result<Principal, AuthError> {
+        if !self.is_enabled() {
+            return Ok(Principal::anonymous(self.config.anonymous_admin));
+        }
+
+        let token = authorization
+            .ok_or(AuthError::Missing)?
+            .strip_prefix("Bearer ")
+            .ok_or_else(|| AuthError::Invalid("expected a bearer token".to_string()))?
+            .trim();
+        if token.is_empty() {
+            return Err(AuthError::Invalid("empty token".to_string()));
+        }
+
+        let claims = match &self.mode {
+            AuthMode::Jwt(_) => self.validate_jwt(token).await?,
+            AuthMode::Introspection(url) => self.introspect(url, token).await?,
+            AuthMode::Disabled => unreachable!(),
+        };
+
+        self.to_principal(claims)
+    }
+
+    fn to_principal(&self, claims: Claims) -> Result<Principal, AuthError> {
+        let subject = claims
+            .sub
+            .filter(|sub| !sub.is_empty())
+            .ok_or_else(|| AuthError::Invalid("token has no subject".to_string()))?;
+
+        let mut scopes = claims.scp.unwrap_or_default();
+        if let Some(scope) = &claims.scope {
+            scopes.extend(split_scopes(scope));
+        }
+
+        let missing: Vec<&str> = self
+            .config
+            .required_scopes
+            .iter()
+            .filter(|required| !scopes.contains(required))
+            .map(String::as_str)
+            .collect();
+        if !missing.is_empty() {
+            return Err(AuthError::Forbidden(missing.join(" ")));
+        }
+
+        Ok(Principal {
+            subject,
+            roles: claims.roles,
+            workspace_id: claims.workspace_id,
+        })
+    }
+
+    async fn validate_jwt(&self, token: &str) -> Result<Claims, AuthError> {
+        let header = decode_header(token).map_err(|e| AuthError::Invalid(e.to_string()))?;
+        let kid = header.kid.clone();
+
+        let mut key = self.find_key(kid.as_deref()).await;
+        if key.is_none() && self.jwks_refresh_due().await {
+            // The issuer may have rotated keys since the last fetch
+            if let Err(e) = self.refresh_jwks().await {
+                return Err(AuthError::Unavailable(e.to_string()));
+            }
+            key = self.find_key(kid.as_deref()).await;
+        }
+        let (key, algorithms) = key.ok_or_else(|| AuthError::Invalid("unknown signing key".to_string()))?;
+        // The header is the token's own claim; only the key decides
+        if !algorithms.contains(&header.alg) {
+            return Err(AuthError::Invalid(format!("algorithm {:?} not allowed for this key", header.alg)));
+        }
+
+        let mut validation = Validation::new(header.alg);
+        validation.algorithms = algorithms;
+        validation.set_required_spec_claims(&["exp", "sub"]);
+        if let Some(issuer) = &self.config.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        match &self.config.audience {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
+
+        decode::<Claims>(token, &key, &validation)
+            .map(|data| data.claims)
+            .map_err(|e| AuthError::Invalid(e.to_string()))
+    }
+
+    async fn find_key(&self, kid: Option<&str>) -> Option<(DecodingKey, Vec<Algorithm>)> {
+        let jwks = self.jwks.read().await;
+        let keys = &jwks.as_ref()?.keys;
+        let jwk = match kid {
+            Some(kid) => keys.find(kid)?,
+            // Without a key id only an unambiguous set can be used
+            None if keys.keys.len() == 1 => &keys.keys[0],
+            None => return None,
+        };
+        Some((DecodingKey::from_jwk(jwk).ok()?, key_algorithms(jwk)))
+    }
+
+    async fn jwks_refresh_due(&self) -> bool {
+        match &*self.jwks.read().await {
+            Some(cached) => cached.fetched_at.elapsed() >= JWKS_REFRESH_INTERVAL,
+            None => true,
+        }
+    }
+
+    async fn refresh_jwks(&self) -> Result<()> {
+        let keys: JwkSet = match &self.mode {
+            AuthMode::Jwt(KeySource::File(path)) => {
+                let contents = tokio::fs::read_to_string(path)
+                    .await
+                    .with_context(|| format!("Failed to read JWKS file {}", path))?;
+                serde_json::from_str(&contents).context("Invalid JWKS file")?
+            }
+            AuthMode::Jwt(KeySource::Url(url)) => self
+                .http
+                .get(url)
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await
+                .context("Invalid JWKS response")?,
+            _ => return Ok(()),
+        };
+
+        *self.jwks.write().await = Some(CachedJwks {
+            keys,
+            fetched_at: Instant::now(),
+        });
+        Ok(())
+    }
+
+    async fn introspect(&self, url: &str, token: &str) -> Result<Claims, AuthError> {
+        let mut request = self.http.post(url).form(&[("token", token)]);
+        if let Some(client_id) = &self.config.client_id {
+            request = request.basic_auth(client_id, self.config.client_secret.as_ref().map(Sensitive::expose));
+        }
+
+        let response: IntrospectionResponse = request
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| AuthError::Unavailable(e.to_string()))?
+            .json()
+            .await
+            .map_err(|e| AuthError::Unavailable(e.to_string()))?;
+
+        if !response.active {
+            return Err(AuthError::Invalid("token is not active".to_string()));
+        }
+        if let Some(issuer) = &self.config.issuer {
+            if response.iss.as_ref() != Some(issuer) {
+                return Err(AuthError::Invalid("unexpected issuer".to_string()));
+            }
+        }
+        if let Some(audience) = &self.config.audience {
+            let matches = match &response.aud {
+                Some(serde_json::Value::String(aud)) => aud == audience,
+                Some(serde_json::Value::Array(auds)) => auds.iter().any(|aud| aud == audience),
+                _ => false,
+            };
+            if !matches {
+                return Err(AuthError::Invalid("unexpected audience".to_string()));
+            }
+        }
+
+        Ok(response.claims)
+    }
+}
+
+// Algorithms a JWK may verify: its `alg` when it names one, otherwise those
+// its key type and curve allow. Symmetric keys must name theirs.
+fn key_algorithms(jwk: &Jwk) -> Vec<Algorithm> {
+    if let Some(alg) = jwk.common.key_algorithm {
+        return Algorithm::from_str(&alg.to_string()).into_iter().collect();
+    }
+    match &jwk.algorithm {
+        AlgorithmParameters::RSA(_) => vec![
+            Algorithm::RS256,
+            Algorithm::RS384,
+            Algorithm::RS512,
+            Algorithm::PS256,
+            Algorithm::PS384,
+            Algorithm::PS512,
+        ],
+        AlgorithmParameters::EllipticCurve(params) => match params.curve {
+            EllipticCurve::P256 => vec![Algorithm::ES256],
+            EllipticCurve::P384 => vec![Algorithm::ES384],
+            _ => vec![],
+        },
+        AlgorithmParameters::OctetKeyPair(params) if params.curve == EllipticCurve::Ed25519 => vec![Algorithm::EdDSA],
+        _ => vec![],
+    }
+}
+
+/// Axum middleware that authenticates the request and attaches the
+/// `Principal` to its extensions.
+pub async fn require_auth(
+    State(authenticator): State<Arc<Authenticator>>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, AuthError> {
+    let authorization = request
+        .headers()
+        .get(axum::http::header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok());
+
+    let principal = authenticator.authenticate(authorization).await?;
+    request.extensions_mut().insert(principal);
+    Ok(next.run(request).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn principal(roles: &[&str], workspace_id: Option<&str>) -> Principal {
+        Principal {
+            subject: "alice".to_string(),
+            roles: roles.iter().map(|role| role.to_string()).collect(),
+            workspace_id: workspace_id.map(str::to_string),
+        }
+    }
+
+    fn ws(id: &str) -> Option<String> {
+        Some(id.to_string())
+    }
+
+    #[test]
+    fn privileged_callers_may_target_any_workspace() {
+        for role in [ADMIN_ROLE, SERVICE_ROLE] {
+            for own in [None, Some("mine")] {
+                let (_, workspace) = principal(&[role], own).resolve_owner(None, ws("other")).unwrap();
+                assert_eq!(workspace, ws("other"));
+            }
+        }
+    }
+
+    #[test]
+    fn own_workspace_may_be_named_explicitly() {
+        let (user, workspace) = principal(&[], Some("mine")).resolve_owner(None, ws("mine")).unwrap();
+        assert_eq!(user, "alice");
+        assert_eq!(workspace, ws("mine"));
+    }
+
+    #[test]
+    fn unprivileged_callers_cannot_target_another_workspace() {
+        let err = principal(&[], Some("mine")).resolve_owner(None, ws("other")).unwrap_err();
+        assert!(matches!(err, ServiceError::Forbidden(_)));
+    }
+
+    #[test]
+    fn callers_without_a_workspace_claim_cannot_pick_one() {
+        let err = principal(&[], None).resolve_owner(None, ws("other")).unwrap_err();
+        assert!(matches!(err, ServiceError::Forbidden(_)));
+    }
+
+    #[test]
+    fn workspace_defaults_to_the_claim() {
+        assert_eq!(principal(&[], Some("mine")).resolve_owner(None, None).unwrap().1, ws("mine"));
+        assert_eq!(principal(&[], None).resolve_owner(None, None).unwrap().1, None);
+    }
+
+    #[test]
+    fn only_privileged_callers_submit_for_another_user() {
+        let err = principal(&[], None).resolve_owner(Some("bob".to_string()), None).unwrap_err();
+        assert!(matches!(err, ServiceError::Forbidden(_)));
+        let (user, _) = principal(&[SERVICE_ROLE], None).resolve_owner(Some("bob".to_string()), None).unwrap();
+        assert_eq!(user, "bob");
+        let (user, _) = principal(&[], None).resolve_owner(Some("alice".to_string()), None).unwrap();
+        assert_eq!(user, "alice");
+    }
+
+    #[test]
+    fn workspace_management_needs_the_claim_or_privilege() {
+        assert!(principal(&[], Some("mine")).in_workspace("mine"));
+        assert!(!principal(&[], Some("mine")).in_workspace("other"));
+        assert!(!principal(&[], None).in_workspace("other"));
+        assert!(principal(&[ADMIN_ROLE], None).in_workspace("other"));
+    }
+
+    #[test]
+    fn secrets_stay_in_the_callers_own_scope() {
+        let caller = principal(&[ADMIN_ROLE], Some("mine"));
+        assert!(caller.check_secret_use("alice", Some("mine")).is_ok());
+        assert!(caller.check_secret_use("alice", Some("other")).is_err());
+        assert!(caller.check_secret_use("alice", None).is_err());
+        assert!(caller.check_secret_use("bob", Some("mine")).is_err());
+        assert!(principal(&[], None).check_secret_use("alice", None).is_ok());
+    }
+
+    #[test]
+    fn anonymous_is_a_plain_user_unless_opted_in() {
+        assert!(!Principal::anonymous(false).is_privileged());
+        assert!(Principal::anonymous(true).is_admin());
+    }
+
+    const SECRET: &[u8] = b"test-signing-secret-of-enough-length";
+
+    async fn jwt_authenticator(jwk: serde_json::Value) -> (Authenticator, tempfile::NamedTempFile) {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), serde_json::json!({ "keys": [jwk] }).to_string()).unwrap();
+        let config = AuthConfig {
+            jwks_path: Some(file.path().to_string_lossy().into_owned()),
+            issuer: Some("https://issuer.test".to_string()),
+            audience: Some("syla".to_string()),
+            required_scopes: vec!["execute".to_string()],
+            ..Default::default()
+        };
+        (Authenticator::new(config).await.unwrap(), file)
+    }
+
+    fn hmac_jwk(alg: Option<&str>) -> serde_json::Value {
+        use base64::Engine;
+        let mut jwk = serde_json::json!({
+            "kty": "oct",
+            "kid": "test",
+            "k": base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(SECRET),
+        });
+        if let Some(alg) = alg {
+            jwk["alg"] = alg.into();
+        }
+        jwk
+    }
+
+    fn token(alg: Algorithm, overrides: serde_json::Value) -> String {
+        let mut claims = serde_json::json!({
+            "sub": "alice",
+            "iss": "https://issuer.test",
+            "aud": "syla",
+            "exp": chrono::Utc::now().timestamp() + 300,
+            "scope": "execute read",
+            "workspace_id": "mine",
+        });
+        for (name, value) in overrides.as_object().unwrap() {
+            claims[name] = value.clone();
+        }
+        let mut header = jsonwebtoken::Header::new(alg);
+        header.kid = Some("test".to_string());
+        let key = jsonwebtoken::EncodingKey::from_secret(SECRET);
+        format!("Bearer {}", jsonwebtoken::encode(&header, &claims, &key).unwrap())
+    }
+
+    #[tokio::test]
+    async fn valid_token_yields_the_principal() {
+        let (auth, _file) = jwt_authenticator(hmac_jwk(Some("HS256"))).await;
+        let principal = auth.authenticate(Some(&token(Algorithm::HS256, serde_json::json!({})))).await.unwrap();
+        assert_eq!(principal.subject, "alice");
+        assert_eq!(principal.workspace_id, ws("mine"));
+    }
+
+    #[tokio::test]
+    async fn scopes_may_come_as_a_list() {
+        let (auth, _file) = jwt_authenticator(hmac_jwk(Some("HS256"))).await;
+        let claims = serde_json::json!({ "scope": null, "scp": ["read", "execute"] });
+        assert!(auth.authenticate(Some(&token(Algorithm::HS256, claims))).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn rejects_tokens_that_fail_validation() {
+        let (auth, _file) = jwt_authenticator(hmac_jwk(Some("HS256"))).await;
+        let cases = [
+            ("wrong issuer", serde_json::json!({ "iss": "https://evil.test" })),
+            ("wrong audience", serde_json::json!({ "aud": "other" })),
+            ("expired", serde_json::json!({ "exp": chrono::Utc::now().timestamp() - 3600 })),
+            ("no subject", serde_json::json!({ "sub": null })),
+        ];
+        for (name, overrides) in cases {
+            let result = auth.authenticate(Some(&token(Algorithm::HS256, overrides))).await;
+            assert!(matches!(result, Err(AuthError::Invalid(_))), "{name}: {result:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn missing_scope_is_forbidden() {
+        let (auth, _file) = jwt_authenticator(hmac_jwk(Some("HS256"))).await;
+        let result = auth.authenticate(Some(&token(Algorithm::HS256, serde_json::json!({ "scope": "read" })))).await;
+        assert!(matches!(result, Err(AuthError::Forbidden(missing)) if missing == "execute"));
+    }
+
+    #[tokio::test]
+    async fn header_algorithm_must_match_the_key() {
+        let (auth, _file) = jwt_authenticator(hmac_jwk(Some("HS256"))).await;
+        let result = auth.authenticate(Some(&token(Algorithm::HS384, serde_json::json!({})))).await;
+        assert!(matches!(result, Err(AuthError::Invalid(_))), "{result:?}");
+    }
+
+    #[tokio::test]
+    async fn symmetric_keys_must_name_their_algorithm() {
+        let (auth, _file) = jwt_authenticator(hmac_jwk(None)).await;
+        let result = auth.authenticate(Some(&token(Algorithm::HS256, serde_json::json!({})))).await;
+        assert!(matches!(result, Err(AuthError::Invalid(_))), "{result:?}");
+    }
+
+    #[test]
+    fn asymmetric_keys_allow_only_their_family() {
+        let rsa: Jwk = serde_json::from_value(serde_json::json!({ "kty": "RSA", "n": "AQAB", "e": "AQAB" })).unwrap();
+        let algorithms = key_algorithms(&rsa);
+        assert!(algorithms.contains(&Algorithm::RS256) && algorithms.contains(&Algorithm::PS512));
+        assert!(!algorithms.contains(&Algorithm::HS256));
+
+        let ec: Jwk = serde_json::from_value(
+            serde_json::json!({ "kty": "EC", "crv": "P-384", "x": "AQAB", "y": "AQAB" }),
+        )
+        .unwrap();
+        assert_eq!(key_algorithms(&ec), vec![Algorithm::ES384]);
+
+        let pinned: Jwk =
+            serde_json::from_value(serde_json::json!({ "kty": "RSA", "alg": "PS256", "n": "AQAB", "e": "AQAB" }))
+                .unwrap();
+        assert_eq!(key_algorithms(&pinned), vec![Algorithm::PS256]);
+    }
+}
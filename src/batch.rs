@@ -0,0 +1,171 @@
+use crate::docker;
+use crate::models::{Termination, TestCase, TestCaseResult, Verdict};
+
+// Exit status of coreutils/busybox `timeout` when the limit is hit
+const TIMEOUT_EXIT_CODE: i32 = 124;
+const SIGKILL: i32 = 9;
+
+// Cap on the number of differing lines reported per case
+const MAX_DIFF_LINES: usize = 20;
+
+/// Verdict for one case that ran under `timeout -k` with `timeout_seconds`.
+pub fn judge(index: usize, case: &TestCase, timeout_seconds: u64, result: docker::ExecutionResult) -> TestCaseResult {
+    let mut diff = None;
+    let verdict = match result.termination {
+        Termination::WallClockTimeout
+        | Termination::CpuTimeLimit
+        | Termination::Exited { exit_code: TIMEOUT_EXIT_CODE } => Verdict::TimeLimitExceeded,
+        // `timeout -k` SIGKILLs a program that ignored SIGTERM, which can
+        // only happen once the limit has passed
+        Termination::Signaled { signal: SIGKILL } if result.duration_ms >= timeout_seconds * 1000 => {
+            Verdict::TimeLimitExceeded
+        }
+        // Cases share one container, so there is no per-process OOM flag; any
+        // other SIGKILL comes from the OOM killer
+        Termination::OutOfMemory | Termination::Signaled { signal: SIGKILL } => {
+            Verdict::MemoryLimitExceeded
+        }
+        Termination::Exited { exit_code: 0 } => {
+            diff = diff_output(&case.expected_stdout, &result.stdout.to_string_lossy());
+            if diff.is_none() {
+                Verdict::Accepted
+            } else {
+                Verdict::WrongAnswer
+            }
+        }
+        _ => Verdict::RuntimeError,
+    };
+
+    TestCaseResult {
+        index,
+        verdict,
+        exit_code: result.exit_code,
+        stdout: result.stdout.to_string_lossy(),
+        stderr: result.stderr.to_string_lossy(),
+        duration_ms: result.duration_ms,
+        diff,
+    }
+}
+
+// Compare outputs ignoring trailing whitespace on each line and trailing
+// blank lines. Returns a line-oriented diff when they differ.
+fn diff_output(expected: &str, actual: &str) -> Option<String> {
+    let expected = normalize(expected);
+    let actual = normalize(actual);
+    if expected == actual {
+        return None;
+    }
+
+    let mut diff = String::new();
+    let mut reported = 0;
+    for line in 0..expected.len().max(actual.len()) {
+        let want = expected.get(line);
+        let got = actual.get(line);
+        if want == got {
+            continue;
+        }
+        if reported == MAX_DIFF_LINES {
+            diff.push_str("...\n");
+            break;
+        }
+        diff.push_str(&format!("@@ line {} @@\n", line + 1));
+        if let Some(want) = want {
+            diff.push_str(&format!("-{}\n", want));
+        }
+        if let Some(got) = got {
+            diff.push_str(&format!("+{}\n", got));
+        }
+        reported += 1;
+    }
+    Some(diff)
+}
+
+fn normalize(output: &str) -> Vec<&str> {
+    let mut lines: Vec<&str> = output.lines().map(str::trim_end).collect();
+    while lines.last().is_some_and(|line| line.is_empty()) {
+        lines.pop();
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::docker::{CapturedOutput, ExecutionResult};
+
+    fn case(expected_stdout: &str) -> TestCase {
+        TestCase {
+            stdin: String::new(),
+            expected_stdout: expected_stdout.to_string(),
+            timeout_seconds: None,
+            memory_mb: None,
+        }
+    }
+
+    fn result(termination: Termination, stdout: &str, duration_ms: u64) -> ExecutionResult {
+        let exit_code = match termination {
+            Termination::Exited { exit_code } => exit_code,
+            Termination::Signaled { signal } => 128 + signal,
+            _ => -1,
+        };
+        ExecutionResult {
+            exit_code,
+            stdout: CapturedOutput {
+                data: stdout.as_bytes().to_vec(),
+                total_bytes: stdout.len() as u64,
+                truncated: false,
+            },
+            stderr: CapturedOutput::default(),
+            duration_ms,
+            termination,
+        }
+    }
+
+    #[test]
+    fn verdicts() {
+        let exited = |code| Termination::Exited { exit_code: code };
+        let killed = Termination::Signaled { signal: SIGKILL };
+        let table = [
+            ("accepted", exited(0), "42\n", 100, Verdict::Accepted),
+            ("trailing whitespace ignored", exited(0), "42  \n\n\n", 100, Verdict::Accepted),
+            ("wrong answer", exited(0), "41\n", 100, Verdict::WrongAnswer),
+            ("missing output", exited(0), "", 100, Verdict::WrongAnswer),
+            ("timeout exit status", exited(TIMEOUT_EXIT_CODE), "", 2_000, Verdict::TimeLimitExceeded),
+            ("outer timeout", Termination::WallClockTimeout, "", 5_000, Verdict::TimeLimitExceeded),
+            ("cpu limit", Termination::CpuTimeLimit, "", 2_000, Verdict::TimeLimitExceeded),
+            ("killed after ignoring SIGTERM", killed, "", 3_000, Verdict::TimeLimitExceeded),
+            ("killed early", killed, "", 300, Verdict::MemoryLimitExceeded),
+            ("oom", Termination::OutOfMemory, "", 300, Verdict::MemoryLimitExceeded),
+            ("nonzero exit", exited(1), "42\n", 100, Verdict::RuntimeError),
+            ("segfault", Termination::Signaled { signal: 11 }, "", 100, Verdict::RuntimeError),
+        ];
+        for (name, termination, stdout, duration_ms, expected) in table {
+            let judged = judge(0, &case("42\n"), 2, result(termination, stdout, duration_ms));
+            assert_eq!(judged.verdict, expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn only_wrong_answers_carry_a_diff() {
+        let accepted = judge(0, &case("a\nb\n"), 2, result(Termination::Exited { exit_code: 0 }, "a\nb\n", 10));
+        assert_eq!(accepted.diff, None);
+        let wrong = judge(3, &case("a\nb\n"), 2, result(Termination::Exited { exit_code: 0 }, "a\nc\n", 10));
+        assert_eq!(wrong.index, 3);
+        assert_eq!(wrong.diff.as_deref(), Some("@@ line 2 @@\n-b\n+c\n"));
+    }
+
+    #[test]
+    fn diff_reports_extra_and_missing_lines() {
+        assert_eq!(diff_output("a\n", "a\nb\n").as_deref(), Some("@@ line 2 @@\n+b\n"));
+        assert_eq!(diff_output("a\nb\n", "a\n").as_deref(), Some("@@ line 2 @@\n-b\n"));
+        assert_eq!(diff_output("a\r\n", "a\n"), None);
+    }
+
+    #[test]
+    fn diff_is_capped() {
+        let expected: String = (0..50).map(|i| format!("{}\n", i)).collect();
+        let diff = diff_output(&expected, "").unwrap();
+        assert_eq!(diff.matches("@@").count(), MAX_DIFF_LINES * 2);
+        assert!(diff.ends_with("...\n"));
+    }
+}
@@ -0,0 +1,134 @@
+use crate::error::ServiceError;
+use crate::executor::DockerExecutor;
+use crate::models::{BatchResult, CreateExecutionRequest, ExecutionJob, ExecutionResult, JobStatus, Termination, Verdict};
+use redis::aio::ConnectionManager;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+const DEFAULT_TTL_SECONDS: u64 = 60 * 60;
+const DEFAULT_MAX_ENTRIES: u64 = 10_000;
+
+// Cache keys scored by when they were stored (ms), oldest evicted first
+const CACHE_INDEX_KEY: &str = "cache:index";
+
+// How long an image digest lookup is reused before asking Docker again
+const DIGEST_TTL: Duration = Duration::from_secs(60);
+
+// Request fields that don't change what the program does
+const NON_SEMANTIC_FIELDS: &[&str] = &["priority", "not_before", "cache", "bypass_cache", "callback_url"];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CachePolicy {
+    pub ttl_seconds: u64,
+    pub max_entries: u64,
+}
+
+impl Default for CachePolicy {
+    fn default() -> Self {
+        Self {
+            ttl_seconds: DEFAULT_TTL_SECONDS,
+            max_entries: DEFAULT_MAX_ENTRIES,
+        }
+    }
+}
+
+/// The outcome of a finished job, as replayed to later identical requests.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedResult {
+    pub status: JobStatus,
+    pub result: Option<ExecutionResult>,
+    pub batch: Option<BatchResult>,
+}
+
+/// Opt-in cache of results for deterministic requests, keyed by the request
+/// content and the digest of the image it runs in.
+pub struct ResultCache {
+    policy: CachePolicy,
+    digests: Mutex<HashMap<String, (String, Instant)>>,
+}
+
+impl ResultCache {
+    pub fn new(policy: CachePolicy) -> Self {
+        Self {
+            policy,
+            digests: Mutex::new(HashMap::new()),
+        }
+    }
+
+    async fn image_digest(&self, executor: &DockerExecutor, language: &str) -> Result<String, ServiceError> {
+        let mut digests = self.digests.lock().await;
+        if let Some((digest, fetched)) = digests.get(language) {
+            if fetched.elapsed() < DIGEST_TTL {
+                return Ok(digest.clone());
+            }
+        }
+        let digest = executor.image_digest(language).await?;
+        digests.insert(language.to_string(), (digest.clone(), Instant::now()));
+        Ok(digest)
+    }
+
+    pub async fn key_for(&self, executor: &DockerExecutor, request: &CreateExecutionRequest) -> Result<String, ServiceError> {
+        let digest = self.image_digest(executor, &request.language).await?;
+
+        // serde_json sorts object keys, so map fields hash the same in any order
+        let mut content = serde_json::to_value(request)?;
+        if let Some(fields) = content.as_object_mut() {
+            for field in NON_SEMANTIC_FIELDS {
+                fields.remove(*field);
+            }
+        }
+        let payload = serde_json::json!({ "request": content, "image": digest });
+        Ok(format!("cache:result:{:x}", Sha256::digest(payload.to_string().as_bytes())))
+    }
+
+    pub async fn get(&self, redis: &mut ConnectionManager, key: &str) -> Result<Option<CachedResult>, ServiceError> {
+        let json: Option<String> = redis::cmd("GET").arg(key).query_async(redis).await?;
+        Ok(json.map(|json| serde_json::from_str(&json)).transpose()?)
+    }
+
+    /// Store a result, evicting the oldest entries beyond the size limit.
+    pub async fn put(&self, redis: &mut ConnectionManager, key: &str, cached: &CachedResult) -> Result<(), ServiceError> {
+        let (count,): (u64,) = redis::pipe()
+            .cmd("SET").arg(key).arg(serde_json::to_string(cached)?).arg("EX").arg(self.policy.ttl_seconds).ignore()
+            .cmd("ZADD").arg(CACHE_INDEX_KEY).arg(chrono::Utc::now().timestamp_millis()).arg(key).ignore()
+            .cmd("ZCARD").arg(CACHE_INDEX_KEY)
+            .query_async(redis)
+            .await?;
+
+        if count > self.policy.max_entries {
+            let evicted: Vec<String> = redis::cmd("ZPOPMIN")
+                .arg(CACHE_INDEX_KEY)
+                .arg(count - self.policy.max_entries)
+                .query_async::<_, Vec<(String, f64)>>(redis)
+                .await?
+                .into_iter()
+                .map(|(key, _)| key)
+                .collect();
+            if !evicted.is_empty() {
+                redis::cmd("DEL").arg(&evicted).query_async::<_, ()>(redis).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether a finished job's outcome would come out the same on a re-run.
+/// Anything cut short by a time or memory limit depends on load.
+pub fn is_cacheable(job: &ExecutionJob) -> bool {
+    if !matches!(job.status, JobStatus::Completed | JobStatus::Failed) {
+        return false;
+    }
+    if let Some(batch) = &job.batch {
+        return batch.cases.iter().all(|case| {
+            !matches!(case.verdict, Verdict::TimeLimitExceeded | Verdict::MemoryLimitExceeded)
+        });
+    }
+    matches!(
+        job.result.as_ref().and_then(|r| r.termination.as_ref()),
+        Some(Termination::Exited { .. })
+    )
+}
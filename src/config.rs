@@ -0,0 +1,506 @@
+use crate::auth::{self, AuthConfig};
+use crate::cache::CachePolicy;
+use crate::docker::{self, OutputLimits};
+use crate::idempotency::IdempotencyPolicy;
+use crate::lifecycle::ShutdownPolicy;
+use crate::queue::QueueWeights;
+use crate::quota::{QuotaLimits, QuotaPolicy};
+use crate::retention::RetentionPolicy;
+use crate::retry::RetryPolicy;
+use crate::secrets::SecretCipher;
+use crate::validation::{ValidationLimits, SUPPORTED_LANGUAGES};
+use crate::webhook::WebhookPolicy;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+// Read when `--config` isn't given
+pub const CONFIG_FILE_ENV: &str = "SYLA_CONFIG";
+
+const REDACTED: &str = "[redacted]";
+
+/// Everything the service can be configured with. Built from defaults, then
+/// the TOML file if one is given, then environment variables, and checked
+/// as a whole before anything starts.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServiceConfig {
+    pub server: ServerConfig,
+    pub redis: RedisConfig,
+    pub database: DatabaseConfig,
+    pub runtime: RuntimeConfig,
+    pub queue: QueueWeights,
+    pub limits: ValidationLimits,
+    pub quotas: QuotaPolicy,
+    pub retry: RetryPolicy,
+    pub retention: RetentionPolicy,
+    pub idempotency: IdempotencyPolicy,
+    pub cache: CachePolicy,
+    pub webhooks: WebhookPolicy,
+    pub auth: AuthConfig,
+    pub secrets: SecretsConfig,
+    pub shutdown: ShutdownPolicy,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub rest_addr: SocketAddr,
+    pub grpc_addr: SocketAddr,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            rest_addr: SocketAddr::from(([0, 0, 0, 0], 8083)),
+            grpc_addr: SocketAddr::from(([0, 0, 0, 0], 8081)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RedisConfig {
+    #[serde(serialize_with = "redact_password")]
+    pub url: String,
+}
+
+impl Default for RedisConfig {
+    fn default() -> Self {
+        Self {
+            url: "redis://127.0.0.1:6380/".to_string(),
+        }
+    }
+}
+
+/// Postgres archive for finished executions; disabled without a URL.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    #[serde(serialize_with = "redact_optional_password")]
+    pub url: Option<String>,
+    pub max_connections: u32,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        Self {
+            url: None,
+            max_connections: 5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RuntimeBackend {
+    Docker,
+}
+
+impl FromStr for RuntimeBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "docker" => Ok(RuntimeBackend::Docker),
+            other => Err(format!("unknown runtime backend `{}`; expected `docker`", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RuntimeConfig {
+    pub backend: RuntimeBackend,
+    pub workspace_root: PathBuf,
+    // TOML file of `[<language>] image = "..."` replacing built-in images
+    pub language_registry: Option<PathBuf>,
+    pub reaper_interval_seconds: u64,
+    pub output_limits: OutputLimits,
+}
+
+impl Default for RuntimeConfig {
+    fn default() -> Self {
+        Self {
+            backend: RuntimeBackend::Docker,
+            workspace_root: docker::default_workspace_root(),
+            language_registry: None,
+            reaper_interval_seconds: 60,
+            output_limits: OutputLimits::default(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LanguageEntry {
+    image: String,
+}
+
+impl RuntimeConfig {
+    /// Images set by the language registry, keyed by language.
+    pub fn language_images(&self) -> Result<HashMap<String, String>> {
+        let Some(path) = &self.language_registry else {
+            return Ok(HashMap::new());
+        };
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read language registry {}", path.display()))?;
+        let entries: HashMap<String, LanguageEntry> = toml::from_str(&contents)
+            .with_context(|| format!("Invalid language registry {}", path.display()))?;
+
+        let mut images = HashMap::new();
+        for (language, entry) in entries {
+            if !SUPPORTED_LANGUAGES.contains(&language.as_str()) {
+                bail!(
+                    "Language registry {} lists unsupported language `{}`; expected one of {}",
+                    path.display(),
+                    language,
+                    SUPPORTED_LANGUAGES.join(", ")
+                );
+            }
+            if entry.image.trim().is_empty() {
+                bail!("Language registry {} has an empty image for `{}`", path.display(), language);
+            }
+            images.insert(language, entry.image);
+        }
+        Ok(images)
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SecretsConfig {
+    // Base64 of 32 bytes; secrets are disabled when unset
+    pub encryption_key: Option<Sensitive>,
+}
+
+/// A credential. Shown as `[redacted]` wherever configuration is printed.
+#[derive(Clone, Deserialize)]
+#[serde(transparent)]
+pub struct Sensitive(String);
+
+impl Sensitive {
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for Sensitive {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self(s.to_string()))
+    }
+}
+
+impl fmt::Debug for Sensitive {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl Serialize for Sensitive {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(REDACTED)
+    }
+}
+
+// Connection URLs are printed with their password masked
+fn redact_password<S: Serializer>(url: &str, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&mask_password(url))
+}
+
+fn redact_optional_password<S: Serializer>(url: &Option<String>, serializer: S) -> Result<S::Ok, S::Error> {
+    match url {
+        Some(url) => serializer.serialize_some(&mask_password(url)),
+        None => serializer.serialize_none(),
+    }
+}
+
+fn mask_password(url: &str) -> String {
+    match reqwest::Url::parse(url) {
+        Ok(mut parsed) if parsed.password().is_some() => {
+            // Brackets would be percent-encoded in a URL
+            let _ = parsed.set_password(Some("redacted"));
+            parsed.to_string()
+        }
+        _ => url.to_string(),
+    }
+}
+
+impl ServiceConfig {
+    /// Load from `path` if given, apply environment overrides and validate.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let mut config = match path {
+            Some(path) => {
+                let contents = std::fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read config file {}", path.display()))?;
+                toml::from_str(&contents).with_context(|| format!("Invalid config file {}", path.display()))?
+            }
+            None => Self::default(),
+        };
+        config.apply_env()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// The effective configuration as TOML, with credentials redacted.
+    pub fn to_toml(&self) -> Result<String> {
+        Ok(toml::to_string_pretty(self)?)
+    }
+
+    // Environment variables override the file. These are the names the
+    // service read before it had a config file, so existing deployments
+    // keep working unchanged.
+    fn apply_env(&mut self) -> Result<()> {
+        let mut env = Env::default();
+
+        env.set("REST_ADDR", &mut self.server.rest_addr);
+        if let Some(port) = env.parse("PORT") {
+            self.server.rest_addr.set_port(port);
+        }
+        env.set("GRPC_ADDR", &mut self.server.grpc_addr);
+
+        env.set("REDIS_URL", &mut self.redis.url);
+        env.set_opt("DATABASE_URL", &mut self.database.url);
+        env.set("DATABASE_MAX_CONNECTIONS", &mut self.database.max_connections);
+
+        env.set("RUNTIME_BACKEND", &mut self.runtime.backend);
+        env.set("WORKSPACE_ROOT", &mut self.runtime.workspace_root);
+        env.set_opt("LANGUAGE_REGISTRY", &mut self.runtime.language_registry);
+        env.set("REAPER_INTERVAL_SECONDS", &mut self.runtime.reaper_interval_seconds);
+        env.set("MAX_STDOUT_BYTES", &mut self.runtime.output_limits.stdout_bytes);
+        env.set("MAX_STDERR_BYTES", &mut self.runtime.output_limits.stderr_bytes);
+
+        env.set("QUEUE_WEIGHT_INTERACTIVE", &mut self.queue.interactive);
+        env.set("QUEUE_WEIGHT_BATCH", &mut self.queue.batch);
+
+        let limits = &mut self.limits;
+        env.set("MAX_CODE_BYTES", &mut limits.max_code_bytes);
+        env.set("MAX_TIMEOUT_SECONDS", &mut limits.max_timeout_seconds);
+        env.set("MAX_ARGS", &mut limits.max_args);
+        env.set("MAX_ARG_BYTES", &mut limits.max_arg_bytes);
+        env.set("MAX_ENV_VARS", &mut limits.max_env_vars);
+        env.set("MAX_ENV_VAR_BYTES", &mut limits.max_env_var_bytes);
+        env.set("MAX_TEST_CASES", &mut limits.max_test_cases);
+        env.set("MAX_TEST_CASE_BYTES", &mut limits.max_test_case_bytes);
+
+        env.quota_limits("QUOTA_USER", &mut self.quotas.user);
+        env.quota_limits("QUOTA_WORKSPACE", &mut self.quotas.workspace);
+
+        env.retry_policy("RETRY", &mut self.retry);
+
+        env.set("JOB_TTL_SECONDS", &mut self.retention.job_ttl_seconds);
+        if let Some(overrides) = env.get("RETENTION_OVERRIDES") {
+            // Comma-separated `tenant=seconds` pairs
+            for pair in overrides.split(',').filter(|p| !p.trim().is_empty()) {
+                match pair.split_once('=').and_then(|(k, v)| Some((k.trim(), v.trim().parse().ok()?))) {
+                    Some((tenant, ttl)) => {
+                        self.retention.overrides.insert(tenant.to_string(), ttl);
+                    }
+                    None => env.problems.push(format!("RETENTION_OVERRIDES: malformed pair `{}`", pair)),
+                }
+            }
+        }
+
+        env.set("IDEMPOTENCY_TTL_SECONDS", &mut self.idempotency.ttl_seconds);
+
+        env.set("RESULT_CACHE_TTL_SECONDS", &mut self.cache.ttl_seconds);
+        env.set("RESULT_CACHE_MAX_ENTRIES", &mut self.cache.max_entries);
+
+        env.set_opt("WEBHOOK_SIGNING_SECRET", &mut self.webhooks.signing_secret);
+        env.set("WEBHOOK_TIMEOUT_SECONDS", &mut self.webhooks.timeout_seconds);
+        env.retry_policy("WEBHOOK", &mut self.webhooks.retry);
+        env.set("WEBHOOK_MAX_CONCURRENT_DELIVERIES", &mut self.webhooks.max_concurrent_deliveries);
+        env.set("WEBHOOK_MAX_SUBSCRIPTIONS", &mut self.webhooks.max_subscriptions_per_workspace);
+
+        env.set_opt("AUTH_JWKS_PATH", &mut self.auth.jwks_path);
+        env.set_opt("AUTH_JWKS_URL", &mut self.auth.jwks_url);
+        // AUTH_SERVICE_URL is the older name
+        if let Some(url) = env.get("AUTH_INTROSPECTION_URL").or_else(|| env.get("AUTH_SERVICE_URL")) {
+            self.auth.introspection_url = Some(url);
+        }
+        env.set_opt("AUTH_ISSUER", &mut self.auth.issuer);
+        env.set_opt("AUTH_AUDIENCE", &mut self.auth.audience);
+        if let Some(scopes) = env.get("AUTH_REQUIRED_SCOPES") {
+            self.auth.required_scopes = auth::split_scopes(&scopes);
+        }
+        env.set_opt("AUTH_CLIENT_ID", &mut self.auth.client_id);
+        env.set_opt("AUTH_CLIENT_SECRET", &mut self.auth.client_secret);
+        env.set("AUTH_ANONYMOUS_ADMIN", &mut self.auth.anonymous_admin);
+
+        env.set_opt("SECRETS_ENCRYPTION_KEY", &mut self.secrets.encryption_key);
+
+        env.set("SHUTDOWN_DRAIN_TIMEOUT_SECONDS", &mut self.shutdown.drain_timeout_seconds);
+        env.set("SHUTDOWN_LISTENER_GRACE_SECONDS", &mut self.shutdown.listener_grace_seconds);
+
+        report("Invalid environment overrides", env.problems)
+    }
+
+    /// Every problem with the configuration at once, so a deploy fails with
+    /// the full list rather than one fix at a time.
+    pub fn validate(&self) -> Result<()> {
+        let mut problems = Vec::new();
+        let mut check = |ok: bool, problem: &str| {
+            if !ok {
+                problems.push(problem.to_string());
+            }
+        };
+
+        check(self.server.rest_addr != self.server.grpc_addr, "server.rest_addr and server.grpc_addr must differ");
+
+        check(
+            redis::Client::open(self.redis.url.as_str()).is_ok(),
+            "redis.url must be a redis://, rediss:// or unix:// URL",
+        );
+        if let Some(url) = &self.database.url {
+            check(
+                url.starts_with("postgres://") || url.starts_with("postgresql://"),
+                "database.url must be a postgres:// URL",
+            );
+        }
+        check(self.database.max_connections >= 1, "database.max_connections must be at least 1");
+
+        check(self.runtime.reaper_interval_seconds >= 1, "runtime.reaper_interval_seconds must be at least 1");
+        check(self.runtime.output_limits.stdout_bytes >= 1, "runtime.output_limits.stdout_bytes must be at least 1");
+        check(self.runtime.output_limits.stderr_bytes >= 1, "runtime.output_limits.stderr_bytes must be at least 1");
+
+        check(self.queue.interactive >= 1, "queue.interactive must be at least 1");
+        check(self.queue.batch >= 1, "queue.batch must be at least 1");
+
+        let limits = &self.limits;
+        for (name, value) in [
+            ("max_code_bytes", limits.max_code_bytes),
+            ("max_timeout_seconds", limits.max_timeout_seconds as usize),
+            ("max_env_var_bytes", limits.max_env_var_bytes),
+            ("max_test_cases", limits.max_test_cases),
+        ] {
+            check(value >= 1, &format!("limits.{} must be at least 1", name));
+        }
+
+        for (name, limits) in [("user", &self.quotas.user), ("workspace", &self.quotas.workspace)] {
+            check(
+                limits.max_concurrent != Some(0),
+                &format!("quotas.{}.max_concurrent must be at least 1 when set", name),
+            );
+        }
+
+        for (name, retry) in [("retry", &self.retry), ("webhooks.retry", &self.webhooks.retry)] {
+            check(retry.max_attempts >= 1, &format!("{}.max_attempts must be at least 1", name));
+            check(
+                retry.base_delay_ms <= retry.max_delay_ms,
+                &format!("{}.base_delay_ms must not exceed max_delay_ms", name),
+            );
+        }
+
+        check(self.retention.job_ttl_seconds >= 1, "retention.job_ttl_seconds must be at least 1");
+        check(self.idempotency.ttl_seconds >= 1, "idempotency.ttl_seconds must be at least 1");
+        check(self.cache.ttl_seconds >= 1, "cache.ttl_seconds must be at least 1");
+        check(self.cache.max_entries >= 1, "cache.max_entries must be at least 1");
+        check(self.webhooks.timeout_seconds >= 1, "webhooks.timeout_seconds must be at least 1");
+        check(
+            self.webhooks.max_concurrent_deliveries >= 1,
+            "webhooks.max_concurrent_deliveries must be at least 1",
+        );
+        check(
+            self.webhooks.max_subscriptions_per_workspace >= 1,
+            "webhooks.max_subscriptions_per_workspace must be at least 1",
+        );
+
+        let auth = &self.auth;
+        let sources = [&auth.jwks_path, &auth.jwks_url, &auth.introspection_url];
+        check(
+            sources.iter().filter(|source| source.is_some()).count() <= 1,
+            "Set at most one of auth.jwks_path, auth.jwks_url and auth.introspection_url",
+        );
+        check(
+            auth.client_secret.is_none() || auth.client_id.is_some(),
+            "auth.client_secret requires auth.client_id",
+        );
+        check(
+            !auth.anonymous_admin || matches!(auth.mode(), auth::AuthMode::Disabled),
+            "auth.anonymous_admin only applies when auth is disabled",
+        );
+
+        if let Err(e) = self.runtime.language_images() {
+            problems.push(format!("{:#}", e));
+        }
+        if let Err(e) = SecretCipher::new(self.secrets.encryption_key.as_ref().map(Sensitive::expose)) {
+            problems.push(format!("{:#}", e));
+        }
+
+        report("Invalid configuration", problems)
+    }
+}
+
+fn report(heading: &str, problems: Vec<String>) -> Result<()> {
+    if problems.is_empty() {
+        return Ok(());
+    }
+    bail!("{}:\n  - {}", heading, problems.join("\n  - "))
+}
+
+// Reads overrides, collecting unparseable values instead of stopping at the
+// first. Empty variables count as unset.
+#[derive(Default)]
+struct Env {
+    problems: Vec<String>,
+}
+
+impl Env {
+    fn get(&self, name: &str) -> Option<String> {
+        std::env::var(name).ok().filter(|v| !v.is_empty())
+    }
+
+    fn parse<T: FromStr>(&mut self, name: &str) -> Option<T>
+    where
+        T::Err: fmt::Display,
+    {
+        let value = self.get(name)?;
+        match value.parse() {
+            Ok(value) => Some(value),
+            Err(e) => {
+                self.problems.push(format!("{}: {}", name, e));
+                None
+            }
+        }
+    }
+
+    fn set<T: FromStr>(&mut self, name: &str, target: &mut T)
+    where
+        T::Err: fmt::Display,
+    {
+        if let Some(value) = self.parse(name) {
+            *target = value;
+        }
+    }
+
+    fn set_opt<T: FromStr>(&mut self, name: &str, target: &mut Option<T>)
+    where
+        T::Err: fmt::Display,
+    {
+        if let Some(value) = self.parse(name) {
+            *target = Some(value);
+        }
+    }
+
+    // `{prefix}_REQUESTS_PER_MINUTE` and so on
+    fn quota_limits(&mut self, prefix: &str, limits: &mut QuotaLimits) {
+        self.set_opt(&format!("{}_REQUESTS_PER_MINUTE", prefix), &mut limits.requests_per_minute);
+        self.set_opt(&format!("{}_MAX_CONCURRENT", prefix), &mut limits.max_concurrent);
+        self.set_opt(&format!("{}_DAILY_CPU_SECONDS", prefix), &mut limits.daily_cpu_seconds);
+        self.set_opt(&format!("{}_DAILY_MEMORY_MB_SECONDS", prefix), &mut limits.daily_memory_mb_seconds);
+    }
+
+    // `{prefix}_MAX_ATTEMPTS`, `{prefix}_BASE_DELAY_MS` and `{prefix}_MAX_DELAY_MS`
+    fn retry_policy(&mut self, prefix: &str, retry: &mut RetryPolicy) {
+        self.set(&format!("{}_MAX_ATTEMPTS", prefix), &mut retry.max_attempts);
+        self.set(&format!("{}_BASE_DELAY_MS", prefix), &mut retry.base_delay_ms);
+        self.set(&format!("{}_MAX_DELAY_MS", prefix), &mut retry.max_delay_ms);
+    }
+}
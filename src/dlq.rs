@@ -0,0 +1,228 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use redis::aio::ConnectionManager;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::error::ServiceError;
+use crate::models::{ExecutionJob, JobStatus};
+
+// Entry ids scored by when they were dead-lettered (ms)
+const DLQ_INDEX_KEY: &str = "dlq:index";
+
+fn entry_key(id: Uuid) -> String {
+    format!("dlq:entry:{}", id)
+}
+
+/// A job the service gave up on. Entries stay until replayed or purged.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct DeadLetter {
+    pub id: Uuid,
+    // The queue entry as popped; normally the job id
+    pub payload: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub job: Option<ExecutionJob>,
+    pub error: String,
+    pub attempts: u32,
+    pub dead_lettered_at: DateTime<Utc>,
+}
+
+impl DeadLetter {
+    pub fn for_job(job: &ExecutionJob, error: String) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            payload: job.id.to_string(),
+            job: Some(job.clone()),
+            error,
+            attempts: job.attempts.len() as u32,
+            dead_lettered_at: Utc::now(),
+        }
+    }
+
+    /// The job as it was before its first run, to be queued again.
+    pub fn into_replay(self) -> Result<ExecutionJob, ServiceError> {
+        let mut job = self.job.ok_or_else(|| {
+            ServiceError::InvalidRequest(format!("Dead letter {} has no job to replay", self.id))
+        })?;
+        job.status = JobStatus::Queued;
+        job.started_at = None;
+        job.completed_at = None;
+        job.result = None;
+        job.batch = None;
+        job.attempts.clear();
+        Ok(job)
+    }
+
+    /// A queue entry that never got as far as running.
+    pub fn for_payload(payload: String, error: String) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            payload,
+            job: None,
+            error,
+            attempts: 0,
+            dead_lettered_at: Utc::now(),
+        }
+    }
+}
+
+/// Which entries a bulk replay or purge applies to.
+#[derive(Debug, Clone, Default, Deserialize, ToSchema)]
+pub struct DeadLetterSelection {
+    #[serde(default)]
+    pub ids: Vec<Uuid>,
+    // Required to act on every entry, so an empty id list can't do it by accident
+    #[serde(default)]
+    pub all: bool,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct SkippedDeadLetter {
+    pub id: Uuid,
+    pub reason: String,
+}
+
+#[derive(Debug, Default, Serialize, ToSchema)]
+pub struct ReplaySummary {
+    pub replayed: Vec<Uuid>,
+    pub skipped: Vec<SkippedDeadLetter>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct PurgedCount {
+    pub purged: u64,
+}
+
+pub struct DeadLetterQueue {
+    conn: Mutex<ConnectionManager>,
+}
+
+impl DeadLetterQueue {
+    pub fn new(conn: ConnectionManager) -> Self {
+        Self {
+            conn: Mutex::new(conn),
+        }
+    }
+
+    pub async fn push(&self, entry: &DeadLetter) -> Result<()> {
+        let mut conn = self.conn.lock().await;
+        redis::pipe()
+            .cmd("SET").arg(entry_key(entry.id)).arg(serde_json::to_string(entry)?).ignore()
+            .cmd("ZADD").arg(DLQ_INDEX_KEY).arg(entry.dead_lettered_at.timestamp_millis()).arg(entry.id.to_string()).ignore()
+            .query_async::<_, ()>(&mut *conn)
+            .await?;
+        Ok(())
+    }
+
+    /// Entries, newest first.
+    pub async fn list(&self) -> Result<Vec<DeadLetter>> {
+        let mut conn = self.conn.lock().await;
+        let ids: Vec<String> = redis::cmd("ZREVRANGE")
+            .arg(DLQ_INDEX_KEY)
+            .arg(0)
+            .arg(-1)
+            .query_async(&mut *conn)
+            .await?;
+        if ids.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let keys: Vec<String> = ids.iter().map(|id| format!("dlq:entry:{}", id)).collect();
+        let entries: Vec<Option<String>> = redis::cmd("MGET").arg(&keys).query_async(&mut *conn).await?;
+        entries
+            .into_iter()
+            .flatten()
+            .map(|json| serde_json::from_str(&json).map_err(Into::into))
+            .collect()
+    }
+
+    pub async fn get(&self, id: Uuid) -> Result<Option<DeadLetter>> {
+        let mut conn = self.conn.lock().await;
+        let json: Option<String> = redis::cmd("GET").arg(entry_key(id)).query_async(&mut *conn).await?;
+        json.map(|json| serde_json::from_str(&json)).transpose().map_err(Into::into)
+    }
+
+    /// Delete entries. Returns how many existed.
+    pub async fn remove(&self, ids: &[Uuid]) -> Result<u64> {
+        if ids.is_empty() {
+            return Ok(0);
+        }
+        let mut conn = self.conn.lock().await;
+        let keys: Vec<String> = ids.iter().map(|id| entry_key(*id)).collect();
+        let members: Vec<String> = ids.iter().map(|id| id.to_string()).collect();
+        let (removed,): (u64,) = redis::pipe()
+            .cmd("DEL").arg(&keys)
+            .cmd("ZREM").arg(DLQ_INDEX_KEY).arg(&members).ignore()
+            .query_async(&mut *conn)
+            .await?;
+        Ok(removed)
+    }
+
+    /// Ids of every entry, for bulk operations.
+    pub async fn ids(&self) -> Result<Vec<Uuid>> {
+        let mut conn = self.conn.lock().await;
+        let ids: Vec<String> = redis::cmd("ZRANGE")
+            .arg(DLQ_INDEX_KEY)
+            .arg(0)
+            .arg(-1)
+            .query_async(&mut *conn)
+            .await?;
+        Ok(ids.iter().filter_map(|id| id.parse().ok()).collect())
+    }
+
+    pub async fn resolve(&self, selection: &DeadLetterSelection) -> Result<Vec<Uuid>> {
+        if selection.all {
+            self.ids().await
+        } else {
+            Ok(selection.ids.clone())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Attempt, FailureKind};
+
+    fn failed_job() -> ExecutionJob {
+        let request = serde_json::from_value(serde_json::json!({"code": "print(1)", "language": "python"})).unwrap();
+        let mut job = ExecutionJob::new(request);
+        let now = Utc::now();
+        job.status = JobStatus::Failed;
+        job.started_at = Some(now);
+        job.completed_at = Some(now);
+        job.attempts = (1..=3)
+            .map(|number| Attempt {
+                number,
+                started_at: now,
+                completed_at: now,
+                failure: Some(FailureKind::Infrastructure),
+                error: Some("Docker daemon unreachable".to_string()),
+            })
+            .collect();
+        job
+    }
+
+    #[test]
+    fn replays_queue_the_job_afresh() {
+        let job = failed_job();
+        let entry = DeadLetter::for_job(&job, "Gave up after 3 attempts".to_string());
+        assert_eq!((entry.payload.as_str(), entry.attempts), (job.id.to_string().as_str(), 3));
+
+        let replay = entry.into_replay().unwrap();
+        assert_eq!(replay.id, job.id);
+        assert_eq!(replay.request.code, "print(1)");
+        assert_eq!(replay.status, JobStatus::Queued);
+        assert!(replay.started_at.is_none() && replay.completed_at.is_none());
+        assert!(replay.result.is_none() && replay.batch.is_none());
+        assert!(replay.attempts.is_empty());
+    }
+
+    #[test]
+    fn entries_without_a_job_cannot_be_replayed() {
+        let entry = DeadLetter::for_payload("not-a-uuid".to_string(), "Invalid job ID".to_string());
+        assert!(matches!(entry.into_replay(), Err(ServiceError::InvalidRequest(_))));
+    }
+}
@@ -1,11 +1,19 @@
 use anyhow::{Context, Result};
-use std::collections::HashMap;
-use std::path::Path;
-use std::process::Command;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
 use std::time::Duration;
-use tokio::process::Command as TokioCommand;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::process::{Child, Command as TokioCommand};
+use tokio::sync::mpsc;
 use uuid::Uuid;
 
+use crate::models::{self, OutputEncoding, OutputStream, Termination};
+use tracing::warn;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde::{Deserialize, Serialize};
+use tempfile::TempDir;
+
 pub struct DockerClient {
     // Future: connection pool, etc
 }
@@ -14,83 +22,235 @@ pub struct ContainerConfig {
     pub image: String,
     pub command: Vec<String>,
     pub environment: HashMap<String, String>,
+    // Set like `environment`, but handed over through the docker CLI's own
+    // environment so the values never appear on its command line
+    pub secrets: HashMap<String, String>,
     pub working_dir: String,
     pub memory_limit: Option<u64>,
     pub cpu_limit: Option<f64>,
     pub timeout_seconds: Option<u64>,
+    // RLIMIT_CPU for the program, in CPU-seconds
+    pub cpu_time_limit: Option<u64>,
+    pub output_limits: OutputLimits,
+    pub labels: HashMap<String, String>,
 }
 
-// Legacy DockerExecutor for backward compatibility
-pub struct DockerExecutor {
-    client: DockerClient,
+/// Receives a program's output as it is written, within the output caps.
+pub type OutputSink = mpsc::UnboundedSender<(OutputStream, Vec<u8>)>;
+
+// Labels put on every container the service starts, so the reaper can find
+// containers left behind by a crash or a failed kill
+pub const MANAGED_LABEL: &str = "syla.managed";
+pub const JOB_ID_LABEL: &str = "syla.job_id";
+pub const DEADLINE_LABEL: &str = "syla.deadline";
+
+// Extra lifetime a container gets past its timeout before it is reaped
+const DEADLINE_GRACE_SECONDS: i64 = 60;
+
+/// Labels identifying a container that runs `job_id` and should be gone
+/// after `timeout_seconds`.
+pub fn job_labels(job_id: Uuid, timeout_seconds: u64) -> HashMap<String, String> {
+    let deadline = chrono::Utc::now().timestamp() + timeout_seconds as i64 + DEADLINE_GRACE_SECONDS;
+    HashMap::from([
+        (MANAGED_LABEL.to_string(), "true".to_string()),
+        (JOB_ID_LABEL.to_string(), job_id.to_string()),
+        (DEADLINE_LABEL.to_string(), deadline.to_string()),
+    ])
 }
 
-impl DockerExecutor {
-    pub fn new() -> Result<Self> {
-        // Verify Docker is available
-        Command::new("docker")
-            .arg("--version")
-            .output()
-            .context("Docker not found. Please install Docker.")?;
-        
-        Ok(Self {
-            client: DockerClient {},
-        })
-    }
-    
-    pub async fn execute(
-        &self,
-        code: &str,
-        language: &str,
-        timeout_seconds: u64,
-    ) -> Result<ExecutionResult> {
-        let temp_dir = tempfile::tempdir()?;
-        let file_extension = match language {
-            "python" => "py",
-            "javascript" => "js",
-            "go" => "go",
-            _ => "txt",
-        };
-        
-        let file_path = temp_dir.path().join(format!("main.{}", file_extension));
-        std::fs::write(&file_path, code)?;
-        
-        let config = ContainerConfig {
-            image: match language {
-                "python" => "python:3.11-slim",
-                "javascript" => "node:20-slim",
-                "go" => "golang:1.21-alpine",
-                _ => "ubuntu:22.04",
-            }.to_string(),
-            command: match language {
-                "python" => vec!["python".to_string(), "main.py".to_string()],
-                "javascript" => vec!["node".to_string(), "main.js".to_string()],
-                "go" => vec!["go".to_string(), "run".to_string(), "main.go".to_string()],
-                _ => vec![],
-            },
-            environment: HashMap::new(),
-            working_dir: "/workspace".to_string(),
-            memory_limit: Some(512 * 1024 * 1024),
-            cpu_limit: Some(1.0),
-            timeout_seconds: Some(timeout_seconds),
-        };
-        
-        self.client.run_container(
-            &format!("syla-exec-{}", Uuid::new_v4()),
-            config,
-            Some(temp_dir.path()),
-        ).await
+// Every workspace directory's name starts with this; nothing else under the
+// workspace root is ours
+pub const WORKSPACE_PREFIX: &str = "exec-";
+
+/// Directory under which per-execution workspaces are created unless
+/// configured otherwise.
+pub fn default_workspace_root() -> PathBuf {
+    std::env::temp_dir().join("syla-workspaces")
+}
+
+/// Create a fresh workspace directory for one execution under `root`. It is
+/// removed when the returned handle is dropped.
+pub fn create_workspace(root: &Path) -> Result<TempDir> {
+    std::fs::create_dir_all(root)?;
+    Ok(tempfile::Builder::new().prefix(WORKSPACE_PREFIX).tempdir_in(root)?)
+}
+
+/// A service-owned container as reported by `docker ps`.
+#[derive(Debug)]
+pub struct ManagedContainer {
+    pub name: String,
+    pub job_id: Option<Uuid>,
+    pub deadline: Option<i64>,
+}
+
+/// Per-stream caps on captured output. Anything past the cap is read and
+/// discarded so the container never blocks on a full pipe.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutputLimits {
+    pub stdout_bytes: usize,
+    pub stderr_bytes: usize,
+}
+
+impl Default for OutputLimits {
+    fn default() -> Self {
+        Self {
+            stdout_bytes: 1024 * 1024, // 1MB
+            stderr_bytes: 1024 * 1024,
+        }
     }
 }
 
+// The only parts of the service's environment the docker CLI gets. Its
+// environment is otherwise cleared, since secret values are passed through
+// it with `-e NAME`.
+const CLIENT_ENV: &[&str] = &[
+    "PATH",
+    "HOME",
+    "TMPDIR",
+    "XDG_RUNTIME_DIR",
+    "DOCKER_HOST",
+    "DOCKER_CONTEXT",
+    "DOCKER_CONFIG",
+    "DOCKER_CERT_PATH",
+    "DOCKER_TLS_VERIFY",
+    "HTTP_PROXY",
+    "HTTPS_PROXY",
+    "NO_PROXY",
+    // Read by the CLI's Go runtime
+    "GODEBUG",
+    "GOGC",
+    "GOMAXPROCS",
+    "GOMEMLIMIT",
+    "GOTRACEBACK",
+];
+// Prefixes of variables the CLI or the dynamic linker read
+const CLIENT_ENV_PREFIXES: &[&str] = &["DOCKER_", "LD_", "SSL_", "XDG_"];
+
+/// Whether a variable name can't be used for a secret because the docker
+/// CLI passing it on would read it too.
+pub fn is_client_env_name(name: &str) -> bool {
+    let name = name.to_ascii_uppercase();
+    CLIENT_ENV.contains(&name.as_str()) || CLIENT_ENV_PREFIXES.iter().any(|prefix| name.starts_with(prefix))
+}
+
+// Markers `docker run` puts on stderr when it fails before the program
+// starts; exit code 125 alone could also come from the program
+const DOCKER_ERROR_EXIT_CODE: i32 = 125;
+const DOCKER_ERROR_MARKERS: &[&str] = &["docker:", "Error response from daemon", "Unable to find image"];
 
 #[derive(Debug)]
 pub struct ExecutionResult {
     pub exit_code: i32,
-    pub stdout: String,
-    pub stderr: String,
+    pub stdout: CapturedOutput,
+    pub stderr: CapturedOutput,
     pub duration_ms: u64,
-    pub timed_out: bool,
+    pub termination: Termination,
+}
+
+impl ExecutionResult {
+    pub fn into_result(self, encoding: OutputEncoding) -> models::ExecutionResult {
+        models::ExecutionResult {
+            exit_code: self.exit_code,
+            stdout: self.stdout.encode(encoding),
+            stderr: self.stderr.encode(encoding),
+            duration_ms: self.duration_ms,
+            stdout_bytes: self.stdout.total_bytes,
+            stderr_bytes: self.stderr.total_bytes,
+            stdout_truncated: self.stdout.truncated,
+            stderr_truncated: self.stderr.truncated,
+            encoding,
+            termination: Some(self.termination),
+        }
+    }
+    
+    /// Why the program never ran, when `docker` itself failed: a pull error,
+    /// a daemon problem or a container name conflict.
+    pub fn infrastructure_error(&self) -> Option<String> {
+        if self.exit_code != DOCKER_ERROR_EXIT_CODE {
+            return None;
+        }
+        let stderr = self.stderr.to_string_lossy();
+        DOCKER_ERROR_MARKERS
+            .iter()
+            .any(|marker| stderr.contains(marker))
+            .then(|| stderr.trim().to_string())
+    }
+}
+
+const SIGXCPU: i32 = 24;
+
+// Docker reports a container killed by signal N as exit code 128 + N. The
+// kernel delivers SIGXCPU when the RLIMIT_CPU soft limit is crossed.
+fn classify_exit(exit_code: i32) -> Termination {
+    match exit_code {
+        code if code > 128 && code - 128 == SIGXCPU => Termination::CpuTimeLimit,
+        code if code > 128 => Termination::Signaled { signal: code - 128 },
+        code => Termination::Exited { exit_code: code },
+    }
+}
+
+// The OOM killer's SIGKILL looks like any other, so docker's flag decides.
+// A run that hit its timeout was killed by us and stays a timeout.
+fn with_oom_kill(termination: Termination, oom_killed: bool) -> Termination {
+    if oom_killed && termination != Termination::WallClockTimeout {
+        Termination::OutOfMemory
+    } else {
+        termination
+    }
+}
+
+/// Output captured from a single stream, up to its limit.
+#[derive(Debug, Default)]
+pub struct CapturedOutput {
+    pub data: Vec<u8>,
+    // Bytes the process wrote, including any that were discarded
+    pub total_bytes: u64,
+    pub truncated: bool,
+}
+
+impl CapturedOutput {
+    pub fn to_string_lossy(&self) -> String {
+        String::from_utf8_lossy(&self.data).into_owned()
+    }
+    
+    pub fn encode(&self, encoding: OutputEncoding) -> String {
+        match encoding {
+            OutputEncoding::Utf8 => self.to_string_lossy(),
+            OutputEncoding::Base64 => BASE64.encode(&self.data),
+        }
+    }
+}
+
+// Drain `reader`, keeping at most `limit` bytes. What is kept is also
+// forwarded to `sink` as it arrives.
+async fn read_capped<R: AsyncRead + Unpin>(
+    mut reader: R,
+    limit: usize,
+    sink: Option<(OutputStream, OutputSink)>,
+) -> CapturedOutput {
+    let mut output = CapturedOutput::default();
+    let mut buf = [0u8; 8192];
+    
+    loop {
+        let n = match reader.read(&mut buf).await {
+            Ok(0) | Err(_) => break,
+            Ok(n) => n,
+        };
+        output.total_bytes += n as u64;
+        let room = limit.saturating_sub(output.data.len());
+        if n > room {
+            output.truncated = true;
+        }
+        let kept = &buf[..n.min(room)];
+        if let (Some((stream, sink)), false) = (&sink, kept.is_empty()) {
+            // Nobody listening any more is fine; the output is still captured
+            let _ = sink.send((*stream, kept.to_vec()));
+        }
+        output.data.extend_from_slice(kept);
+    }
+    
+    output
 }
 
 impl DockerClient {
@@ -109,72 +269,492 @@ impl DockerClient {
         name: &str,
         config: ContainerConfig,
         mount_path: Option<&Path>,
+        output: Option<OutputSink>,
     ) -> Result<ExecutionResult> {
+        // No --rm: the container must outlive the process so its OOM flag
+        // can be inspected before it is removed below
         let mut cmd = TokioCommand::new("docker");
         cmd.arg("run")
-            .arg("--rm")
             .arg("--name").arg(name);
-            
-        // Add volume mount if provided
-        if let Some(path) = mount_path {
-            cmd.arg("-v").arg(format!("{}:{}:ro", path.display(), config.working_dir));
-        }
+        apply_container_config(&mut cmd, &config, mount_path);
         
-        // Set working directory
-        cmd.arg("-w").arg(&config.working_dir);
+        // Execute with timeout
+        let timeout = config.timeout_seconds.unwrap_or(30);
+        let child = cmd
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
         
-        // Resource limits
-        if let Some(memory) = config.memory_limit {
-            cmd.arg("--memory").arg(format!("{}", memory));
-        }
-        if let Some(cpus) = config.cpu_limit {
-            cmd.arg("--cpus").arg(format!("{}", cpus));
-        }
+        let result = self.wait_with_limits(child, Some(name), config.output_limits, timeout, output).await;
         
-        // Environment variables
-        for (key, value) in &config.environment {
-            cmd.arg("-e").arg(format!("{}={}", key, value));
+        let oom_killed = match self.inspect_oom_killed(name).await {
+            Ok(oom_killed) => oom_killed,
+            Err(e) => {
+                warn!("Failed to inspect container {}: {}", name, e);
+                false
+            }
+        };
+        if let Err(e) = self.remove_container(name).await {
+            warn!("Failed to remove container {}: {}", name, e);
         }
         
-        // Image and command
-        cmd.arg(&config.image);
-        cmd.args(&config.command);
-        
-        // Execute with timeout
+        let mut result = result?;
+        result.termination = with_oom_kill(result.termination, oom_killed);
+        Ok(result)
+    }
+    
+    // Wait for a spawned `docker` process while draining its output through
+    // the configured caps. On timeout the container (when given) is killed
+    // and whatever output was produced so far is returned.
+    async fn wait_with_limits(
+        &self,
+        mut child: Child,
+        container: Option<&str>,
+        limits: OutputLimits,
+        timeout_seconds: u64,
+        output: Option<OutputSink>,
+    ) -> Result<ExecutionResult> {
         let start = std::time::Instant::now();
-        let timeout = config.timeout_seconds.unwrap_or(30);
-        let output = tokio::time::timeout(
-            Duration::from_secs(timeout),
-            cmd.output()
-        ).await;
+        let stdout = child.stdout.take().context("stdout not captured")?;
+        let stderr = child.stderr.take().context("stderr not captured")?;
+        let stdout_sink = output.clone().map(|sink| (OutputStream::Stdout, sink));
+        let stderr_sink = output.map(|sink| (OutputStream::Stderr, sink));
+        let stdout = tokio::spawn(read_capped(stdout, limits.stdout_bytes, stdout_sink));
+        let stderr = tokio::spawn(read_capped(stderr, limits.stderr_bytes, stderr_sink));
         
-        let duration_ms = start.elapsed().as_millis() as u64;
+        let status = tokio::time::timeout(
+            Duration::from_secs(timeout_seconds),
+            child.wait()
+        ).await;
         
-        match output {
-            Ok(Ok(output)) => {
-                Ok(ExecutionResult {
-                    exit_code: output.status.code().unwrap_or(-1),
-                    stdout: String::from_utf8_lossy(&output.stdout).to_string(),
-                    stderr: String::from_utf8_lossy(&output.stderr).to_string(),
-                    duration_ms,
-                    timed_out: false,
-                })
+        let (exit_code, termination) = match status {
+            Ok(Ok(status)) => {
+                let exit_code = status.code().unwrap_or(-1);
+                (exit_code, classify_exit(exit_code))
             }
-            Ok(Err(e)) => Err(e.into()),
+            Ok(Err(e)) => return Err(e.into()),
             Err(_) => {
-                // Timeout - try to kill container
-                let _ = Command::new("docker")
-                    .args(&["kill", name])
-                    .output();
-                    
-                Ok(ExecutionResult {
-                    exit_code: -1,
-                    stdout: String::new(),
-                    stderr: "Execution timed out".to_string(),
-                    duration_ms,
-                    timed_out: true,
-                })
+                if let Some(name) = container {
+                    if let Err(e) = self.kill_container(name).await {
+                        warn!("Failed to kill timed out container {}: {}", name, e);
+                    }
+                }
+                let _ = child.kill().await;
+                (-1, Termination::WallClockTimeout)
+            }
+        };
+        
+        let duration_ms = start.elapsed().as_millis() as u64;
+        
+        Ok(ExecutionResult {
+            exit_code,
+            stdout: stdout.await?,
+            stderr: stderr.await?,
+            duration_ms,
+            termination,
+        })
+    }
+    
+    /// List every container, running or not, carrying the managed label.
+    pub async fn list_managed_containers(&self) -> Result<Vec<ManagedContainer>> {
+        let format = format!(
+            "{{{{.Names}}}}\t{{{{.Label \"{}\"}}}}\t{{{{.Label \"{}\"}}}}",
+            JOB_ID_LABEL, DEADLINE_LABEL
+        );
+        let output = TokioCommand::new("docker")
+            .args(["ps", "-a", "--no-trunc"])
+            .arg("--filter").arg(format!("label={}=true", MANAGED_LABEL))
+            .arg("--format").arg(format)
+            .output()
+            .await?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "Failed to list containers: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        
+        let containers = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                let mut fields = line.split('\t');
+                ManagedContainer {
+                    name: fields.next().unwrap_or_default().to_string(),
+                    job_id: fields.next().and_then(|id| Uuid::parse_str(id).ok()),
+                    deadline: fields.next().and_then(|d| d.parse().ok()),
+                }
+            })
+            .collect();
+        Ok(containers)
+    }
+    
+    pub async fn kill_container(&self, name: &str) -> Result<()> {
+        let output = TokioCommand::new("docker")
+            .args(["kill", name])
+            .output()
+            .await?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "Failed to kill container {}: {}",
+                name,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(())
+    }
+    
+    async fn inspect_oom_killed(&self, name: &str) -> Result<bool> {
+        let output = TokioCommand::new("docker")
+            .args(["inspect", "--format", "{{.State.OOMKilled}}", name])
+            .output()
+            .await?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "Failed to inspect container {}: {}",
+                name,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).trim() == "true")
+    }
+    
+    /// Start a long-lived container that stays idle until commands are
+    /// exec'd into it. The caller is responsible for `remove_container`.
+    pub async fn start_container(
+        &self,
+        name: &str,
+        config: &ContainerConfig,
+        mount_path: Option<&Path>,
+    ) -> Result<()> {
+        let mut cmd = TokioCommand::new("docker");
+        cmd.arg("run")
+            .arg("-d")
+            .arg("--name").arg(name);
+        apply_container_config(&mut cmd, config, mount_path);
+        
+        let output = cmd.output().await?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "Failed to start container {}: {}",
+                name,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(())
+    }
+    
+    /// Run a command inside a container started with `start_container`,
+    /// feeding it `stdin` and enforcing `timeout_seconds` from the outside.
+    pub async fn exec(
+        &self,
+        name: &str,
+        command: &[String],
+        stdin: Option<&str>,
+        limits: OutputLimits,
+        timeout_seconds: u64,
+    ) -> Result<ExecutionResult> {
+        let mut cmd = TokioCommand::new("docker");
+        cmd.arg("exec");
+        if stdin.is_some() {
+            cmd.arg("-i");
+        }
+        cmd.arg(name)
+            .args(command)
+            .stdin(if stdin.is_some() { Stdio::piped() } else { Stdio::null() })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        
+        let mut child = cmd.spawn()?;
+        if let (Some(input), Some(mut pipe)) = (stdin, child.stdin.take()) {
+            // Feed stdin concurrently so a program that writes before it
+            // reads can't deadlock against us; dropping the pipe sends EOF.
+            let input = input.to_owned();
+            tokio::spawn(async move {
+                let _ = pipe.write_all(input.as_bytes()).await;
+            });
+        }
+        
+        let result = self.wait_with_limits(child, None, limits, timeout_seconds, None).await?;
+        // Killing the `docker exec` client leaves its process running in the
+        // shared container, where it would eat into later commands' limits
+        if result.termination == Termination::WallClockTimeout {
+            if let Err(e) = self.kill_exec_processes(name).await {
+                warn!("Failed to kill timed out processes in container {}: {}", name, e);
             }
         }
+        Ok(result)
+    }
+    
+    // Kill everything in a container started with `start_container` except
+    // its idle init process. `kill -1` spares PID 1 and the caller.
+    async fn kill_exec_processes(&self, name: &str) -> Result<()> {
+        let output = TokioCommand::new("docker")
+            .args(["exec", name, "sh", "-c", "kill -9 -1"])
+            .output()
+            .await?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "Failed to kill processes in container {}: {}",
+                name,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(())
+    }
+    
+    /// Change the memory limit of a running container.
+    pub async fn update_memory(&self, name: &str, memory: u64) -> Result<()> {
+        let output = TokioCommand::new("docker")
+            .arg("update")
+            .arg("--memory").arg(memory.to_string())
+            .arg("--memory-swap").arg(memory.to_string())
+            .arg(name)
+            .output()
+            .await?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "Failed to update container {}: {}",
+                name,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(())
+    }
+    
+    /// Version of the Docker daemon, which fails if it can't be reached.
+    pub async fn server_version(&self) -> Result<String> {
+        let output = TokioCommand::new("docker")
+            .args(["version", "--format", "{{.Server.Version}}"])
+            .output()
+            .await?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "Docker daemon unreachable: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+    
+    /// References (`repository:tag` and `repository@digest`) of the images
+    /// present locally.
+    pub async fn local_images(&self) -> Result<HashSet<String>> {
+        let output = TokioCommand::new("docker")
+            .args(["image", "ls", "--format", "{{.Repository}}:{{.Tag}} {{.Repository}}@{{.Digest}}"])
+            .output()
+            .await?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "Failed to list images: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .split_whitespace()
+            .map(str::to_string)
+            .collect())
     }
-}
\ No newline at end of file
+    
+    /// The local image id (`sha256:...`) for a tag.
+    pub async fn image_digest(&self, image: &str) -> Result<String> {
+        let output = TokioCommand::new("docker")
+            .args(["image", "inspect", "--format", "{{.Id}}", image])
+            .output()
+            .await?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "Failed to inspect image {}: {}",
+                image,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+    
+    pub async fn remove_container(&self, name: &str) -> Result<()> {
+        let output = TokioCommand::new("docker")
+            .args(["rm", "-f", name])
+            .output()
+            .await?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "Failed to remove container {}: {}",
+                name,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(())
+    }
+}
+
+fn apply_container_config(cmd: &mut TokioCommand, config: &ContainerConfig, mount_path: Option<&Path>) {
+    // Add volume mount if provided
+    if let Some(path) = mount_path {
+        cmd.arg("-v").arg(format!("{}:{}:ro", path.display(), config.working_dir));
+    }
+    
+    // Set working directory
+    cmd.arg("-w").arg(&config.working_dir);
+    
+    // Resource limits
+    if let Some(memory) = config.memory_limit {
+        cmd.arg("--memory").arg(format!("{}", memory));
+    }
+    if let Some(cpus) = config.cpu_limit {
+        cmd.arg("--cpus").arg(format!("{}", cpus));
+    }
+    if let Some(cpu_seconds) = config.cpu_time_limit {
+        // SIGXCPU at the soft limit, SIGKILL one second later
+        cmd.arg("--ulimit").arg(format!("cpu={}:{}", cpu_seconds, cpu_seconds + 1));
+    }
+    
+    // Environment variables. Secret values go through the CLI's environment
+    // so they never appear in its arguments.
+    for (key, value) in &config.environment {
+        cmd.arg("-e").arg(format!("{}={}", key, value));
+    }
+    cmd.env_clear();
+    for name in CLIENT_ENV {
+        if let Some(value) = std::env::var_os(name) {
+            cmd.env(name, value);
+        }
+    }
+    for (key, value) in &config.secrets {
+        cmd.env(key, value);
+        cmd.arg("-e").arg(key);
+    }
+    
+    for (key, value) in &config.labels {
+        cmd.arg("--label").arg(format!("{}={}", key, value));
+    }
+    
+    // Image and command
+    cmd.arg(&config.image);
+    cmd.args(&config.command);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn captured(data: &[u8], truncated: bool) -> CapturedOutput {
+        CapturedOutput {
+            data: data.to_vec(),
+            total_bytes: data.len() as u64,
+            truncated,
+        }
+    }
+
+    #[tokio::test]
+    async fn output_within_the_limit_is_kept_whole() {
+        let output = read_capped(&b"hello\n"[..], 6, None).await;
+        assert_eq!(output.data, b"hello\n");
+        assert_eq!(output.total_bytes, 6);
+        assert!(!output.truncated);
+    }
+
+    #[tokio::test]
+    async fn output_past_the_limit_is_cut_but_counted() {
+        // Larger than one read, so the cap falls in a later chunk
+        let written = vec![b'x'; 20_000];
+        let output = read_capped(&written[..], 10_000, None).await;
+        assert_eq!(output.data.len(), 10_000);
+        assert_eq!(output.total_bytes, 20_000);
+        assert!(output.truncated);
+    }
+
+    #[tokio::test]
+    async fn zero_limit_keeps_nothing() {
+        let output = read_capped(&b"abc"[..], 0, None).await;
+        assert!(output.data.is_empty());
+        assert_eq!(output.total_bytes, 3);
+        assert!(output.truncated);
+    }
+
+    #[tokio::test]
+    async fn only_kept_output_is_streamed() {
+        let (sink, mut received) = mpsc::unbounded_channel();
+        let output = read_capped(&b"abcdef"[..], 4, Some((OutputStream::Stderr, sink))).await;
+        assert_eq!(output.data, b"abcd");
+
+        let mut streamed = vec![];
+        while let Ok((stream, chunk)) = received.try_recv() {
+            assert_eq!(stream, OutputStream::Stderr);
+            streamed.extend(chunk);
+        }
+        assert_eq!(streamed, b"abcd");
+    }
+
+    #[test]
+    fn output_is_encoded_as_requested() {
+        let output = captured(&[0x68, 0x69, 0xff], false);
+        assert_eq!(output.encode(OutputEncoding::Utf8), "hi\u{fffd}");
+        assert_eq!(output.encode(OutputEncoding::Base64), "aGn/");
+    }
+
+    #[test]
+    fn results_carry_sizes_and_truncation() {
+        let result = ExecutionResult {
+            exit_code: 0,
+            stdout: CapturedOutput {
+                total_bytes: 10,
+                ..captured(b"12345", true)
+            },
+            stderr: captured(b"", false),
+            duration_ms: 5,
+            termination: Termination::Exited { exit_code: 0 },
+        }
+        .into_result(OutputEncoding::Utf8);
+        assert_eq!(result.stdout, "12345");
+        assert_eq!((result.stdout_bytes, result.stdout_truncated), (10, true));
+        assert_eq!((result.stderr_bytes, result.stderr_truncated), (0, false));
+    }
+
+    #[test]
+    fn docker_failures_are_told_apart_from_the_program() {
+        let result = |exit_code, stderr: &str| ExecutionResult {
+            exit_code,
+            stdout: captured(b"", false),
+            stderr: captured(stderr.as_bytes(), false),
+            duration_ms: 0,
+            termination: Termination::Exited { exit_code },
+        };
+        let pull_error = "Unable to find image 'nope:latest' locally\n";
+        assert_eq!(
+            result(125, pull_error).infrastructure_error().as_deref(),
+            Some("Unable to find image 'nope:latest' locally")
+        );
+        // The program may exit 125 itself, or print docker-like text
+        assert_eq!(result(125, "bad input\n").infrastructure_error(), None);
+        assert_eq!(result(1, pull_error).infrastructure_error(), None);
+    }
+
+    #[test]
+    fn exit_codes_are_classified() {
+        let cases = [
+            (0, Termination::Exited { exit_code: 0 }),
+            (1, Termination::Exited { exit_code: 1 }),
+            (128, Termination::Exited { exit_code: 128 }),
+            (-1, Termination::Exited { exit_code: -1 }),
+            (128 + SIGXCPU, Termination::CpuTimeLimit),
+            (137, Termination::Signaled { signal: 9 }),
+            (139, Termination::Signaled { signal: 11 }),
+        ];
+        for (exit_code, expected) in cases {
+            assert_eq!(classify_exit(exit_code), expected, "exit code {exit_code}");
+        }
+    }
+
+    #[test]
+    fn oom_kills_override_the_exit_but_not_a_timeout() {
+        let killed = Termination::Signaled { signal: 9 };
+        assert_eq!(with_oom_kill(killed, true), Termination::OutOfMemory);
+        assert_eq!(with_oom_kill(Termination::Exited { exit_code: 1 }, true), Termination::OutOfMemory);
+        assert_eq!(with_oom_kill(killed, false), killed);
+        assert_eq!(with_oom_kill(Termination::WallClockTimeout, true), Termination::WallClockTimeout);
+    }
+}
@@ -1,16 +1,39 @@
+use crate::request_id;
 use axum::{
-    http::StatusCode,
+    http::{header, HeaderValue, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
-use serde_json::json;
+use serde::Serialize;
 use thiserror::Error;
+use tracing::error;
+use utoipa::ToSchema;
 
 #[derive(Error, Debug)]
 pub enum ServiceError {
     #[error("Not found")]
     NotFound,
 
+    #[error("Invalid request: {0}")]
+    InvalidRequest(String),
+
+    // Well-formed request with field values we can't accept
+    #[error("Validation failed: {}", describe_violations(.0))]
+    Validation(Vec<FieldViolation>),
+
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
+
+    #[error("Conflict: {0}")]
+    Conflict(String),
+
+    #[error("Quota exceeded: {reason}")]
+    QuotaExceeded { reason: String, retry_after: u64 },
+
+    // This instance is draining before it exits
+    #[error("Shutting down")]
+    ShuttingDown,
+
     #[error("Redis error: {0}")]
     Redis(#[from] redis::RedisError),
 
@@ -21,19 +44,256 @@ pub enum ServiceError {
     Internal(#[from] anyhow::Error),
 }
 
+/// How an error is reported on each front door.
+struct Classification {
+    code: &'static str,
+    http: StatusCode,
+    grpc: tonic::Code,
+    retryable: bool,
+}
+
+impl ServiceError {
+    // The single mapping from error variants to what clients see
+    fn classify(&self) -> Classification {
+        let (code, http, grpc, retryable) = match self {
+            ServiceError::NotFound => ("not_found", StatusCode::NOT_FOUND, tonic::Code::NotFound, false),
+            ServiceError::InvalidRequest(_) => ("invalid_request", StatusCode::BAD_REQUEST, tonic::Code::InvalidArgument, false),
+            ServiceError::Validation(_) => ("validation_failed", StatusCode::UNPROCESSABLE_ENTITY, tonic::Code::InvalidArgument, false),
+            ServiceError::Forbidden(_) => ("forbidden", StatusCode::FORBIDDEN, tonic::Code::PermissionDenied, false),
+            ServiceError::Conflict(_) => ("conflict", StatusCode::CONFLICT, tonic::Code::FailedPrecondition, false),
+            ServiceError::QuotaExceeded { .. } => ("quota_exceeded", StatusCode::TOO_MANY_REQUESTS, tonic::Code::ResourceExhausted, true),
+            ServiceError::ShuttingDown => ("shutting_down", StatusCode::SERVICE_UNAVAILABLE, tonic::Code::Unavailable, true),
+            ServiceError::Redis(_) => ("storage_unavailable", StatusCode::SERVICE_UNAVAILABLE, tonic::Code::Unavailable, true),
+            ServiceError::Serialization(_) => ("internal", StatusCode::INTERNAL_SERVER_ERROR, tonic::Code::Internal, false),
+            ServiceError::Internal(_) => ("internal", StatusCode::INTERNAL_SERVER_ERROR, tonic::Code::Internal, false),
+        };
+        Classification { code, http, grpc, retryable }
+    }
+
+    /// Machine-readable error code, stable across releases.
+    pub fn code(&self) -> &'static str {
+        self.classify().code
+    }
+
+    pub fn status_code(&self) -> StatusCode {
+        self.classify().http
+    }
+
+    pub fn grpc_code(&self) -> tonic::Code {
+        self.classify().grpc
+    }
+
+    /// Whether the same request may succeed if sent again later.
+    pub fn retryable(&self) -> bool {
+        self.classify().retryable
+    }
+
+    /// Validation failures for a request embedded in another one, with field
+    /// paths relative to the outer request.
+    pub fn nested_under(self, parent: &str) -> Self {
+        match self {
+            ServiceError::Validation(violations) => ServiceError::Validation(
+                violations
+                    .into_iter()
+                    .map(|v| FieldViolation::new(format!("{}.{}", parent, v.field), v.description))
+                    .collect(),
+            ),
+            other => other,
+        }
+    }
+
+    /// Message safe to show to clients. Storage and internal failures are
+    /// logged in full but reported generically.
+    pub fn public_message(&self) -> String {
+        match self {
+            ServiceError::NotFound => "Not found".to_string(),
+            ServiceError::InvalidRequest(reason)
+            | ServiceError::Forbidden(reason)
+            | ServiceError::Conflict(reason)
+            | ServiceError::QuotaExceeded { reason, .. } => reason.clone(),
+            ServiceError::Validation(violations) => format!("Invalid request: {}", describe_violations(violations)),
+            ServiceError::ShuttingDown => "Service is shutting down; retry shortly".to_string(),
+            ServiceError::Redis(_) => "Storage unavailable".to_string(),
+            ServiceError::Serialization(_) => "Serialization error".to_string(),
+            ServiceError::Internal(_) => "Internal error".to_string(),
+        }
+    }
+}
+
+/// One rejected field of a request.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct FieldViolation {
+    // Path into the request, e.g. `test_cases[2].stdin`
+    pub field: String,
+    pub description: String,
+}
+
+impl FieldViolation {
+    pub fn new(field: impl Into<String>, description: impl Into<String>) -> Self {
+        Self {
+            field: field.into(),
+            description: description.into(),
+        }
+    }
+}
+
+fn describe_violations(violations: &[FieldViolation]) -> String {
+    violations
+        .iter()
+        .map(|v| if v.field.is_empty() { v.description.clone() } else { format!("{}: {}", v.field, v.description) })
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// Body of every error response.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ErrorBody {
+    pub error: ErrorDetail,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ErrorDetail {
+    /// Machine-readable error code, e.g. `not_found` or `validation_failed`.
+    pub code: String,
+    pub message: String,
+    /// The offending fields, for validation failures.
+    pub details: Vec<FieldViolation>,
+    /// Matches the `X-Request-Id` response header; quote it when reporting problems.
+    pub request_id: Option<String>,
+    /// Whether sending the same request again later may succeed.
+    pub retryable: bool,
+}
+
+impl ErrorBody {
+    pub fn new(code: &str, message: impl Into<String>, details: Vec<FieldViolation>, retryable: bool) -> Self {
+        Self {
+            error: ErrorDetail {
+                code: code.to_string(),
+                message: message.into(),
+                details,
+                request_id: request_id::current(),
+                retryable,
+            },
+        }
+    }
+}
+
 impl IntoResponse for ServiceError {
     fn into_response(self) -> Response {
-        let (status, message) = match self {
-            ServiceError::NotFound => (StatusCode::NOT_FOUND, "Not found"),
-            ServiceError::Redis(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Database error"),
-            ServiceError::Serialization(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Serialization error"),
-            ServiceError::Internal(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Internal error"),
+        let status = self.status_code();
+        if status.is_server_error() {
+            error!(request_id = ?request_id::current(), "Request failed: {:?}", self);
+        }
+
+        let message = self.public_message();
+        let details = match &self {
+            ServiceError::Validation(violations) => violations.clone(),
+            _ => Vec::new(),
         };
+        let body = ErrorBody::new(self.code(), message, details, self.retryable());
+        let mut response = (status, Json(body)).into_response();
+        if let ServiceError::QuotaExceeded { retry_after, .. } = &self {
+            response.headers_mut().insert(header::RETRY_AFTER, HeaderValue::from(*retry_after));
+        }
+        response
+    }
+}
 
-        let body = Json(json!({
-            "error": message,
-        }));
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grpc::IntoStatus;
 
-        (status, body).into_response()
+    fn quota_exceeded() -> ServiceError {
+        ServiceError::QuotaExceeded { reason: "user alice exceeded 10 requests per minute".to_string(), retry_after: 18 }
     }
-}
\ No newline at end of file
+
+    fn redis_error() -> ServiceError {
+        redis::RedisError::from((redis::ErrorKind::IoError, "connection refused", "10.0.0.5:6379".to_string())).into()
+    }
+
+    fn internal_error() -> ServiceError {
+        anyhow::anyhow!("docker socket /var/run/docker.sock unreachable").into()
+    }
+
+    async fn body(error: ServiceError) -> (StatusCode, Option<HeaderValue>, serde_json::Value) {
+        let response = error.into_response();
+        let status = response.status();
+        let retry_after = response.headers().get(header::RETRY_AFTER).cloned();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, retry_after, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    #[test]
+    fn every_variant_has_one_classification() {
+        use tonic::Code;
+        let cases = [
+            (ServiceError::NotFound, "not_found", 404, Code::NotFound, false),
+            (ServiceError::InvalidRequest("bad".into()), "invalid_request", 400, Code::InvalidArgument, false),
+            (ServiceError::Validation(vec![]), "validation_failed", 422, Code::InvalidArgument, false),
+            (ServiceError::Forbidden("no".into()), "forbidden", 403, Code::PermissionDenied, false),
+            (ServiceError::Conflict("taken".into()), "conflict", 409, Code::FailedPrecondition, false),
+            (quota_exceeded(), "quota_exceeded", 429, Code::ResourceExhausted, true),
+            (ServiceError::ShuttingDown, "shutting_down", 503, Code::Unavailable, true),
+            (redis_error(), "storage_unavailable", 503, Code::Unavailable, true),
+            (serde_json::from_str::<u32>("x").unwrap_err().into(), "internal", 500, Code::Internal, false),
+            (internal_error(), "internal", 500, Code::Internal, false),
+        ];
+        for (error, code, http, grpc, retryable) in cases {
+            assert_eq!(
+                (error.code(), error.status_code().as_u16(), error.grpc_code(), error.retryable()),
+                (code, http, grpc, retryable),
+                "{:?}",
+                error
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn error_bodies_have_one_shape() {
+        let error = ServiceError::Validation(vec![FieldViolation::new("timeout_seconds", "must be at most 300")]);
+        let (status, retry_after, body) = body(error).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(retry_after, None);
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "error": {
+                    "code": "validation_failed",
+                    "message": "Invalid request: timeout_seconds: must be at most 300",
+                    "details": [{"field": "timeout_seconds", "description": "must be at most 300"}],
+                    "request_id": null,
+                    "retryable": false,
+                }
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn quota_rejections_say_when_to_retry() {
+        let (status, retry_after, body) = body(quota_exceeded()).await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(retry_after, Some(HeaderValue::from(18)));
+        assert_eq!(body["error"]["message"], "user alice exceeded 10 requests per minute");
+        assert_eq!(body["error"]["retryable"], true);
+
+        let status = quota_exceeded().into_status();
+        assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+        assert_eq!(status.metadata().get("retry-after").unwrap(), "18");
+    }
+
+    #[tokio::test]
+    async fn storage_and_internal_failures_stay_generic() {
+        for (error, message) in [(redis_error(), "Storage unavailable"), (internal_error(), "Internal error")] {
+            let (_, _, body) = body(error).await;
+            assert_eq!(body["error"]["message"], message);
+            let body = body.to_string();
+            assert!(!body.contains("10.0.0.5") && !body.contains("docker.sock"), "{}", body);
+        }
+
+        for (error, message) in [(redis_error(), "Storage unavailable"), (internal_error(), "Internal error")] {
+            let status = error.into_status();
+            assert_eq!(status.message(), message);
+        }
+    }
+}
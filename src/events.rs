@@ -0,0 +1,126 @@
+use crate::error::ServiceError;
+use crate::models::{JobStatus, OutputStream};
+use crate::state::ServiceState;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+// How often a followed stream checks for new events
+const EVENT_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+// Each job's events in order; an event's sequence number is its position
+// in the list, counting from 1
+pub fn events_key(job_id: Uuid) -> String {
+    format!("job:{}:events", job_id)
+}
+
+/// Something that happened to an execution, as streamed to clients.
+/// Mirrors the gRPC `ExecutionEvent`.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ExecutionEvent {
+    // Assigned from the event's position when read back, never stored
+    #[serde(default)]
+    pub sequence: u64,
+    pub execution_id: Uuid,
+    pub timestamp: DateTime<Utc>,
+    #[serde(flatten)]
+    pub event: EventKind,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    StatusChange {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        old_status: Option<JobStatus>,
+        new_status: JobStatus,
+        #[serde(default, skip_serializing_if = "String::is_empty")]
+        message: String,
+    },
+    Output {
+        #[serde(rename = "type")]
+        stream: OutputStream,
+        // Encoded with the job's output encoding
+        data: String,
+    },
+    Error {
+        code: String,
+        message: String,
+        // The execution won't be retried
+        fatal: bool,
+    },
+}
+
+impl EventKind {
+    /// SSE event name, matching the `oneof` field in the gRPC model.
+    pub fn name(&self) -> &'static str {
+        match self {
+            EventKind::StatusChange { .. } => "status_change",
+            EventKind::Output { .. } => "output",
+            EventKind::Error { .. } => "error",
+        }
+    }
+}
+
+impl ExecutionEvent {
+    pub fn new(execution_id: Uuid, event: EventKind) -> Self {
+        Self {
+            sequence: 0,
+            execution_id,
+            timestamp: Utc::now(),
+            event,
+        }
+    }
+
+    /// Whether this is the last event the execution will produce.
+    pub fn is_final(&self) -> bool {
+        matches!(&self.event, EventKind::StatusChange { new_status, .. } if new_status.is_terminal())
+    }
+}
+
+/// Follow a job's events with a sequence number above `after` as they are
+/// published, for the SSE and gRPC streams. Ends after the final status
+/// change, or once the receiver is dropped.
+pub fn follow(state: Arc<ServiceState>, id: Uuid, after: u64) -> mpsc::Receiver<ExecutionEvent> {
+    let (sender, receiver) = mpsc::channel(64);
+    tokio::spawn(async move {
+        let mut last = after;
+        loop {
+            // Status first: if the job had already finished, the events read
+            // next include its final one
+            let finished = match state.get_execution(id).await {
+                Ok(job) => job.status.is_terminal(),
+                Err(ServiceError::NotFound) => true,
+                Err(e) => {
+                    tracing::error!("Event stream for {} failed: {}", id, e);
+                    return;
+                }
+            };
+            let events = match state.execution_events(id, last).await {
+                Ok(events) => events,
+                Err(e) => {
+                    tracing::error!("Event stream for {} failed: {}", id, e);
+                    return;
+                }
+            };
+
+            let ended = finished && (events.is_empty() || events.last().is_some_and(|event| event.is_final()));
+            for event in events {
+                last = event.sequence;
+                // The client went away
+                if sender.send(event).await.is_err() {
+                    return;
+                }
+            }
+            if ended {
+                return;
+            }
+            tokio::time::sleep(EVENT_POLL_INTERVAL).await;
+        }
+    });
+    receiver
+}
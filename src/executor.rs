@@ -1,65 +1,301 @@
 use anyhow::Result;
-use std::collections::HashMap;
-use std::path::Path;
+use std::collections::{BTreeSet, HashMap};
+use std::path::PathBuf;
 use uuid::Uuid;
 
-use crate::docker::{self, ContainerConfig};
+use crate::batch;
+use crate::config::RuntimeConfig;
+use crate::docker::{self, ContainerConfig, OutputLimits, OutputSink};
+use crate::models::{BatchResult, CreateExecutionRequest, OutputEncoding, Termination, TestCase};
+use crate::secrets::Redactor;
+use crate::validation::SUPPORTED_LANGUAGES;
 
+const DEFAULT_MEMORY_LIMIT: u64 = 512 * 1024 * 1024; // 512MB
+const DEFAULT_TIMEOUT_SECONDS: u64 = 30;
+const COMPILE_TIMEOUT_SECONDS: u64 = 60;
+// Extra time the outer timeout allows beyond the in-container `timeout`
+const EXEC_GRACE_SECONDS: u64 = 2;
+// How long a batch case gets to exit after SIGTERM before `timeout` kills it
+const KILL_AFTER_SECONDS: u64 = 1;
+
+// Every container for an execution, single or batch, is named after it
+fn container_name(execution_id: Uuid) -> String {
+    format!("execution-{}", execution_id)
+}
+
+fn default_image(language: &str) -> &'static str {
+    match language {
+        "python" => "python:3.11-slim",
+        "javascript" | "typescript" => "node:20-slim",
+        "rust" => "rust:1.75-slim",
+        "go" => "golang:1.21-alpine",
+        "java" => "openjdk:17-slim",
+        "ruby" => "ruby:3.2-slim",
+        "php" => "php:8.2-cli",
+        _ => "ubuntu:22.04",
+    }
+}
+
+// `docker image ls` lists untagged references under `latest`
+fn with_default_tag(image: &str) -> String {
+    let name = image.rsplit('/').next().unwrap_or(image);
+    if name.contains(':') || name.contains('@') {
+        image.to_string()
+    } else {
+        format!("{}:latest", image)
+    }
+}
+
+/// What the container runtime looks like from here.
+pub struct RuntimeStatus {
+    pub version: String,
+    // Language images that would have to be pulled on first use
+    pub missing_images: Vec<String>,
+}
+
+/// The execution engine behind every entry point: runs code in a container
+/// for each supported language, once or against a batch of test cases.
 pub struct DockerExecutor {
     docker: docker::DockerClient,
+    output_limits: OutputLimits,
+    workspace_root: PathBuf,
+    // Built-in images with the language registry's overrides applied
+    images: HashMap<String, String>,
 }
 
 impl DockerExecutor {
-    pub async fn new() -> Result<Self> {
+    pub async fn new(runtime: &RuntimeConfig) -> Result<Self> {
+        let mut images: HashMap<String, String> = SUPPORTED_LANGUAGES
+            .iter()
+            .map(|language| (language.to_string(), default_image(language).to_string()))
+            .collect();
+        images.extend(runtime.language_images()?);
         Ok(Self {
             docker: docker::DockerClient::new().await?,
+            output_limits: runtime.output_limits,
+            workspace_root: runtime.workspace_root.clone(),
+            images,
         })
     }
     
-    pub async fn execute(&self, 
+    /// Run the request's code once in a fresh container, sending output to
+    /// `output` as it is produced.
+    pub async fn execute(
+        &self,
         execution_id: Uuid,
-        code: &str,
-        language: &str,
-        args: Vec<String>,
-        environment: HashMap<String, String>,
-        timeout_seconds: Option<i32>,
+        request: &CreateExecutionRequest,
+        secrets: HashMap<String, String>,
+        output: Option<OutputSink>,
     ) -> Result<docker::ExecutionResult> {
-        let config = ContainerConfig {
-            image: self.get_image_for_language(language),
-            command: self.get_command_for_language(language, &args),
-            environment,
-            working_dir: "/workspace".to_string(),
-            memory_limit: Some(512 * 1024 * 1024), // 512MB
-            cpu_limit: Some(1.0),
-            timeout_seconds: timeout_seconds.map(|t| t as u64),
-        };
+        let language = request.language.as_str();
+        let config = self.run_config(execution_id, request, secrets);
         
         // Create temporary file for code
-        let temp_dir = tempfile::tempdir()?;
+        let temp_dir = docker::create_workspace(&self.workspace_root)?;
         let code_file = temp_dir.path().join(self.get_filename_for_language(language));
-        std::fs::write(&code_file, code)?;
+        std::fs::write(&code_file, &request.code)?;
         
         // Execute in container
-        let result = self.docker.run_container(
-            &format!("execution-{}", execution_id),
+        self.docker.run_container(
+            &container_name(execution_id),
             config,
             Some(temp_dir.path()),
-        ).await?;
+            output,
+        ).await
+    }
+    
+    fn run_config(
+        &self,
+        execution_id: Uuid,
+        request: &CreateExecutionRequest,
+        secrets: HashMap<String, String>,
+    ) -> ContainerConfig {
+        let language = request.language.as_str();
+        let timeout_seconds = request.timeout_seconds.unwrap_or(DEFAULT_TIMEOUT_SECONDS);
+        ContainerConfig {
+            image: self.get_image_for_language(language),
+            command: self.get_command_for_language(language, request.args.as_deref().unwrap_or_default()),
+            environment: request.environment.clone().unwrap_or_default(),
+            secrets,
+            working_dir: "/workspace".to_string(),
+            memory_limit: Some(DEFAULT_MEMORY_LIMIT),
+            cpu_limit: Some(1.0),
+            timeout_seconds: Some(timeout_seconds),
+            cpu_time_limit: request.cpu_time_limit_seconds,
+            output_limits: self.output_limits,
+            labels: docker::job_labels(execution_id, timeout_seconds),
+        }
+    }
+    
+    /// Kill and remove an execution's container, wherever it has got to.
+    pub async fn abort(&self, execution_id: Uuid) -> Result<()> {
+        self.docker.remove_container(&container_name(execution_id)).await
+    }
+    
+    /// Compile the request's code once inside a single container, then run
+    /// it against every test case and judge the output. Secret values are
+    /// redacted from the output before judging.
+    pub async fn execute_batch(
+        &self,
+        execution_id: Uuid,
+        request: &CreateExecutionRequest,
+        test_cases: &[TestCase],
+        secrets: HashMap<String, String>,
+    ) -> Result<BatchResult> {
+        let language = request.language.as_str();
+        let default_timeout_seconds = request.timeout_seconds.unwrap_or(DEFAULT_TIMEOUT_SECONDS);
+        let redactor = Redactor::new(secrets.values());
+        let config = self.batch_config(execution_id, request, test_cases, secrets);
+
+        let temp_dir = docker::create_workspace(&self.workspace_root)?;
+        let code_file = temp_dir.path().join(self.get_filename_for_language(language));
+        std::fs::write(&code_file, &request.code)?;
+        
+        let name = container_name(execution_id);
+        self.docker.start_container(&name, &config, Some(temp_dir.path())).await?;
+        
+        let result = self
+            .run_batch(&name, request, test_cases, default_timeout_seconds, &redactor)
+            .await;
+        
+        if let Err(e) = self.docker.remove_container(&name).await {
+            tracing::warn!("Failed to remove batch container {}: {}", name, e);
+        }
+        
+        result
+    }
+
+    // The shared container stays idle; every step runs through `docker exec`
+    // and inherits its limits
+    fn batch_config(
+        &self,
+        execution_id: Uuid,
+        request: &CreateExecutionRequest,
+        test_cases: &[TestCase],
+        secrets: HashMap<String, String>,
+    ) -> ContainerConfig {
+        let default_timeout_seconds = request.timeout_seconds.unwrap_or(DEFAULT_TIMEOUT_SECONDS);
+        // Upper bound on how long the shared container may legitimately live
+        let lifetime = COMPILE_TIMEOUT_SECONDS
+            + test_cases
+                .iter()
+                .map(|case| {
+                    case.timeout_seconds.unwrap_or(default_timeout_seconds) + KILL_AFTER_SECONDS + EXEC_GRACE_SECONDS
+                })
+                .sum::<u64>();
+
+        ContainerConfig {
+            image: self.get_image_for_language(&request.language),
+            command: vec!["tail".to_string(), "-f".to_string(), "/dev/null".to_string()],
+            environment: request.environment.clone().unwrap_or_default(),
+            secrets,
+            working_dir: "/workspace".to_string(),
+            memory_limit: Some(DEFAULT_MEMORY_LIMIT),
+            cpu_limit: Some(1.0),
+            timeout_seconds: None,
+            // RLIMIT_CPU is per process, so each case gets the whole limit
+            cpu_time_limit: request.cpu_time_limit_seconds,
+            output_limits: self.output_limits,
+            labels: docker::job_labels(execution_id, lifetime),
+        }
+    }
+
+    async fn run_batch(
+        &self,
+        name: &str,
+        request: &CreateExecutionRequest,
+        test_cases: &[TestCase],
+        default_timeout_seconds: u64,
+        redactor: &Redactor,
+    ) -> Result<BatchResult> {
+        let language = request.language.as_str();
+        let compile = match self.get_compile_command_for_language(language) {
+            Some(command) => {
+                let mut result = self.docker.exec(name, &command, None, self.output_limits, COMPILE_TIMEOUT_SECONDS).await?;
+                redactor.redact_result(&mut result);
+                let failed = result.termination != Termination::Exited { exit_code: 0 };
+                let compile = result.into_result(OutputEncoding::Utf8);
+                if failed {
+                    return Ok(BatchResult {
+                        compile: Some(compile),
+                        cases: vec![],
+                        passed: 0,
+                        total: test_cases.len(),
+                    });
+                }
+                Some(compile)
+            }
+            None => None,
+        };
+        
+        let mut run_command = self.get_run_command_for_language(language);
+        run_command.extend(request.args.iter().flatten().cloned());
+        let mut current_memory = DEFAULT_MEMORY_LIMIT;
+        let mut cases = Vec::with_capacity(test_cases.len());
+        
+        for (index, case) in test_cases.iter().enumerate() {
+            let case_memory = case.memory_mb.map(|mb| mb * 1024 * 1024).unwrap_or(DEFAULT_MEMORY_LIMIT);
+            if case_memory != current_memory {
+                self.docker.update_memory(name, case_memory).await?;
+                current_memory = case_memory;
+            }
+            
+            let timeout = case.timeout_seconds.unwrap_or(default_timeout_seconds);
+            let mut command = vec![
+                "timeout".to_string(),
+                "-k".to_string(),
+                KILL_AFTER_SECONDS.to_string(),
+                timeout.to_string(),
+            ];
+            command.extend(run_command.iter().cloned());
+            
+            let mut result = self.docker
+                .exec(name, &command, Some(&case.stdin), self.output_limits, timeout + KILL_AFTER_SECONDS + EXEC_GRACE_SECONDS)
+                .await?;
+            redactor.redact_result(&mut result);
+            cases.push(batch::judge(index, case, timeout, result));
+        }
+        
+        let passed = cases
+            .iter()
+            .filter(|case| case.verdict == crate::models::Verdict::Accepted)
+            .count();
         
-        Ok(result)
+        Ok(BatchResult {
+            compile,
+            cases,
+            passed,
+            total: test_cases.len(),
+        })
+    }
+    
+    /// Check the Docker daemon answers and which language images are
+    /// missing locally.
+    pub async fn runtime_status(&self) -> Result<RuntimeStatus> {
+        let version = self.docker.server_version().await?;
+        let local = self.docker.local_images().await?;
+        let missing_images = self
+            .images
+            .values()
+            .map(|image| with_default_tag(image))
+            .filter(|image| !local.contains(image))
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        Ok(RuntimeStatus { version, missing_images })
+    }
+    
+    /// Digest of the image `language` runs in, so cached results are tied
+    /// to the exact runtime that produced them.
+    pub async fn image_digest(&self, language: &str) -> Result<String> {
+        self.docker.image_digest(&self.get_image_for_language(language)).await
     }
     
     fn get_image_for_language(&self, language: &str) -> String {
-        match language {
-            "python" => "python:3.11-slim",
-            "javascript" | "typescript" => "node:20-slim",
-            "rust" => "rust:1.75-slim",
-            "go" => "golang:1.21-alpine",
-            "java" => "openjdk:17-slim",
-            "ruby" => "ruby:3.2-slim",
-            "php" => "php:8.2-cli",
-            _ => "ubuntu:22.04",
-        }.to_string()
+        self.images
+            .get(language)
+            .cloned()
+            .unwrap_or_else(|| default_image(language).to_string())
     }
     
     fn get_command_for_language(&self, language: &str, args: &[String]) -> Vec<String> {
@@ -67,7 +303,8 @@ impl DockerExecutor {
             "python" => vec!["python", "main.py"],
             "javascript" => vec!["node", "main.js"],
             "typescript" => vec!["npx", "tsx", "main.ts"],
-            "rust" => vec!["cargo", "run"],
+            // Compiled to /tmp, as the workspace is read-only
+            "rust" => vec!["sh", "-c", "rustc -O -o /tmp/main main.rs && exec /tmp/main \"$@\"", "main"],
             "go" => vec!["go", "run", "main.go"],
             "java" => vec!["java", "Main.java"],
             "ruby" => vec!["ruby", "main.rb"],
@@ -80,6 +317,31 @@ impl DockerExecutor {
         cmd
     }
     
+    // Build step for compiled languages; artifacts go to /tmp because the
+    // workspace is mounted read-only
+    fn get_compile_command_for_language(&self, language: &str) -> Option<Vec<String>> {
+        let cmd = match language {
+            "rust" => vec!["rustc", "-O", "-o", "/tmp/main", "main.rs"],
+            "go" => vec!["go", "build", "-o", "/tmp/main", "main.go"],
+            "java" => vec!["javac", "-d", "/tmp", "Main.java"],
+            _ => return None,
+        };
+        Some(cmd.into_iter().map(String::from).collect())
+    }
+    
+    // Command that runs the compiled artifact, or the source directly for
+    // interpreted languages
+    fn get_run_command_for_language(&self, language: &str) -> Vec<String> {
+        match language {
+            "rust" | "go" => vec!["/tmp/main".to_string()],
+            "java" => vec!["java", "-cp", "/tmp", "Main"]
+                .into_iter()
+                .map(String::from)
+                .collect(),
+            _ => self.get_command_for_language(language, &[]),
+        }
+    }
+    
     fn get_filename_for_language(&self, language: &str) -> &str {
         match language {
             "python" => "main.py",
@@ -94,4 +356,56 @@ impl DockerExecutor {
             _ => "main.txt",
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn executor() -> DockerExecutor {
+        DockerExecutor {
+            docker: docker::DockerClient {},
+            output_limits: OutputLimits::default(),
+            workspace_root: std::env::temp_dir(),
+            images: HashMap::new(),
+        }
+    }
+
+    fn request(cpu_time_limit_seconds: Option<u64>) -> CreateExecutionRequest {
+        serde_json::from_value(serde_json::json!({
+            "code": "print(input())",
+            "language": "python",
+            "timeout_seconds": 5,
+            "cpu_time_limit_seconds": cpu_time_limit_seconds,
+        }))
+        .unwrap()
+    }
+
+    fn case(timeout_seconds: Option<u64>) -> TestCase {
+        TestCase {
+            stdin: String::new(),
+            expected_stdout: String::new(),
+            timeout_seconds,
+            memory_mb: None,
+        }
+    }
+
+    #[test]
+    fn single_runs_get_the_cpu_time_limit() {
+        let config = executor().run_config(Uuid::new_v4(), &request(Some(3)), HashMap::new());
+        assert_eq!(config.cpu_time_limit, Some(3));
+        assert_eq!(config.timeout_seconds, Some(5));
+    }
+
+    #[test]
+    fn batch_runs_get_the_cpu_time_limit() {
+        let executor = executor();
+        let cases = [case(None), case(Some(10))];
+        let config = executor.batch_config(Uuid::new_v4(), &request(Some(3)), &cases, HashMap::new());
+        assert_eq!(config.cpu_time_limit, Some(3));
+        // The idle container itself has no wall-clock limit
+        assert_eq!(config.timeout_seconds, None);
+
+        let config = executor.batch_config(Uuid::new_v4(), &request(None), &cases, HashMap::new());
+        assert_eq!(config.cpu_time_limit, None);
+    }
+}
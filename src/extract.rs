@@ -0,0 +1,125 @@
+use crate::error::{FieldViolation, ServiceError};
+use axum::{
+    async_trait,
+    body::Bytes,
+    extract::{
+        path::ErrorKind,
+        rejection::PathRejection,
+        FromRequest, FromRequestParts, Path, RawPathParams, Request,
+    },
+    http::{header, request::Parts, HeaderMap},
+};
+use serde::de::DeserializeOwned;
+
+// Request extractors that reject with the service's error envelope rather
+// than axum's plain-text rejections, naming the field that failed to parse.
+
+/// JSON request body.
+pub struct ApiJson<T>(pub T);
+
+/// Query string.
+pub struct ApiQuery<T>(pub T);
+
+/// Path parameters.
+pub struct ApiPath<T>(pub T);
+
+fn is_json(headers: &HeaderMap) -> bool {
+    let Some(content_type) = headers.get(header::CONTENT_TYPE).and_then(|value| value.to_str().ok()) else {
+        return false;
+    };
+    let mime = content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+    mime == "application/json" || (mime.starts_with("application/") && mime.ends_with("+json"))
+}
+
+// serde reports a missing field at its parent, so name the field itself
+fn violation(path: String, description: String) -> FieldViolation {
+    let mut field = if path == "." { String::new() } else { path };
+    if let Some(name) = description.strip_prefix("missing field `").and_then(|rest| rest.strip_suffix('`')) {
+        if !field.is_empty() {
+            field.push('.');
+        }
+        field.push_str(name);
+    }
+    FieldViolation::new(field, description)
+}
+
+#[async_trait]
+impl<T, S> FromRequest<S> for ApiJson<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = ServiceError;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        if !is_json(request.headers()) {
+            return Err(ServiceError::InvalidRequest(
+                "Expected a request body with `Content-Type: application/json`".to_string(),
+            ));
+        }
+        let body = Bytes::from_request(request, state)
+            .await
+            .map_err(|rejection| ServiceError::InvalidRequest(rejection.body_text()))?;
+
+        let deserializer = &mut serde_json::Deserializer::from_slice(&body);
+        serde_path_to_error::deserialize(deserializer).map(ApiJson).map_err(|e| {
+            let inner = e.inner();
+            match inner.classify() {
+                serde_json::error::Category::Data => {
+                    // Drop serde_json's "at line N column M"; the path says where
+                    let location = format!(" at line {} column {}", inner.line(), inner.column());
+                    let message = inner.to_string();
+                    let description = message.strip_suffix(&location).unwrap_or(&message).to_string();
+                    ServiceError::Validation(vec![violation(e.path().to_string(), description)])
+                }
+                _ => ServiceError::InvalidRequest(format!("Malformed JSON body: {}", inner)),
+            }
+        })
+    }
+}
+
+#[async_trait]
+impl<T, S> FromRequestParts<S> for ApiQuery<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = ServiceError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let query = parts.uri.query().unwrap_or_default();
+        let deserializer = serde_urlencoded::Deserializer::new(form_urlencoded::parse(query.as_bytes()));
+        serde_path_to_error::deserialize(deserializer)
+            .map(ApiQuery)
+            .map_err(|e| ServiceError::Validation(vec![violation(e.path().to_string(), e.inner().to_string())]))
+    }
+}
+
+#[async_trait]
+impl<T, S> FromRequestParts<S> for ApiPath<T>
+where
+    T: DeserializeOwned + Send,
+    S: Send + Sync,
+{
+    type Rejection = ServiceError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let error = match Path::<T>::from_request_parts(parts, state).await {
+            Ok(Path(value)) => return Ok(ApiPath(value)),
+            Err(PathRejection::FailedToDeserializePathParams(error)) => error,
+            Err(rejection) => return Err(ServiceError::Internal(anyhow::anyhow!(rejection.body_text()))),
+        };
+
+        let field = match error.kind() {
+            ErrorKind::ParseErrorAtKey { key, .. } | ErrorKind::InvalidUtf8InPathParam { key } => key.clone(),
+            // Single values like `Path<Uuid>` don't know their name; the route does
+            _ => match RawPathParams::from_request_parts(parts, state).await {
+                Ok(params) if params.iter().count() == 1 => {
+                    params.iter().next().map(|(key, _)| key.to_string()).unwrap_or_default()
+                }
+                _ => String::new(),
+            },
+        };
+        Err(ServiceError::Validation(vec![FieldViolation::new(field, error.kind().to_string())]))
+    }
+}
@@ -1,7 +1,19 @@
+// Every handler returns tonic's `Status`, which is large by design
+#![allow(clippy::result_large_err)]
+
 pub mod server;
 
-use tonic::{Request, Response, Status};
-use tracing::{info, warn, error};
+use crate::auth::Authenticator;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tonic::body::BoxBody;
+use tonic::Status;
+use tower::{Layer, Service};
+use tracing::{debug, error};
+
+type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
 
 // Re-export generated types
 pub mod proto {
@@ -20,7 +32,6 @@ pub mod proto {
 }
 
 pub use proto::syla::execution::v1::*;
-pub use proto::syla::common::v1::{HealthCheckRequest, HealthCheckResponse, HealthStatus, PageRequest, PageResponse};
 
 // Helper trait for converting errors to gRPC status
 pub trait IntoStatus {
@@ -44,6 +55,20 @@ impl IntoStatus for sqlx::Error {
     }
 }
 
+impl IntoStatus for crate::error::ServiceError {
+    fn into_status(self) -> Status {
+        use crate::error::ServiceError;
+        if matches!(self, ServiceError::Redis(_) | ServiceError::Serialization(_) | ServiceError::Internal(_)) {
+            error!("Error: {:?}", self);
+        }
+        let mut status = Status::new(self.grpc_code(), self.public_message());
+        if let ServiceError::QuotaExceeded { retry_after, .. } = &self {
+            status.metadata_mut().insert("retry-after", (*retry_after).into());
+        }
+        status
+    }
+}
+
 impl IntoStatus for redis::RedisError {
     fn into_status(self) -> Status {
         error!("Redis error: {:?}", self);
@@ -51,40 +76,87 @@ impl IntoStatus for redis::RedisError {
     }
 }
 
-// Authentication interceptor
+// Authentication interceptor, installed as a tower layer on the tonic server
+// so token validation can be async. Authenticated requests carry the
+// `Principal` in their extensions.
 #[derive(Clone)]
 pub struct AuthInterceptor {
-    auth_service_url: String,
+    authenticator: Arc<Authenticator>,
 }
 
+// Methods reachable without a token
+const UNAUTHENTICATED_PATHS: &[&str] = &[
+    "/syla.execution.v1.ExecutionService/HealthCheck",
+    "/grpc.health.v1.Health/Check",
+    "/grpc.health.v1.Health/Watch",
+];
+
 impl AuthInterceptor {
-    pub fn new(auth_service_url: String) -> Self {
-        Self { auth_service_url }
+    pub fn new(authenticator: Arc<Authenticator>) -> Self {
+        Self { authenticator }
     }
     
-    pub async fn intercept(&self, req: Request<()>) -> Result<Request<()>, Status> {
-        // Check for authorization header
+    pub async fn intercept<B>(&self, mut req: http::Request<B>) -> Result<http::Request<B>, Status> {
+        if UNAUTHENTICATED_PATHS.contains(&req.uri().path()) {
+            return Ok(req);
+        }
+        
         let auth_header = req
-            .metadata()
+            .headers()
             .get("authorization")
-            .ok_or_else(|| Status::unauthenticated("Missing authorization header"))?;
-        
-        let auth_str = auth_header
-            .to_str()
+            .map(|value| value.to_str())
+            .transpose()
             .map_err(|_| Status::unauthenticated("Invalid authorization header"))?;
         
-        // Extract bearer token
-        let token = auth_str
-            .strip_prefix("Bearer ")
-            .ok_or_else(|| Status::unauthenticated("Invalid authorization format"))?;
-        
-        // TODO: Validate token with auth service
-        // For now, just check that it's not empty
-        if token.is_empty() {
-            return Err(Status::unauthenticated("Invalid token"));
+        let principal = self.authenticator.authenticate(auth_header).await?;
+        debug!("Authenticated request from {}", principal.subject);
+        req.extensions_mut().insert(principal);
+        Ok(req)
+    }
+}
+
+impl<S> Layer<S> for AuthInterceptor {
+    type Service = AuthService<S>;
+    
+    fn layer(&self, inner: S) -> Self::Service {
+        AuthService {
+            interceptor: self.clone(),
+            inner,
         }
+    }
+}
+
+#[derive(Clone)]
+pub struct AuthService<S> {
+    interceptor: AuthInterceptor,
+    inner: S,
+}
+
+impl<S, B> Service<http::Request<B>> for AuthService<S>
+where
+    S: Service<http::Request<B>, Response = http::Response<BoxBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    B: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+    
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+    
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        // Take the service that was polled ready and leave a fresh clone behind
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let interceptor = self.interceptor.clone();
         
-        info!("Authenticated request");
-        Ok(req)
+        Box::pin(async move {
+            match interceptor.intercept(req).await {
+                Ok(req) => inner.call(req).await,
+                Err(status) => Ok(status.into_http()),
+            }
+        })
     }
-}
\ No newline at end of file
+}
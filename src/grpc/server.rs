@@ -1,41 +1,124 @@
 use super::proto::syla::execution::v1 as proto;
-use super::proto::syla::common::v1::{HealthCheckRequest, HealthCheckResponse, HealthStatus, PageRequest, PageResponse};
+use super::proto::syla::common::v1::{HealthCheckRequest, HealthCheckResponse, HealthStatus, PageResponse};
 use super::IntoStatus;
-use crate::executor::DockerExecutor;
-use crate::models::{Execution, ExecutionStatus as DbExecutionStatus};
-use crate::queue::RedisQueue;
+use crate::auth::Principal;
+use crate::dlq::{DeadLetter, DeadLetterSelection};
+use crate::error::{FieldViolation, ServiceError};
+use crate::events::{self, EventKind, ExecutionEvent};
+use crate::health;
+use crate::idempotency;
+use crate::models::{
+    CreateExecutionRequest, Execution, ExecutionJob, ExecutionStatus as DbExecutionStatus, JobStatus, OutputStream,
+    Termination,
+};
+use crate::pagination::{self, ExecutionFilter};
+use crate::quota::QuotaUsage;
+use crate::state::ServiceState;
+use std::collections::HashMap;
+use std::pin::Pin;
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::time::{Duration, Instant};
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::{Stream, StreamExt};
 use tonic::{Request, Response, Status};
 use uuid::Uuid;
 
+// A synchronous submit waits this much longer than the execution's timeout,
+// for queueing, before answering with the status so far
+const SYNC_WAIT_GRACE: Duration = Duration::from_secs(30);
+const SYNC_POLL_INTERVAL: Duration = Duration::from_millis(250);
+const DEFAULT_TIMEOUT_SECONDS: u64 = 30;
+
 pub struct ExecutionServiceImpl {
-    queue: Arc<RedisQueue>,
-    executor: Arc<DockerExecutor>,
-    // In-memory storage for now, will be replaced with database
-    executions: Arc<RwLock<std::collections::HashMap<String, Execution>>>,
+    state: Arc<ServiceState>,
 }
 
 impl ExecutionServiceImpl {
-    pub fn new(queue: Arc<RedisQueue>, executor: Arc<DockerExecutor>) -> Self {
-        Self {
-            queue,
-            executor,
-            executions: Arc::new(RwLock::new(std::collections::HashMap::new())),
+    pub fn new(state: Arc<ServiceState>) -> Self {
+        Self { state }
+    }
+    
+    fn parse_execution_id(id: &str) -> Result<Uuid, Status> {
+        Uuid::parse_str(id).map_err(|_| Status::invalid_argument("Invalid execution id"))
+    }
+    
+    // Set by `AuthInterceptor` on every authenticated call
+    fn principal<T>(request: &Request<T>) -> Result<Principal, Status> {
+        request
+            .extensions()
+            .get::<Principal>()
+            .cloned()
+            .ok_or_else(|| Status::unauthenticated("Missing credentials"))
+    }
+    
+    // Load an execution the caller is allowed to see. Other users'
+    // executions are reported as missing rather than forbidden.
+    async fn load_owned(&self, principal: &Principal, execution_id: &str) -> Result<ExecutionJob, Status> {
+        let execution_id = Self::parse_execution_id(execution_id)?;
+        let job = self.state.get_execution(execution_id).await.map_err(IntoStatus::into_status)?;
+        if !principal.can_access(&job) {
+            return Err(Status::not_found("Execution not found"));
+        }
+        Ok(job)
+    }
+    
+    // Wait for a submitted job to finish, up to its timeout plus the grace.
+    // Returns it as last seen either way.
+    async fn wait_for_completion(&self, mut job: ExecutionJob) -> Result<ExecutionJob, Status> {
+        let timeout = Duration::from_secs(job.request.timeout_seconds.unwrap_or(DEFAULT_TIMEOUT_SECONDS));
+        let deadline = Instant::now() + timeout + SYNC_WAIT_GRACE;
+        while !job.status.is_terminal() && Instant::now() < deadline {
+            tokio::time::sleep(SYNC_POLL_INTERVAL).await;
+            job = self.state.get_execution(job.id).await.map_err(IntoStatus::into_status)?;
+        }
+        Ok(job)
+    }
+
+    fn event_to_proto(event: ExecutionEvent) -> proto::ExecutionEvent {
+        let event_status = |status: &JobStatus| Self::status_to_proto(&DbExecutionStatus::from(status)) as i32;
+        let kind = match event.event {
+            EventKind::StatusChange { old_status, new_status, message } => {
+                proto::execution_event::Event::StatusChange(proto::StatusChangeEvent {
+                    old_status: old_status.as_ref().map_or(0, event_status),
+                    new_status: event_status(&new_status),
+                    message,
+                })
+            }
+            EventKind::Output { stream, data } => proto::execution_event::Event::Output(proto::OutputEvent {
+                r#type: match stream {
+                    OutputStream::Stdout => proto::OutputType::Stdout,
+                    OutputStream::Stderr => proto::OutputType::Stderr,
+                } as i32,
+                data,
+                sequence: event.sequence as u32,
+            }),
+            EventKind::Error { code, message, fatal } => {
+                proto::execution_event::Event::Error(proto::ErrorEvent { code, message, fatal })
+            }
+        };
+        proto::ExecutionEvent {
+            execution_id: event.execution_id.to_string(),
+            timestamp: Some(prost_types::Timestamp {
+                seconds: event.timestamp.timestamp(),
+                nanos: event.timestamp.timestamp_subsec_nanos() as i32,
+            }),
+            event: Some(kind),
         }
     }
     
-    // Convert database execution to proto execution
-    fn to_proto_execution(&self, db_exec: &Execution) -> proto::Execution {
+    // Convert a job to proto execution
+    fn to_proto_execution(job: &ExecutionJob) -> proto::Execution {
+        let db_exec = &Execution::from(job);
         proto::Execution {
             id: db_exec.id.to_string(),
             user_id: db_exec.user_id.clone(),
             workspace_id: db_exec.workspace_id.clone().unwrap_or_default(),
             request: Some(proto::ExecutionRequest {
                 code: db_exec.code.clone(),
-                language: self.language_to_proto(&db_exec.language) as i32,
+                language: Self::language_to_proto(&db_exec.language) as i32,
                 args: db_exec.args.clone().unwrap_or_default(),
                 environment: db_exec.environment.clone().unwrap_or_default(),
+                secrets: db_exec.secrets.clone().unwrap_or_default(),
                 resources: Some(proto::ResourceRequirements {
                     memory_mb: 512, // Default for now
                     cpu_cores: 1.0,
@@ -50,24 +133,29 @@ impl ExecutionServiceImpl {
                 files: vec![],
                 mode: proto::ExecutionMode::Sandbox as i32,
                 metadata: std::collections::HashMap::new(),
+                not_before: None,
+                cache: false,
+                bypass_cache: false,
+                callback_url: String::new(),
             }),
-            status: self.status_to_proto(&db_exec.status) as i32,
-            result: db_exec.exit_code.map(|code| proto::ExecutionResult {
-                exit_code: code,
-                stdout: db_exec.stdout.clone().unwrap_or_default(),
-                stderr: db_exec.stderr.clone().unwrap_or_default(),
-                files: vec![],
-                outputs: std::collections::HashMap::new(),
-                error: if code != 0 {
-                    Some(proto::ExecutionError {
-                        code: "EXECUTION_FAILED".to_string(),
-                        message: format!("Process exited with code {}", code),
+            status: Self::status_to_proto(&db_exec.status) as i32,
+            result: db_exec.exit_code.map(|code| {
+                let termination = db_exec
+                    .termination
+                    .unwrap_or(Termination::Exited { exit_code: code });
+                proto::ExecutionResult {
+                    exit_code: code,
+                    stdout: db_exec.stdout.clone().unwrap_or_default(),
+                    stderr: db_exec.stderr.clone().unwrap_or_default(),
+                    files: vec![],
+                    outputs: std::collections::HashMap::new(),
+                    error: termination.error_code().map(|error_code| proto::ExecutionError {
+                        code: error_code.to_string(),
+                        message: termination.describe(),
                         details: db_exec.stderr.clone().unwrap_or_default(),
                         stack_trace: String::new(),
-                    })
-                } else {
-                    None
-                },
+                    }),
+                }
             }),
             created_at: Some(prost_types::Timestamp {
                 seconds: db_exec.created_at.timestamp(),
@@ -83,10 +171,16 @@ impl ExecutionServiceImpl {
             }),
             worker_id: String::new(),
             metrics: None,
+            queue_position: job.queue.as_ref().map_or(0, |queue| queue.position),
+            estimated_wait: job.queue.as_ref().map(|queue| prost_types::Duration {
+                seconds: (queue.estimated_wait_ms / 1000) as i64,
+                nanos: (queue.estimated_wait_ms % 1000) as i32 * 1_000_000,
+            }),
+            cache_hit: job.cache_hit,
         }
     }
     
-    fn language_to_proto(&self, lang: &str) -> proto::Language {
+    fn language_to_proto(lang: &str) -> proto::Language {
         match lang {
             "python" => proto::Language::Python,
             "javascript" => proto::Language::Javascript,
@@ -103,13 +197,82 @@ impl ExecutionServiceImpl {
         }
     }
     
-    fn status_to_proto(&self, status: &DbExecutionStatus) -> proto::ExecutionStatus {
+    fn status_to_proto(status: &DbExecutionStatus) -> proto::ExecutionStatus {
         match status {
             DbExecutionStatus::Pending => proto::ExecutionStatus::Pending,
+            DbExecutionStatus::Queued => proto::ExecutionStatus::Queued,
             DbExecutionStatus::Running => proto::ExecutionStatus::Running,
             DbExecutionStatus::Completed => proto::ExecutionStatus::Completed,
             DbExecutionStatus::Failed => proto::ExecutionStatus::Failed,
             DbExecutionStatus::Cancelled => proto::ExecutionStatus::Cancelled,
+            DbExecutionStatus::Timeout => proto::ExecutionStatus::Timeout,
+        }
+    }
+    
+    // Unspecified means no filter; PREPARING has no counterpart here
+    fn proto_to_status(status: i32) -> Result<Option<JobStatus>, Status> {
+        let status = match proto::ExecutionStatus::try_from(status) {
+            Ok(proto::ExecutionStatus::Unspecified) => return Ok(None),
+            Ok(proto::ExecutionStatus::Pending) => JobStatus::Pending,
+            Ok(proto::ExecutionStatus::Queued) => JobStatus::Queued,
+            Ok(proto::ExecutionStatus::Running) => JobStatus::Running,
+            Ok(proto::ExecutionStatus::Completed) => JobStatus::Completed,
+            Ok(proto::ExecutionStatus::Failed) => JobStatus::Failed,
+            Ok(proto::ExecutionStatus::Cancelled) => JobStatus::Cancelled,
+            Ok(proto::ExecutionStatus::Timeout) => JobStatus::Timeout,
+            Ok(proto::ExecutionStatus::Preparing) | Err(_) => {
+                return Err(Status::invalid_argument("Unsupported status filter"));
+            }
+        };
+        Ok(Some(status))
+    }
+    
+    // Whole seconds, a partial second rounding up. Out-of-range values are
+    // left for the shared validation to report.
+    fn duration_to_seconds(field: &str, duration: &prost_types::Duration) -> Result<u64, Status> {
+        if duration.seconds < 0 || duration.nanos < 0 {
+            let violation = FieldViolation::new(field, "Must not be negative");
+            return Err(ServiceError::Validation(vec![violation]).into_status());
+        }
+        Ok((duration.seconds as u64).saturating_add(u64::from(duration.nanos > 0)))
+    }
+    
+    fn parse_selection(ids: Vec<String>, all: bool) -> Result<DeadLetterSelection, Status> {
+        let ids = ids
+            .iter()
+            .map(|id| Uuid::parse_str(id))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| Status::invalid_argument("Invalid dead letter id"))?;
+        Ok(DeadLetterSelection { ids, all })
+    }
+    
+    fn dead_letter_to_proto(&self, entry: &DeadLetter) -> proto::DeadLetter {
+        proto::DeadLetter {
+            id: entry.id.to_string(),
+            payload: entry.payload.clone(),
+            execution: entry.job.as_ref().map(Self::to_proto_execution),
+            error: entry.error.clone(),
+            attempts: entry.attempts,
+            dead_lettered_at: Some(prost_types::Timestamp {
+                seconds: entry.dead_lettered_at.timestamp(),
+                nanos: 0,
+            }),
+        }
+    }
+    
+    fn quota_usage_to_proto(usage: QuotaUsage) -> proto::QuotaUsage {
+        proto::QuotaUsage {
+            id: usage.id,
+            requests_this_minute: usage.requests_this_minute,
+            running: usage.running,
+            cpu_seconds_today: usage.cpu_seconds_today,
+            memory_mb_seconds_today: usage.memory_mb_seconds_today,
+            limits: Some(proto::QuotaLimits {
+                requests_per_minute: usage.limits.requests_per_minute.unwrap_or(0),
+                max_concurrent: usage.limits.max_concurrent.unwrap_or(0),
+                daily_cpu_seconds: usage.limits.daily_cpu_seconds.unwrap_or(0),
+                daily_memory_mb_seconds: usage.limits.daily_memory_mb_seconds.unwrap_or(0),
+            }),
         }
     }
     
@@ -126,7 +289,8 @@ impl ExecutionServiceImpl {
             proto::Language::Ruby => "ruby",
             proto::Language::Php => "php",
             proto::Language::Shell => "shell",
-            _ => "unknown",
+            // Left empty for validation to reject
+            _ => "",
         }.to_string()
     }
 }
@@ -137,52 +301,74 @@ impl proto::execution_service_server::ExecutionService for ExecutionServiceImpl
         &self,
         request: Request<proto::SubmitExecutionRequest>,
     ) -> Result<Response<proto::SubmitExecutionResponse>, Status> {
+        let principal = Self::principal(&request)?;
+        let header_key = request
+            .metadata()
+            .get(idempotency::IDEMPOTENCY_HEADER)
+            .map(|value| value.to_str().map(str::to_string))
+            .transpose()
+            .map_err(|_| Status::invalid_argument("idempotency-key must be ASCII"))?;
         let req = request.into_inner();
+        let idempotency_key = Some(req.idempotency_key).filter(|key| !key.is_empty()).or(header_key);
         let exec_req = req.request.ok_or_else(|| Status::invalid_argument("Missing execution request"))?;
         
-        // Create execution record
-        let execution_id = Uuid::new_v4();
         let context = req.context.unwrap_or_default();
-        
-        let execution = Execution {
-            id: execution_id,
-            user_id: context.user_id,
-            workspace_id: Some(context.workspace_id),
+        let priority = match exec_req.metadata.get("priority") {
+            Some(priority) => priority.parse().map_err(Status::invalid_argument)?,
+            None => principal.default_priority(),
+        };
+        let request = CreateExecutionRequest {
             code: exec_req.code,
             language: self.proto_to_language(proto::Language::try_from(exec_req.language).unwrap_or(proto::Language::Unspecified)),
+            timeout_seconds: exec_req.timeout.as_ref().map(|d| Self::duration_to_seconds("timeout", d)).transpose()?,
             args: Some(exec_req.args),
             environment: Some(exec_req.environment),
-            timeout_seconds: exec_req.timeout.map(|d| d.seconds as i32),
-            status: DbExecutionStatus::Pending,
-            exit_code: None,
-            stdout: None,
-            stderr: None,
-            created_at: chrono::Utc::now(),
-            started_at: None,
-            completed_at: None,
+            secrets: Some(exec_req.secrets).filter(|secrets| !secrets.is_empty()),
+            cpu_time_limit_seconds: None,
+            test_cases: None,
+            output_encoding: None,
+            priority: Some(priority),
+            not_before: exec_req.not_before.and_then(|t| {
+                chrono::DateTime::from_timestamp(t.seconds, t.nanos.max(0) as u32)
+            }),
+            idempotency_key: None,
+            cache: exec_req.cache.then_some(true),
+            bypass_cache: exec_req.bypass_cache.then_some(true),
+            callback_url: Some(exec_req.callback_url).filter(|url| !url.is_empty()),
         };
         
-        // Store execution
-        {
-            let mut executions = self.executions.write().await;
-            executions.insert(execution_id.to_string(), execution.clone());
+        // The owner comes from the token; the context is only honoured for
+        // privileged callers acting on behalf of someone else
+        let (user_id, workspace_id) = principal
+            .resolve_owner(
+                Some(context.user_id).filter(|id| !id.is_empty()),
+                Some(context.workspace_id).filter(|id| !id.is_empty()),
+            )
+            .map_err(IntoStatus::into_status)?;
+        if request.uses_secrets() {
+            principal
+                .check_secret_use(&user_id, workspace_id.as_deref())
+                .map_err(IntoStatus::into_status)?;
         }
         
-        // Queue for processing
-        self.queue.push_job(execution_id).await
-            .map_err(|e| Status::internal(format!("Failed to queue job: {}", e)))?;
+        let mut job = self.state
+            .submit_execution(request, user_id, workspace_id, idempotency_key)
+            .await
+            .map_err(IntoStatus::into_status)?;
+        if !req.r#async {
+            job = self.wait_for_completion(job).await?;
+        }
         
-        // If sync execution requested, wait for completion
-        let result = if !req.r#async {
-            // TODO: Implement sync execution with timeout
-            None
+        // Finished if sync, or served from the result cache either way
+        let result = if job.status.is_terminal() {
+            Self::to_proto_execution(&job).result
         } else {
             None
         };
         
         Ok(Response::new(proto::SubmitExecutionResponse {
-            execution_id: execution_id.to_string(),
-            status: proto::ExecutionStatus::Pending as i32,
+            execution_id: job.id.to_string(),
+            status: Self::status_to_proto(&DbExecutionStatus::from(&job.status)) as i32,
             result,
         }))
     }
@@ -191,14 +377,14 @@ impl proto::execution_service_server::ExecutionService for ExecutionServiceImpl
         &self,
         request: Request<proto::GetExecutionRequest>,
     ) -> Result<Response<proto::GetExecutionResponse>, Status> {
+        let principal = Self::principal(&request)?;
         let req = request.into_inner();
         
-        let executions = self.executions.read().await;
-        let execution = executions.get(&req.execution_id)
-            .ok_or_else(|| Status::not_found("Execution not found"))?;
+        let job = self.load_owned(&principal, &req.execution_id).await?;
+        let job = self.state.with_queue_position(job).await.map_err(IntoStatus::into_status)?;
         
         Ok(Response::new(proto::GetExecutionResponse {
-            execution: Some(self.to_proto_execution(execution)),
+            execution: Some(Self::to_proto_execution(&job)),
         }))
     }
     
@@ -206,104 +392,274 @@ impl proto::execution_service_server::ExecutionService for ExecutionServiceImpl
         &self,
         request: Request<proto::StreamExecutionRequest>,
     ) -> Result<Response<Self::StreamExecutionStream>, Status> {
-        // TODO: Implement streaming
-        Err(Status::unimplemented("Streaming not yet implemented"))
+        let principal = Self::principal(&request)?;
+        let req = request.into_inner();
+        let job = self.load_owned(&principal, &req.execution_id).await?;
+        
+        // Sequence numbers count every event, so resuming after an output
+        // event's sequence skips nothing else
+        let after = if req.last_sequence > 0 {
+            u64::from(req.last_sequence)
+        } else if req.from_start {
+            0
+        } else {
+            self.state.execution_event_count(job.id).await.map_err(IntoStatus::into_status)?
+        };
+        let events = ReceiverStream::new(events::follow(self.state.clone(), job.id, after))
+            .map(|event| Ok(Self::event_to_proto(event)));
+        Ok(Response::new(Box::pin(events)))
     }
     
-    type StreamExecutionStream = tonic::codec::Streaming<proto::ExecutionEvent>;
+    type StreamExecutionStream = Pin<Box<dyn Stream<Item = Result<proto::ExecutionEvent, Status>> + Send>>;
     
     async fn cancel_execution(
         &self,
         request: Request<proto::CancelExecutionRequest>,
     ) -> Result<Response<proto::CancelExecutionResponse>, Status> {
+        let principal = Self::principal(&request)?;
         let req = request.into_inner();
         
-        // TODO: Implement cancellation
-        let mut executions = self.executions.write().await;
-        if let Some(execution) = executions.get_mut(&req.execution_id) {
-            execution.status = DbExecutionStatus::Cancelled;
-            execution.completed_at = Some(chrono::Utc::now());
-            
-            Ok(Response::new(proto::CancelExecutionResponse {
-                success: true,
-                final_status: proto::ExecutionStatus::Cancelled as i32,
-            }))
-        } else {
-            Err(Status::not_found("Execution not found"))
-        }
+        let execution_id = self.load_owned(&principal, &req.execution_id).await?.id;
+        
+        let job = self.state.cancel_execution(execution_id).await.map_err(IntoStatus::into_status)?;
+        
+        Ok(Response::new(proto::CancelExecutionResponse {
+            success: matches!(job.status, JobStatus::Cancelled),
+            final_status: Self::status_to_proto(&DbExecutionStatus::from(&job.status)) as i32,
+        }))
     }
     
     async fn list_executions(
         &self,
         request: Request<proto::ListExecutionsRequest>,
     ) -> Result<Response<proto::ListExecutionsResponse>, Status> {
+        let principal = Self::principal(&request)?;
         let req = request.into_inner();
         
-        let executions = self.executions.read().await;
-        let mut results = Vec::new();
-        
-        for (_, execution) in executions.iter() {
-            // Filter by user_id if provided
-            if !req.user_id.is_empty() && execution.user_id != req.user_id {
-                continue;
-            }
-            
-            // Filter by workspace_id if provided
-            if !req.workspace_id.is_empty() {
-                if let Some(ws_id) = &execution.workspace_id {
-                    if ws_id != &req.workspace_id {
-                        continue;
-                    }
-                } else {
-                    continue;
-                }
-            }
-            
-            results.push(self.to_proto_execution(execution));
-        }
-        
-        // Apply pagination
-        let page = req.page.unwrap_or_default();
-        let page_size = page.size.max(10).min(100) as usize;
-        let page_num = page.number.max(1) as usize;
-        let start = (page_num - 1) * page_size;
-        let end = (start + page_size).min(results.len());
+        let timestamp = |t: Option<prost_types::Timestamp>| {
+            t.and_then(|t| chrono::DateTime::from_timestamp(t.seconds, t.nanos.max(0) as u32))
+        };
+        let language = proto::Language::try_from(req.language).unwrap_or(proto::Language::Unspecified);
+        let filter = ExecutionFilter {
+            user_id: principal
+                .resolve_list_filter(Some(req.user_id).filter(|id| !id.is_empty()))
+                .map_err(IntoStatus::into_status)?,
+            workspace_id: Some(req.workspace_id).filter(|id| !id.is_empty()),
+            status: Self::proto_to_status(req.status)?,
+            language: (language != proto::Language::Unspecified).then(|| self.proto_to_language(language)),
+            created_after: timestamp(req.created_after),
+            created_before: timestamp(req.created_before),
+        };
+        let page_size = pagination::page_size(req.page.map(|page| page.size as usize));
+        let page_token = Some(req.page_token).filter(|token| !token.is_empty());
         
-        let total = results.len() as u32;
-        let paginated_results = results[start..end].to_vec();
+        let page = self.state
+            .list_executions(&filter, page_size, page_token.as_deref())
+            .await
+            .map_err(IntoStatus::into_status)?;
         
         Ok(Response::new(proto::ListExecutionsResponse {
-            executions: paginated_results,
             page: Some(PageResponse {
-                total,
-                size: page_size as u32,
-                number: page_num as u32,
-                total_pages: (total as f32 / page_size as f32).ceil() as u32,
+                size: page.executions.len() as u32,
+                ..Default::default()
             }),
+            executions: page.executions
+                .iter()
+                .map(Self::to_proto_execution)
+                .collect(),
+            next_page_token: page.next_page_token.unwrap_or_default(),
         }))
     }
     
     async fn get_execution_metrics(
         &self,
-        request: Request<proto::GetExecutionMetricsRequest>,
+        _request: Request<proto::GetExecutionMetricsRequest>,
     ) -> Result<Response<proto::GetExecutionMetricsResponse>, Status> {
-        // TODO: Implement metrics collection
-        Err(Status::unimplemented("Metrics not yet implemented"))
+        // Resource usage isn't sampled, so there is nothing to aggregate
+        Err(Status::unimplemented("Execution metrics are not collected"))
+    }
+    
+    async fn get_quota_usage(
+        &self,
+        request: Request<proto::GetQuotaUsageRequest>,
+    ) -> Result<Response<proto::GetQuotaUsageResponse>, Status> {
+        let principal = Self::principal(&request)?;
+        let req = request.into_inner();
+        
+        let (user_id, workspace_id) = principal
+            .resolve_owner(
+                Some(req.user_id).filter(|id| !id.is_empty()),
+                Some(req.workspace_id).filter(|id| !id.is_empty()),
+            )
+            .map_err(IntoStatus::into_status)?;
+        let report = self.state
+            .quota_usage(&user_id, workspace_id.as_deref())
+            .await
+            .map_err(IntoStatus::into_status)?;
+        
+        Ok(Response::new(proto::GetQuotaUsageResponse {
+            user: Some(Self::quota_usage_to_proto(report.user)),
+            workspace: report.workspace.map(Self::quota_usage_to_proto),
+        }))
+    }
+    
+    async fn list_dead_letters(
+        &self,
+        request: Request<proto::ListDeadLettersRequest>,
+    ) -> Result<Response<proto::ListDeadLettersResponse>, Status> {
+        Self::principal(&request)?.require_admin().map_err(IntoStatus::into_status)?;
+        
+        let entries = self.state.dlq.list().await.map_err(IntoStatus::into_status)?;
+        Ok(Response::new(proto::ListDeadLettersResponse {
+            entries: entries.iter().map(|entry| self.dead_letter_to_proto(entry)).collect(),
+        }))
+    }
+    
+    async fn get_dead_letter(
+        &self,
+        request: Request<proto::GetDeadLetterRequest>,
+    ) -> Result<Response<proto::GetDeadLetterResponse>, Status> {
+        Self::principal(&request)?.require_admin().map_err(IntoStatus::into_status)?;
+        let id = Uuid::parse_str(&request.get_ref().id)
+            .map_err(|_| Status::invalid_argument("Invalid dead letter id"))?;
+        
+        let entry = self.state.dlq
+            .get(id)
+            .await
+            .map_err(IntoStatus::into_status)?
+            .ok_or_else(|| Status::not_found("Dead letter not found"))?;
+        Ok(Response::new(proto::GetDeadLetterResponse {
+            entry: Some(self.dead_letter_to_proto(&entry)),
+        }))
+    }
+    
+    async fn replay_dead_letters(
+        &self,
+        request: Request<proto::ReplayDeadLettersRequest>,
+    ) -> Result<Response<proto::ReplayDeadLettersResponse>, Status> {
+        Self::principal(&request)?.require_admin().map_err(IntoStatus::into_status)?;
+        let req = request.into_inner();
+        let selection = Self::parse_selection(req.ids, req.all)?;
+        
+        let summary = self.state
+            .replay_dead_letters(&selection)
+            .await
+            .map_err(IntoStatus::into_status)?;
+        Ok(Response::new(proto::ReplayDeadLettersResponse {
+            replayed: summary.replayed.iter().map(Uuid::to_string).collect(),
+            skipped: summary.skipped
+                .into_iter()
+                .map(|skipped| proto::SkippedDeadLetter {
+                    id: skipped.id.to_string(),
+                    reason: skipped.reason,
+                })
+                .collect(),
+        }))
+    }
+    
+    async fn purge_dead_letters(
+        &self,
+        request: Request<proto::PurgeDeadLettersRequest>,
+    ) -> Result<Response<proto::PurgeDeadLettersResponse>, Status> {
+        Self::principal(&request)?.require_admin().map_err(IntoStatus::into_status)?;
+        let req = request.into_inner();
+        let selection = Self::parse_selection(req.ids, req.all)?;
+        
+        let purged = self.state
+            .purge_dead_letters(&selection)
+            .await
+            .map_err(IntoStatus::into_status)?;
+        Ok(Response::new(proto::PurgeDeadLettersResponse { purged }))
     }
     
     async fn health_check(
         &self,
         _request: Request<HealthCheckRequest>,
     ) -> Result<Response<HealthCheckResponse>, Status> {
+        let report = health::readiness(&self.state).await;
+        let (status, message) = match report.status {
+            health::HealthStatus::Healthy => (HealthStatus::Healthy, "Execution service is healthy"),
+            health::HealthStatus::Degraded => (HealthStatus::Degraded, "Execution service is degraded"),
+            health::HealthStatus::Unhealthy => (HealthStatus::Unhealthy, "Execution service is unavailable"),
+        };
+
+        let mut metadata = HashMap::new();
+        metadata.insert("worker_status".to_string(), report.worker.as_str().to_string());
+        if let Some(depth) = report.queue_depth {
+            metadata.insert("queue_depth".to_string(), depth.to_string());
+        }
+        // `check.<name>` is the check's status, followed by its detail if any
+        for check in &report.checks {
+            let value = match &check.detail {
+                Some(detail) => format!("{}: {}", check.status.as_str(), detail),
+                None => check.status.as_str().to_string(),
+            };
+            metadata.insert(format!("check.{}", check.name), value);
+        }
+
+        let uptime = self.state.lifecycle.uptime();
         Ok(Response::new(HealthCheckResponse {
-            status: HealthStatus::Healthy as i32,
-            message: "Execution service is healthy".to_string(),
-            version: env!("CARGO_PKG_VERSION").to_string(),
+            status: status as i32,
+            message: message.to_string(),
+            version: report.version,
             uptime: Some(prost_types::Duration {
-                seconds: 0, // TODO: Track uptime
-                nanos: 0,
+                seconds: uptime.as_secs() as i64,
+                nanos: uptime.subsec_nanos() as i32,
             }),
-            metadata: std::collections::HashMap::new(),
+            metadata,
         }))
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::QueuePosition;
+
+    #[test]
+    fn executions_carry_the_cache_hit_and_queue_position() {
+        let request = serde_json::from_value(serde_json::json!({"code": "", "language": "python"})).unwrap();
+        let mut job = ExecutionJob::new(request);
+        let execution = ExecutionServiceImpl::to_proto_execution(&job);
+        assert!(!execution.cache_hit);
+        assert_eq!((execution.queue_position, execution.estimated_wait), (0, None));
+
+        job.cache_hit = true;
+        job.queue = Some(QueuePosition { position: 3, estimated_wait_ms: 4_500 });
+        let execution = ExecutionServiceImpl::to_proto_execution(&job);
+        assert!(execution.cache_hit);
+        assert_eq!(execution.queue_position, 3);
+        assert_eq!(execution.estimated_wait, Some(prost_types::Duration { seconds: 4, nanos: 500_000_000 }));
+    }
+
+    #[test]
+    fn output_events_carry_their_sequence() {
+        let mut event = ExecutionEvent::new(
+            Uuid::nil(),
+            EventKind::Output { stream: OutputStream::Stderr, data: "oops".to_string() },
+        );
+        event.sequence = 7;
+        match ExecutionServiceImpl::event_to_proto(event).event {
+            Some(proto::execution_event::Event::Output(output)) => {
+                assert_eq!(output.r#type, proto::OutputType::Stderr as i32);
+                assert_eq!((output.data.as_str(), output.sequence), ("oops", 7));
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
+    }
+
+    #[test]
+    fn status_changes_map_both_statuses() {
+        let event = ExecutionEvent::new(
+            Uuid::nil(),
+            EventKind::StatusChange { old_status: None, new_status: JobStatus::Timeout, message: String::new() },
+        );
+        match ExecutionServiceImpl::event_to_proto(event).event {
+            Some(proto::execution_event::Event::StatusChange(change)) => {
+                assert_eq!(change.old_status, proto::ExecutionStatus::Unspecified as i32);
+                assert_eq!(change.new_status, proto::ExecutionStatus::Timeout as i32);
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
+    }
+}
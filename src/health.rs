@@ -0,0 +1,304 @@
+use crate::grpc::WorkerStatus;
+use crate::lifecycle::Lifecycle;
+use crate::state::ServiceState;
+use serde::Serialize;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tonic_health::server::HealthReporter;
+use tonic_health::ServingStatus;
+use tracing::warn;
+use utoipa::ToSchema;
+
+// Longest a single dependency check may take before it counts as failed
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+// How often the grpc.health.v1 status is refreshed
+const REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+// Services whose grpc.health.v1 status follows readiness; "" is the server
+// as a whole
+const REPORTED_SERVICES: &[&str] = &["", "syla.execution.v1.ExecutionService"];
+
+/// Overall verdict. Degraded instances still take traffic; unhealthy ones
+/// should be taken out of rotation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    Healthy,
+    Degraded,
+    Unhealthy,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Ok,
+    // Working, but not as well as it should
+    Degraded,
+    Failed,
+    // Not configured on this instance
+    Disabled,
+}
+
+impl CheckStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CheckStatus::Ok => "ok",
+            CheckStatus::Degraded => "degraded",
+            CheckStatus::Failed => "failed",
+            CheckStatus::Disabled => "disabled",
+        }
+    }
+}
+
+/// Outcome of checking one dependency.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct DependencyCheck {
+    pub name: String,
+    pub status: CheckStatus,
+    pub latency_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+/// What the worker is doing, from `WorkerStatus`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum WorkerState {
+    Ready,
+    Busy,
+    Draining,
+    Offline,
+}
+
+impl From<WorkerStatus> for WorkerState {
+    fn from(status: WorkerStatus) -> Self {
+        match status {
+            WorkerStatus::Busy => WorkerState::Busy,
+            WorkerStatus::Draining => WorkerState::Draining,
+            WorkerStatus::Offline => WorkerState::Offline,
+            WorkerStatus::Ready | WorkerStatus::Unspecified => WorkerState::Ready,
+        }
+    }
+}
+
+impl WorkerState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WorkerState::Ready => "ready",
+            WorkerState::Busy => "busy",
+            WorkerState::Draining => "draining",
+            WorkerState::Offline => "offline",
+        }
+    }
+}
+
+/// The process is up; says nothing about its dependencies.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Liveness {
+    pub version: String,
+    pub uptime_seconds: u64,
+    pub worker: WorkerState,
+}
+
+/// Whether this instance can take executions, and why not.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Readiness {
+    pub status: HealthStatus,
+    pub version: String,
+    pub uptime_seconds: u64,
+    pub worker: WorkerState,
+    // Unknown when Redis can't be reached
+    pub queue_depth: Option<u64>,
+    pub checks: Vec<DependencyCheck>,
+}
+
+impl Readiness {
+    pub fn is_ready(&self) -> bool {
+        self.status != HealthStatus::Unhealthy
+    }
+}
+
+pub fn liveness(lifecycle: &Lifecycle) -> Liveness {
+    Liveness {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        uptime_seconds: lifecycle.uptime().as_secs(),
+        worker: lifecycle.status().into(),
+    }
+}
+
+/// Check every dependency an execution needs. Redis, the database (when
+/// configured) and the Docker daemon are required; missing language images
+/// only degrade the instance, since they are pulled on first use.
+pub async fn readiness(state: &ServiceState) -> Readiness {
+    let (redis, database, runtime, queue_depth) = tokio::join!(
+        check("redis", async {
+            let mut conn = state.redis.lock().await.clone();
+            redis::cmd("PING").query_async::<_, String>(&mut conn).await?;
+            Ok((CheckStatus::Ok, None))
+        }),
+        check_database(state),
+        check("runtime", async {
+            let runtime = state.executor.runtime_status().await?;
+            if runtime.missing_images.is_empty() {
+                Ok((CheckStatus::Ok, Some(format!("Docker {}", runtime.version))))
+            } else {
+                Ok((
+                    CheckStatus::Degraded,
+                    Some(format!("Images not pulled: {}", runtime.missing_images.join(", "))),
+                ))
+            }
+        }),
+        tokio::time::timeout(CHECK_TIMEOUT, state.queue.depth()),
+    );
+    let checks = vec![redis, database, runtime];
+
+    let worker = WorkerState::from(state.lifecycle.status());
+    Readiness {
+        status: verdict(&checks, worker),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        uptime_seconds: state.lifecycle.uptime().as_secs(),
+        worker,
+        queue_depth: queue_depth.ok().and_then(Result::ok),
+        checks,
+    }
+}
+
+// A failed dependency or a stopping worker takes the instance out of rotation
+fn verdict(checks: &[DependencyCheck], worker: WorkerState) -> HealthStatus {
+    if checks.iter().any(|check| check.status == CheckStatus::Failed)
+        || matches!(worker, WorkerState::Draining | WorkerState::Offline)
+    {
+        HealthStatus::Unhealthy
+    } else if checks.iter().any(|check| check.status == CheckStatus::Degraded) {
+        HealthStatus::Degraded
+    } else {
+        HealthStatus::Healthy
+    }
+}
+
+async fn check_database(state: &ServiceState) -> DependencyCheck {
+    match &state.archive {
+        Some(archive) => {
+            check("database", async {
+                archive.ping().await?;
+                Ok((CheckStatus::Ok, None))
+            })
+            .await
+        }
+        None => DependencyCheck {
+            name: "database".to_string(),
+            status: CheckStatus::Disabled,
+            latency_ms: 0,
+            detail: None,
+        },
+    }
+}
+
+// Run one check under the timeout; errors and timeouts count as failed
+async fn check<F>(name: &str, probe: F) -> DependencyCheck
+where
+    F: Future<Output = anyhow::Result<(CheckStatus, Option<String>)>>,
+{
+    let started = Instant::now();
+    let (status, detail) = match tokio::time::timeout(CHECK_TIMEOUT, probe).await {
+        Ok(Ok(outcome)) => outcome,
+        Ok(Err(e)) => (CheckStatus::Failed, Some(e.to_string())),
+        Err(_) => (
+            CheckStatus::Failed,
+            Some(format!("No answer within {}s", CHECK_TIMEOUT.as_secs())),
+        ),
+    };
+    if status == CheckStatus::Failed {
+        warn!("Health check {} failed: {}", name, detail.as_deref().unwrap_or_default());
+    }
+    DependencyCheck {
+        name: name.to_string(),
+        status,
+        latency_ms: started.elapsed().as_millis() as u64,
+        detail,
+    }
+}
+
+/// Keep the grpc.health.v1 status in line with readiness until the
+/// instance starts draining, which flips it to not serving straight away.
+pub async fn run_reporter(state: Arc<ServiceState>, mut reporter: HealthReporter) {
+    loop {
+        let serving = if readiness(&state).await.is_ready() {
+            ServingStatus::Serving
+        } else {
+            ServingStatus::NotServing
+        };
+        for service in REPORTED_SERVICES {
+            reporter.set_service_status(*service, serving).await;
+        }
+        if state.lifecycle.is_draining() {
+            return;
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(REPORT_INTERVAL) => {}
+            _ = state.lifecycle.draining() => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn probe(status: CheckStatus) -> DependencyCheck {
+        check("redis", async move { Ok((status, None)) }).await
+    }
+
+    #[tokio::test]
+    async fn failing_probes_count_as_failed() {
+        let failed = check("redis", async { Err(anyhow::anyhow!("Connection refused")) }).await;
+        assert_eq!(failed.status, CheckStatus::Failed);
+        assert_eq!(failed.detail.as_deref(), Some("Connection refused"));
+        assert_eq!(probe(CheckStatus::Ok).await.status, CheckStatus::Ok);
+    }
+
+    #[tokio::test]
+    async fn a_dependency_down_is_not_ready_but_still_live() {
+        let lifecycle = Lifecycle::new();
+        let checks = vec![
+            check("redis", async { Err(anyhow::anyhow!("Connection refused")) }).await,
+            probe(CheckStatus::Ok).await,
+        ];
+        let worker = WorkerState::from(lifecycle.status());
+        assert_eq!(verdict(&checks, worker), HealthStatus::Unhealthy);
+
+        // Liveness doesn't look at dependencies at all
+        let live = liveness(&lifecycle);
+        assert_eq!(live.worker, WorkerState::Ready);
+    }
+
+    #[tokio::test]
+    async fn missing_images_only_degrade() {
+        let checks = vec![probe(CheckStatus::Ok).await, probe(CheckStatus::Degraded).await, probe(CheckStatus::Disabled).await];
+        assert_eq!(verdict(&checks, WorkerState::Busy), HealthStatus::Degraded);
+        assert_eq!(verdict(&checks[..1], WorkerState::Ready), HealthStatus::Healthy);
+    }
+
+    #[tokio::test]
+    async fn draining_instances_are_not_ready() {
+        let checks = vec![probe(CheckStatus::Ok).await];
+        assert_eq!(verdict(&checks, WorkerState::Draining), HealthStatus::Unhealthy);
+        assert_eq!(verdict(&checks, WorkerState::Offline), HealthStatus::Unhealthy);
+    }
+
+    #[test]
+    fn readiness_follows_the_verdict() {
+        let readiness = |status| Readiness {
+            status,
+            version: String::new(),
+            uptime_seconds: 0,
+            worker: WorkerState::Ready,
+            queue_depth: None,
+            checks: vec![],
+        };
+        assert!(readiness(HealthStatus::Healthy).is_ready());
+        assert!(readiness(HealthStatus::Degraded).is_ready());
+        assert!(!readiness(HealthStatus::Unhealthy).is_ready());
+    }
+}
@@ -0,0 +1,137 @@
+use crate::error::ServiceError;
+use crate::models::CreateExecutionRequest;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+const DEFAULT_TTL_SECONDS: u64 = 24 * 60 * 60;
+const MAX_KEY_LENGTH: usize = 255;
+
+pub const IDEMPOTENCY_HEADER: &str = "idempotency-key";
+
+/// How long an idempotency key is remembered after first use.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IdempotencyPolicy {
+    pub ttl_seconds: u64,
+}
+
+impl Default for IdempotencyPolicy {
+    fn default() -> Self {
+        Self {
+            ttl_seconds: DEFAULT_TTL_SECONDS,
+        }
+    }
+}
+
+/// What a key maps to. `execution_id` is unset while the first request
+/// holding the key is still creating its execution.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IdempotencyRecord {
+    pub payload_hash: String,
+    pub execution_id: Option<Uuid>,
+}
+
+impl IdempotencyRecord {
+    /// The execution to answer a repeat with. Reusing a key for a different
+    /// payload, or before the first request has finished, is a conflict.
+    pub fn reuse(&self, payload_hash: &str) -> Result<Uuid, ServiceError> {
+        if self.payload_hash != payload_hash {
+            return Err(ServiceError::Conflict(
+                "Idempotency key was already used with a different request".to_string(),
+            ));
+        }
+        self.execution_id.ok_or_else(|| {
+            ServiceError::Conflict("A request with this idempotency key is still in progress".to_string())
+        })
+    }
+}
+
+/// Keys are scoped to the submitting user so tenants can't collide.
+pub fn record_key(user_id: &str, key: &str) -> String {
+    format!("idem:{}:{}", user_id, key)
+}
+
+pub fn validate_key(key: &str) -> Result<(), String> {
+    if key.is_empty() || key.len() > MAX_KEY_LENGTH {
+        return Err(format!("Idempotency key must be 1 to {} characters", MAX_KEY_LENGTH));
+    }
+    if key.chars().any(|c| c.is_control() || c.is_whitespace()) {
+        return Err("Idempotency key must not contain whitespace".to_string());
+    }
+    Ok(())
+}
+
+/// Hash of what was submitted and where. serde_json sorts object keys, so
+/// the environment map hashes the same regardless of order.
+pub fn payload_hash(request: &CreateExecutionRequest, workspace_id: Option<&str>) -> Result<String, serde_json::Error> {
+    let payload = serde_json::json!({
+        "request": serde_json::to_value(request)?,
+        "workspace_id": workspace_id,
+    });
+    Ok(format!("{:x}", Sha256::digest(payload.to_string().as_bytes())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(json: serde_json::Value) -> CreateExecutionRequest {
+        serde_json::from_value(json).unwrap()
+    }
+
+    fn hash(json: serde_json::Value, workspace_id: Option<&str>) -> String {
+        payload_hash(&request(json), workspace_id).unwrap()
+    }
+
+    #[test]
+    fn the_same_request_gets_the_original_execution() {
+        let original = Uuid::new_v4();
+        let record = IdempotencyRecord {
+            payload_hash: hash(serde_json::json!({"code": "print(1)", "language": "python"}), Some("ws")),
+            execution_id: Some(original),
+        };
+
+        let repeat = hash(serde_json::json!({"language": "python", "code": "print(1)"}), Some("ws"));
+        assert_eq!(record.reuse(&repeat).unwrap(), original);
+    }
+
+    #[test]
+    fn a_different_request_with_the_key_is_a_conflict() {
+        let record = IdempotencyRecord {
+            payload_hash: hash(serde_json::json!({"code": "print(1)", "language": "python"}), Some("ws")),
+            execution_id: Some(Uuid::new_v4()),
+        };
+
+        for other in [
+            hash(serde_json::json!({"code": "print(2)", "language": "python"}), Some("ws")),
+            hash(serde_json::json!({"code": "print(1)", "language": "python"}), Some("other")),
+            hash(serde_json::json!({"code": "print(1)", "language": "python"}), None),
+        ] {
+            assert!(matches!(record.reuse(&other), Err(ServiceError::Conflict(_))));
+        }
+    }
+
+    #[test]
+    fn a_repeat_while_the_first_is_in_flight_is_a_conflict() {
+        let payload_hash = hash(serde_json::json!({"code": "print(1)", "language": "python"}), None);
+        let record = IdempotencyRecord { payload_hash: payload_hash.clone(), execution_id: None };
+        assert!(matches!(record.reuse(&payload_hash), Err(ServiceError::Conflict(_))));
+    }
+
+    #[test]
+    fn environment_order_does_not_change_the_hash() {
+        assert_eq!(
+            hash(serde_json::json!({"code": "", "language": "python", "environment": {"A": "1", "B": "2"}}), None),
+            hash(serde_json::json!({"code": "", "language": "python", "environment": {"B": "2", "A": "1"}}), None),
+        );
+    }
+
+    #[test]
+    fn keys_must_be_short_and_printable() {
+        assert!(validate_key("order-42").is_ok());
+        assert!(validate_key("").is_err());
+        assert!(validate_key("two words").is_err());
+        assert!(validate_key(&"k".repeat(MAX_KEY_LENGTH + 1)).is_err());
+    }
+}
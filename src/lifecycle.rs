@@ -0,0 +1,187 @@
+use crate::grpc::WorkerStatus;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+use tracing::info;
+
+const DEFAULT_DRAIN_TIMEOUT_SECONDS: u64 = 30;
+const DEFAULT_LISTENER_GRACE_SECONDS: u64 = 5;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ShutdownPolicy {
+    // How long a running execution may take to finish once draining starts
+    pub drain_timeout_seconds: u64,
+    // How long open connections (event streams in particular) get once the
+    // listeners stop accepting
+    pub listener_grace_seconds: u64,
+}
+
+impl Default for ShutdownPolicy {
+    fn default() -> Self {
+        Self {
+            drain_timeout_seconds: DEFAULT_DRAIN_TIMEOUT_SECONDS,
+            listener_grace_seconds: DEFAULT_LISTENER_GRACE_SECONDS,
+        }
+    }
+}
+
+/// Where this instance is between starting and exiting. Ready and busy
+/// instances take submissions and run jobs; once draining starts they do
+/// neither, and they go offline when the last job has been dealt with.
+pub struct Lifecycle {
+    status: watch::Sender<WorkerStatus>,
+    started_at: Instant,
+}
+
+impl Lifecycle {
+    pub fn new() -> Self {
+        Self {
+            status: watch::Sender::new(WorkerStatus::Ready),
+            started_at: Instant::now(),
+        }
+    }
+
+    pub fn uptime(&self) -> Duration {
+        self.started_at.elapsed()
+    }
+
+    pub fn status(&self) -> WorkerStatus {
+        *self.status.borrow()
+    }
+
+    /// Whether new work should be turned away.
+    pub fn is_draining(&self) -> bool {
+        is_stopping(self.status())
+    }
+
+    /// Mark the worker as running a job or idle. Ignored once draining.
+    pub fn set_busy(&self, busy: bool) {
+        self.status.send_if_modified(|status| {
+            if is_stopping(*status) {
+                return false;
+            }
+            let next = if busy { WorkerStatus::Busy } else { WorkerStatus::Ready };
+            std::mem::replace(status, next) != next
+        });
+    }
+
+    pub fn start_draining(&self) {
+        let started = self.status.send_if_modified(|status| {
+            if is_stopping(*status) {
+                return false;
+            }
+            *status = WorkerStatus::Draining;
+            true
+        });
+        if started {
+            info!("Draining: no longer accepting submissions or starting jobs");
+        }
+    }
+
+    pub fn go_offline(&self) {
+        self.status.send_replace(WorkerStatus::Offline);
+    }
+
+    /// Resolves once draining has started.
+    pub async fn draining(&self) {
+        let mut status = self.status.subscribe();
+        // The sender lives as long as `self`, so this can't fail
+        let _ = status.wait_for(|status| is_stopping(*status)).await;
+    }
+
+    /// Resolves once the instance has gone offline.
+    pub async fn offline(&self) {
+        let mut status = self.status.subscribe();
+        let _ = status.wait_for(|status| *status == WorkerStatus::Offline).await;
+    }
+}
+
+fn is_stopping(status: WorkerStatus) -> bool {
+    matches!(status, WorkerStatus::Draining | WorkerStatus::Offline)
+}
+
+/// Resolves on SIGTERM or Ctrl-C.
+pub async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!("Failed to listen for Ctrl-C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                tracing::error!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => info!("Received Ctrl-C"),
+        _ = terminate => info!("Received SIGTERM"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn busy_and_idle_until_draining() {
+        let lifecycle = Lifecycle::new();
+        assert_eq!(lifecycle.status(), WorkerStatus::Ready);
+        lifecycle.set_busy(true);
+        assert_eq!(lifecycle.status(), WorkerStatus::Busy);
+        lifecycle.set_busy(false);
+        assert_eq!(lifecycle.status(), WorkerStatus::Ready);
+        assert!(!lifecycle.is_draining());
+
+        lifecycle.set_busy(true);
+        lifecycle.start_draining();
+        assert_eq!(lifecycle.status(), WorkerStatus::Draining);
+        assert!(lifecycle.is_draining());
+
+        // The job in hand finishing doesn't make the worker ready again
+        lifecycle.set_busy(false);
+        assert_eq!(lifecycle.status(), WorkerStatus::Draining);
+
+        lifecycle.go_offline();
+        lifecycle.start_draining();
+        assert_eq!(lifecycle.status(), WorkerStatus::Offline);
+        assert!(lifecycle.is_draining());
+    }
+
+    #[tokio::test]
+    async fn waiters_wake_when_draining_starts() {
+        let lifecycle = std::sync::Arc::new(Lifecycle::new());
+        let draining = tokio::spawn({
+            let lifecycle = lifecycle.clone();
+            async move { lifecycle.draining().await }
+        });
+        let offline = tokio::spawn({
+            let lifecycle = lifecycle.clone();
+            async move { lifecycle.offline().await }
+        });
+        tokio::task::yield_now().await;
+        assert!(!draining.is_finished());
+
+        lifecycle.start_draining();
+        tokio::time::timeout(Duration::from_secs(1), draining).await.unwrap().unwrap();
+        assert!(!offline.is_finished());
+
+        lifecycle.go_offline();
+        tokio::time::timeout(Duration::from_secs(1), offline).await.unwrap().unwrap();
+
+        // Already draining: resolves straight away
+        tokio::time::timeout(Duration::from_secs(1), lifecycle.draining()).await.unwrap();
+    }
+}
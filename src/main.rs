@@ -1,31 +1,97 @@
 use anyhow::Result;
 use axum::{
-    extract::{Path, State},
-    routing::{get, post},
-    Json, Router,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    middleware,
+    response::sse::{Event, KeepAlive, Sse},
+    routing::{delete, get, post, put},
+    Extension, Json, Router,
 };
 use redis::aio::ConnectionManager;
-use std::net::SocketAddr;
+use std::convert::Infallible;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::Mutex;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::{Stream, StreamExt};
 use tower_http::trace::TraceLayer;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use utoipa::IntoParams;
 use uuid::Uuid;
 
+mod archive;
+mod auth;
+mod batch;
+mod cache;
+mod config;
+mod dlq;
 mod docker;
 mod error;
+mod events;
 mod executor;
+mod extract;
 mod grpc;
+mod health;
+mod idempotency;
+mod lifecycle;
 mod models;
+mod openapi;
+mod pagination;
 mod queue;
+mod quota;
+mod reaper;
+mod request_id;
+mod retention;
+mod retry;
+mod scheduler;
+mod secrets;
 mod state;
+mod validation;
+mod webhook;
 mod worker;
 
-use error::ServiceError;
+use auth::Principal;
+use config::ServiceConfig;
+use error::{ErrorBody, ServiceError};
+use extract::{ApiJson, ApiPath, ApiQuery};
 use state::ServiceState;
 
+// `--config <path>` names the config file; `--check-config` validates the
+// configuration, prints it and exits
+struct Args {
+    config: Option<PathBuf>,
+    check_config: bool,
+}
+
+fn parse_args() -> Result<Args> {
+    let mut args = Args {
+        config: std::env::var_os(config::CONFIG_FILE_ENV).map(PathBuf::from),
+        check_config: false,
+    };
+    let mut argv = std::env::args().skip(1);
+    while let Some(arg) = argv.next() {
+        match arg.as_str() {
+            "--config" => match argv.next() {
+                Some(path) => args.config = Some(PathBuf::from(path)),
+                None => anyhow::bail!("--config requires a path"),
+            },
+            "--check-config" => args.check_config = true,
+            other => anyhow::bail!("Unknown argument `{}`; expected --config <path> or --check-config", other),
+        }
+    }
+    Ok(args)
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
+    let args = parse_args()?;
+    let config = ServiceConfig::load(args.config.as_deref())?;
+    if args.check_config {
+        print!("{}", config.to_toml()?);
+        return Ok(());
+    }
+
     // Initialize tracing
     tracing_subscriber::registry()
         .with(
@@ -35,81 +101,829 @@ async fn main() -> Result<()> {
         .with(tracing_subscriber::fmt::layer())
         .init();
 
+    match &args.config {
+        Some(path) => tracing::info!("Loaded configuration from {}", path.display()),
+        None => tracing::info!("No config file given; using defaults and environment"),
+    }
+
     // Connect to Redis
-    let redis_url = std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1:6380/".to_string());
-    let redis_client = redis::Client::open(redis_url)?;
+    let redis_client = redis::Client::open(config.redis.url.as_str())?;
     let redis_conn = ConnectionManager::new(redis_client).await?;
 
     // Initialize components
-    let redis_queue = Arc::new(queue::RedisQueue::new(redis_conn.clone()));
-    let docker_executor = Arc::new(executor::DockerExecutor::new().await?);
+    let executor = match config.runtime.backend {
+        config::RuntimeBackend::Docker => Arc::new(executor::DockerExecutor::new(&config.runtime).await?),
+    };
 
-    // Initialize state for REST API
+    // Finished executions are archived only when a database is configured
+    let archive = match &config.database.url {
+        Some(url) => Some(archive::ExecutionArchive::connect(url, config.database.max_connections).await?),
+        None => {
+            tracing::warn!("No database configured; finished executions will not be archived");
+            None
+        }
+    };
+    
+    let (webhooks, webhook_jobs) = webhook::WebhookNotifier::new(config.webhooks.clone());
+
+    // Shared state for the REST and gRPC APIs
     let state = Arc::new(ServiceState {
-        redis: Arc::new(Mutex::new(redis_conn)),
-        docker_executor: Arc::new(docker::DockerExecutor::new()?),
+        redis: Arc::new(Mutex::new(redis_conn.clone())),
+        executor,
+        queue: queue::RedisQueue::new(redis_conn.clone(), config.queue),
+        dlq: dlq::DeadLetterQueue::new(redis_conn.clone()),
+        retention: config.retention.clone(),
+        quotas: config.quotas.clone(),
+        retry: config.retry.clone(),
+        idempotency: config.idempotency.clone(),
+        validation: config.limits.clone(),
+        cache: cache::ResultCache::new(config.cache.clone()),
+        webhooks,
+        secrets: secrets::SecretCipher::new(config.secrets.encryption_key.as_ref().map(config::Sensitive::expose))?,
+        archive,
+        lifecycle: lifecycle::Lifecycle::new(),
     });
 
+    // Shared by the gRPC layer and the REST middleware
+    let authenticator = Arc::new(auth::Authenticator::new(config.auth.clone()).await?);
+
+    // Nothing is running yet, so any workspace on disk is from a previous run
+    reaper::cleanup_workspaces(&config.runtime.workspace_root);
+    
+    // Start container reaper
+    let reaper_state = state.clone();
+    let reaper_interval = config.runtime.reaper_interval_seconds;
+    tokio::spawn(async move {
+        reaper::run_reaper(reaper_state, reaper_interval).await;
+    });
+    
+    // Start retention sweeper
+    let sweeper_state = state.clone();
+    tokio::spawn(async move {
+        retention::run_sweeper(sweeper_state).await;
+    });
+    
+    // Start scheduler for delayed and recurring executions
+    let scheduler_state = state.clone();
+    tokio::spawn(async move {
+        scheduler::run_scheduler(scheduler_state).await;
+    });
+    
+    // Start webhook dispatcher
+    let dispatcher_state = state.clone();
+    tokio::spawn(async move {
+        webhook::run_dispatcher(dispatcher_state, webhook_jobs).await;
+    });
+    
     // Start worker task
     let worker_state = state.clone();
-    tokio::spawn(async move {
-        worker::run_worker(worker_state).await;
+    let drain_timeout = Duration::from_secs(config.shutdown.drain_timeout_seconds);
+    let worker = tokio::spawn(async move {
+        worker::run_worker(worker_state, drain_timeout).await;
     });
 
-    // Start gRPC server
-    let grpc_queue = redis_queue.clone();
-    let grpc_executor = docker_executor.clone();
+    // Start gRPC server, with grpc.health.v1 following readiness
+    let (health_reporter, health_service) = tonic_health::server::health_reporter();
+    let reporter_state = state.clone();
     tokio::spawn(async move {
-        let addr = "0.0.0.0:8081".parse().unwrap();
+        health::run_reporter(reporter_state, health_reporter).await;
+    });
+
+    let grpc_state = state.clone();
+    let grpc_auth = grpc::AuthInterceptor::new(authenticator.clone());
+    let addr = config.server.grpc_addr;
+    let grpc = tokio::spawn(async move {
         tracing::info!("Starting gRPC server on {}", addr);
         
-        let service = grpc::server::ExecutionServiceImpl::new(grpc_queue, grpc_executor);
+        let service = grpc::server::ExecutionServiceImpl::new(grpc_state.clone());
         
         tonic::transport::Server::builder()
+            .layer(grpc_auth)
+            .add_service(health_service)
             .add_service(grpc::proto::syla::execution::v1::execution_service_server::ExecutionServiceServer::new(service))
-            .serve(addr)
+            .serve_with_shutdown(addr, async move { grpc_state.lifecycle.offline().await })
             .await
             .expect("gRPC server failed");
     });
 
-    // Build REST router
+    // Build REST router; everything except the health checks and the API docs requires
+    // a valid token. The API lives under /v1; the unversioned paths predate it
+    // and stay as aliases until clients have moved over.
+    let api = Router::new()
+        .route("/executions", post(create_execution).get(list_executions))
+        .route("/executions/batch", post(create_batch_execution))
+        .route("/executions/:id", get(get_execution).delete(cancel_execution))
+        .route("/executions/:id/events", get(stream_execution_events))
+        .route("/schedules", post(create_schedule).get(list_schedules))
+        .route("/schedules/:id", delete(delete_schedule))
+        .route("/quotas/usage", get(get_quota_usage))
+        .route("/webhooks", post(create_webhook).get(list_webhooks))
+        .route("/webhooks/:id", delete(delete_webhook))
+        .route("/secrets", get(list_secrets))
+        .route("/secrets/:name", put(put_secret).delete(delete_secret))
+        .route("/admin/users/:user_id/executions", delete(purge_user_executions))
+        .route("/admin/dlq", get(list_dead_letters))
+        .route("/admin/dlq/replay", post(replay_dead_letters))
+        .route("/admin/dlq/purge", post(purge_dead_letters))
+        .route("/admin/dlq/:id", get(get_dead_letter).delete(delete_dead_letter))
+        .route("/admin/dlq/:id/replay", post(replay_dead_letter))
+        .route_layer(middleware::from_fn_with_state(authenticator, auth::require_auth));
+    
     let app = Router::new()
         .route("/health", get(health_handler))
-        .route("/executions", post(create_execution))
-        .route("/executions/:id", get(get_execution))
-        .layer(TraceLayer::new_for_http())
-        .with_state(state);
+        .route("/health/live", get(liveness_handler))
+        .route("/health/ready", get(readiness_handler))
+        .route("/openapi.json", get(openapi::openapi_json))
+        .route("/docs", get(openapi::docs))
+        .nest("/v1", api.clone())
+        .merge(api)
+        .fallback(not_found)
+        .layer(TraceLayer::new_for_http().make_span_with(|request: &axum::http::Request<_>| {
+            let request_id = request
+                .headers()
+                .get(request_id::REQUEST_ID_HEADER)
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or_default();
+            tracing::info_span!("request", method = %request.method(), uri = %request.uri(), request_id)
+        }))
+        .layer(middleware::from_fn(request_id::propagate))
+        .with_state(state.clone());
 
     // Start REST server
-    let port = std::env::var("PORT")
-        .unwrap_or_else(|_| "8083".to_string())
-        .parse::<u16>()
-        .expect("Invalid PORT");
-    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+    let addr = config.server.rest_addr;
     tracing::info!("Starting REST API on {}", addr);
 
     let listener = tokio::net::TcpListener::bind(addr).await?;
-    axum::serve(listener, app).await?;
+    let rest_state = state.clone();
+    let mut rest = tokio::spawn(async move {
+        axum::serve(listener, app)
+            .with_graceful_shutdown(async move { rest_state.lifecycle.offline().await })
+            .await
+    });
+
+    tokio::select! {
+        _ = lifecycle::shutdown_signal() => {}
+        result = &mut rest => return Ok(result??),
+    }
+
+    // Drain: submissions are refused and no new jobs start while the running
+    // one gets the drain timeout. The listeners stay up meanwhile so clients
+    // can follow executions to the end.
+    state.lifecycle.start_draining();
+    if let Err(e) = worker.await {
+        tracing::error!("Worker task failed: {}", e);
+    }
+    state.lifecycle.go_offline();
+
+    tracing::info!("Drained; closing listeners");
+    let grace = Duration::from_secs(config.shutdown.listener_grace_seconds);
+    if tokio::time::timeout(grace, async { tokio::join!(rest, grpc) }).await.is_err() {
+        tracing::warn!("Connections still open after {}s; exiting anyway", grace.as_secs());
+    }
 
     Ok(())
 }
 
-async fn health_handler() -> &'static str {
-    "OK"
+// Older probes point here; it answers like /health/live
+#[utoipa::path(
+    get, path = "/health", tag = "health", security(()),
+    responses((status = 200, description = "Process is up; alias of /health/live", body = health::Liveness))
+)]
+async fn health_handler(State(state): State<Arc<ServiceState>>) -> Json<health::Liveness> {
+    Json(health::liveness(&state.lifecycle))
+}
+
+#[utoipa::path(
+    get, path = "/health/live", tag = "health", security(()),
+    responses((status = 200, description = "Process is up", body = health::Liveness))
+)]
+async fn liveness_handler(State(state): State<Arc<ServiceState>>) -> Json<health::Liveness> {
+    Json(health::liveness(&state.lifecycle))
+}
+
+#[utoipa::path(
+    get, path = "/health/ready", tag = "health", security(()),
+    responses(
+        (status = 200, description = "Ready for executions, possibly degraded", body = health::Readiness),
+        (status = 503, description = "A required dependency is down or the service is draining", body = health::Readiness),
+    )
+)]
+async fn readiness_handler(State(state): State<Arc<ServiceState>>) -> (StatusCode, Json<health::Readiness>) {
+    let report = health::readiness(&state).await;
+    let status = if report.is_ready() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(report))
+}
+
+// Unknown routes get the same error body as everything else
+async fn not_found() -> ServiceError {
+    ServiceError::NotFound
 }
 
+// The header wins over the body field when both are given
+fn idempotency_key(headers: &HeaderMap, body: Option<String>) -> Result<Option<String>, ServiceError> {
+    match headers.get(idempotency::IDEMPOTENCY_HEADER) {
+        Some(value) => value
+            .to_str()
+            .map(|key| Some(key.to_string()))
+            .map_err(|_| ServiceError::InvalidRequest("Idempotency-Key must be ASCII".to_string())),
+        None => Ok(body),
+    }
+}
+
+#[utoipa::path(
+    post, path = "/v1/executions", tag = "executions",
+    params(("Idempotency-Key" = Option<String>, Header, description = "Resubmitting with the same key returns the original execution")),
+    request_body = models::CreateExecutionRequest,
+    responses(
+        (status = 200, description = "Execution created", body = models::ExecutionJob),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 422, description = "Invalid field values", body = ErrorBody),
+        (status = 409, description = "Idempotency key reused or still in progress, or a callback_url without webhooks enabled", body = ErrorBody),
+        (status = 429, description = "Over quota; see Retry-After", body = ErrorBody),
+        (status = 503, description = "Shutting down; retry shortly", body = ErrorBody),
+    )
+)]
 async fn create_execution(
     State(state): State<Arc<ServiceState>>,
-    Json(request): Json<models::CreateExecutionRequest>,
+    Extension(principal): Extension<Principal>,
+    headers: HeaderMap,
+    ApiJson(mut request): ApiJson<models::CreateExecutionRequest>,
+) -> Result<Json<models::ExecutionJob>, ServiceError> {
+    let key = idempotency_key(&headers, request.idempotency_key.take())?;
+    request.priority.get_or_insert_with(|| principal.default_priority());
+    let (user_id, workspace_id) = principal.resolve_owner(None, None)?;
+    let job = state.submit_execution(request, user_id, workspace_id, key).await?;
+    Ok(Json(job))
+}
+
+#[utoipa::path(
+    post, path = "/v1/executions/batch", tag = "executions",
+    params(("Idempotency-Key" = Option<String>, Header, description = "Resubmitting with the same key returns the original execution")),
+    request_body = models::BatchExecutionRequest,
+    responses(
+        (status = 200, description = "Batch execution created", body = models::ExecutionJob),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 422, description = "Invalid field values", body = ErrorBody),
+        (status = 409, description = "Idempotency key reused or still in progress", body = ErrorBody),
+        (status = 429, description = "Over quota; see Retry-After", body = ErrorBody),
+        (status = 503, description = "Shutting down; retry shortly", body = ErrorBody),
+    )
+)]
+async fn create_batch_execution(
+    State(state): State<Arc<ServiceState>>,
+    Extension(principal): Extension<Principal>,
+    headers: HeaderMap,
+    ApiJson(mut request): ApiJson<models::BatchExecutionRequest>,
 ) -> Result<Json<models::ExecutionJob>, ServiceError> {
-    let job = state.create_execution(request).await?;
+    let key = idempotency_key(&headers, request.idempotency_key.take())?;
+    request.priority.get_or_insert_with(|| principal.default_priority());
+    let (user_id, workspace_id) = principal.resolve_owner(None, None)?;
+    let job = state.submit_execution(request.into(), user_id, workspace_id, key).await?;
     Ok(Json(job))
 }
 
+#[utoipa::path(
+    get, path = "/v1/executions/{id}", tag = "executions",
+    params(("id" = Uuid, Path, description = "Execution id")),
+    responses(
+        (status = 200, description = "The execution", body = models::ExecutionJob),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 422, description = "Invalid field values", body = ErrorBody),
+        (status = 404, description = "Not found", body = ErrorBody),
+    )
+)]
 async fn get_execution(
     State(state): State<Arc<ServiceState>>,
-    Path(id): Path<Uuid>,
+    Extension(principal): Extension<Principal>,
+    ApiPath(id): ApiPath<Uuid>,
+) -> Result<Json<models::ExecutionJob>, ServiceError> {
+    let job = state.get_execution(id).await?;
+    if !principal.can_access(&job) {
+        return Err(ServiceError::NotFound);
+    }
+    let job = state.with_queue_position(job).await?;
+    Ok(Json(job))
+}
+
+// Server-Sent Events for one execution: status changes, output and errors,
+// each with `id:` set to its sequence number. A reconnecting browser sends
+// `Last-Event-ID` and picks up after it. The stream ends after the final
+// status change.
+#[utoipa::path(
+    get, path = "/v1/executions/{id}/events", tag = "executions",
+    params(
+        ("id" = Uuid, Path, description = "Execution id"),
+        ("Last-Event-ID" = Option<u64>, Header, description = "Resume after this sequence number"),
+    ),
+    responses(
+        (status = 200, description = "Server-Sent Events, one per execution event", content_type = "text/event-stream", body = events::ExecutionEvent),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 422, description = "Invalid field values", body = ErrorBody),
+        (status = 404, description = "Not found", body = ErrorBody),
+    )
+)]
+async fn stream_execution_events(
+    State(state): State<Arc<ServiceState>>,
+    Extension(principal): Extension<Principal>,
+    ApiPath(id): ApiPath<Uuid>,
+    headers: HeaderMap,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, ServiceError> {
+    let job = state.get_execution(id).await?;
+    if !principal.can_access(&job) {
+        return Err(ServiceError::NotFound);
+    }
+    let last_event_id = match headers.get("last-event-id") {
+        Some(value) => value
+            .to_str()
+            .ok()
+            .and_then(|v| v.trim().parse::<u64>().ok())
+            .ok_or_else(|| ServiceError::InvalidRequest("Last-Event-ID must be a sequence number".to_string()))?,
+        None => 0,
+    };
+
+    let events = ReceiverStream::new(events::follow(state, id, last_event_id)).map(|event| {
+        Ok(Event::default()
+            .id(event.sequence.to_string())
+            .event(event.event.name())
+            .json_data(&event)
+            .expect("events serialize to JSON"))
+    });
+
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+#[derive(Debug, serde::Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ListExecutionsQuery {
+    user_id: Option<String>,
+    workspace_id: Option<String>,
+    status: Option<models::JobStatus>,
+    language: Option<String>,
+    created_after: Option<chrono::DateTime<chrono::Utc>>,
+    created_before: Option<chrono::DateTime<chrono::Utc>>,
+    page_size: Option<usize>,
+    page_token: Option<String>,
+}
+
+#[utoipa::path(
+    get, path = "/v1/executions", tag = "executions",
+    params(ListExecutionsQuery),
+    responses(
+        (status = 200, description = "A page of executions, newest first", body = pagination::ExecutionPage),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 422, description = "Invalid field values", body = ErrorBody),
+        (status = 403, description = "Listing another user's executions", body = ErrorBody),
+    )
+)]
+async fn list_executions(
+    State(state): State<Arc<ServiceState>>,
+    Extension(principal): Extension<Principal>,
+    ApiQuery(query): ApiQuery<ListExecutionsQuery>,
+) -> Result<Json<pagination::ExecutionPage>, ServiceError> {
+    let filter = pagination::ExecutionFilter {
+        user_id: principal.resolve_list_filter(query.user_id)?,
+        workspace_id: query.workspace_id,
+        status: query.status,
+        language: query.language,
+        created_after: query.created_after,
+        created_before: query.created_before,
+    };
+    let page = state
+        .list_executions(&filter, pagination::page_size(query.page_size), query.page_token.as_deref())
+        .await?;
+    Ok(Json(page))
+}
+
+#[utoipa::path(
+    delete, path = "/v1/executions/{id}", tag = "executions",
+    params(("id" = Uuid, Path, description = "Execution id")),
+    responses(
+        (status = 200, description = "The execution after cancelling", body = models::ExecutionJob),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 422, description = "Invalid field values", body = ErrorBody),
+        (status = 404, description = "Not found", body = ErrorBody),
+    )
+)]
+async fn cancel_execution(
+    State(state): State<Arc<ServiceState>>,
+    Extension(principal): Extension<Principal>,
+    ApiPath(id): ApiPath<Uuid>,
 ) -> Result<Json<models::ExecutionJob>, ServiceError> {
     let job = state.get_execution(id).await?;
+    if !principal.can_access(&job) {
+        return Err(ServiceError::NotFound);
+    }
+    let job = state.cancel_execution(id).await?;
     Ok(Json(job))
-}
\ No newline at end of file
+}
+
+#[utoipa::path(
+    post, path = "/v1/schedules", tag = "schedules",
+    request_body = models::CreateScheduleRequest,
+    responses(
+        (status = 200, description = "Schedule created", body = models::Schedule),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 422, description = "Invalid field values", body = ErrorBody),
+    )
+)]
+async fn create_schedule(
+    State(state): State<Arc<ServiceState>>,
+    Extension(principal): Extension<Principal>,
+    ApiJson(mut request): ApiJson<models::CreateScheduleRequest>,
+) -> Result<Json<models::Schedule>, ServiceError> {
+    request.execution.priority.get_or_insert_with(|| principal.default_priority());
+    let (user_id, workspace_id) = principal.resolve_owner(None, None)?;
+    let schedule = state
+        .create_schedule(request.cron, request.execution, Some(user_id), workspace_id)
+        .await?;
+    Ok(Json(schedule))
+}
+
+#[derive(Debug, serde::Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ListSchedulesQuery {
+    user_id: Option<String>,
+}
+
+#[utoipa::path(
+    get, path = "/v1/schedules", tag = "schedules",
+    params(ListSchedulesQuery),
+    responses(
+        (status = 200, description = "Schedules ordered by next run", body = [models::Schedule]),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 422, description = "Invalid field values", body = ErrorBody),
+    )
+)]
+async fn list_schedules(
+    State(state): State<Arc<ServiceState>>,
+    Extension(principal): Extension<Principal>,
+    ApiQuery(query): ApiQuery<ListSchedulesQuery>,
+) -> Result<Json<Vec<models::Schedule>>, ServiceError> {
+    let user_filter = principal.resolve_list_filter(query.user_id)?;
+    let schedules = state.list_schedules(user_filter.as_deref()).await?;
+    Ok(Json(schedules))
+}
+
+#[utoipa::path(
+    delete, path = "/v1/schedules/{id}", tag = "schedules",
+    params(("id" = Uuid, Path, description = "Schedule id")),
+    responses(
+        (status = 200, description = "The deleted schedule", body = models::Schedule),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 422, description = "Invalid field values", body = ErrorBody),
+        (status = 404, description = "Not found", body = ErrorBody),
+    )
+)]
+async fn delete_schedule(
+    State(state): State<Arc<ServiceState>>,
+    Extension(principal): Extension<Principal>,
+    ApiPath(id): ApiPath<Uuid>,
+) -> Result<Json<models::Schedule>, ServiceError> {
+    let schedule = state.get_schedule(id).await?;
+    if !principal.owns(schedule.user_id.as_deref()) {
+        return Err(ServiceError::NotFound);
+    }
+    state.delete_schedule(&schedule).await?;
+    Ok(Json(schedule))
+}
+
+#[derive(Debug, serde::Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct QuotaUsageQuery {
+    user_id: Option<String>,
+    workspace_id: Option<String>,
+}
+
+#[utoipa::path(
+    get, path = "/v1/quotas/usage", tag = "quotas",
+    params(QuotaUsageQuery),
+    responses(
+        (status = 200, description = "Usage against the user's and workspace's quotas", body = quota::UsageReport),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 422, description = "Invalid field values", body = ErrorBody),
+        (status = 403, description = "Asking about another user or workspace", body = ErrorBody),
+    )
+)]
+async fn get_quota_usage(
+    State(state): State<Arc<ServiceState>>,
+    Extension(principal): Extension<Principal>,
+    ApiQuery(query): ApiQuery<QuotaUsageQuery>,
+) -> Result<Json<quota::UsageReport>, ServiceError> {
+    let (user_id, workspace_id) = principal.resolve_owner(query.user_id, query.workspace_id)?;
+    let report = state.quota_usage(&user_id, workspace_id.as_deref()).await?;
+    Ok(Json(report))
+}
+
+// Webhook subscriptions belong to a workspace: the caller's own, or any one
+// a privileged caller names
+fn webhook_workspace(principal: &Principal, requested: Option<String>) -> Result<String, ServiceError> {
+    let workspace_id = requested
+        .or_else(|| principal.workspace_id.clone())
+        .ok_or_else(|| ServiceError::InvalidRequest("A workspace_id is required".to_string()))?;
+    if !principal.in_workspace(&workspace_id) {
+        return Err(ServiceError::Forbidden("Cannot manage another workspace's webhooks".to_string()));
+    }
+    Ok(workspace_id)
+}
+
+#[utoipa::path(
+    post, path = "/v1/webhooks", tag = "webhooks",
+    request_body = webhook::CreateWebhookRequest,
+    responses(
+        (status = 200, description = "Subscription created", body = webhook::WebhookSubscription),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 422, description = "Invalid field values", body = ErrorBody),
+        (status = 403, description = "Another workspace's webhooks", body = ErrorBody),
+        (status = 409, description = "Webhooks are not enabled, or the workspace has the maximum", body = ErrorBody),
+    )
+)]
+async fn create_webhook(
+    State(state): State<Arc<ServiceState>>,
+    Extension(principal): Extension<Principal>,
+    ApiJson(request): ApiJson<webhook::CreateWebhookRequest>,
+) -> Result<Json<webhook::WebhookSubscription>, ServiceError> {
+    let workspace_id = webhook_workspace(&principal, request.workspace_id)?;
+    let subscription = state.create_webhook(workspace_id, request.url).await?;
+    Ok(Json(subscription))
+}
+
+#[derive(Debug, serde::Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ListWebhooksQuery {
+    workspace_id: Option<String>,
+}
+
+#[utoipa::path(
+    get, path = "/v1/webhooks", tag = "webhooks",
+    params(ListWebhooksQuery),
+    responses(
+        (status = 200, description = "The workspace's subscriptions", body = [webhook::WebhookSubscription]),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 422, description = "Invalid field values", body = ErrorBody),
+        (status = 403, description = "Another workspace's webhooks", body = ErrorBody),
+    )
+)]
+async fn list_webhooks(
+    State(state): State<Arc<ServiceState>>,
+    Extension(principal): Extension<Principal>,
+    ApiQuery(query): ApiQuery<ListWebhooksQuery>,
+) -> Result<Json<Vec<webhook::WebhookSubscription>>, ServiceError> {
+    let workspace_id = webhook_workspace(&principal, query.workspace_id)?;
+    let subscriptions = state.list_webhooks(&workspace_id).await?;
+    Ok(Json(subscriptions))
+}
+
+#[utoipa::path(
+    delete, path = "/v1/webhooks/{id}", tag = "webhooks",
+    params(("id" = Uuid, Path, description = "Subscription id")),
+    responses(
+        (status = 200, description = "The deleted subscription", body = webhook::WebhookSubscription),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 422, description = "Invalid field values", body = ErrorBody),
+        (status = 404, description = "Not found", body = ErrorBody),
+    )
+)]
+async fn delete_webhook(
+    State(state): State<Arc<ServiceState>>,
+    Extension(principal): Extension<Principal>,
+    ApiPath(id): ApiPath<Uuid>,
+) -> Result<Json<webhook::WebhookSubscription>, ServiceError> {
+    let subscription = state.get_webhook(id).await?;
+    if !principal.in_workspace(&subscription.workspace_id) {
+        return Err(ServiceError::NotFound);
+    }
+    state.delete_webhook(&subscription).await?;
+    Ok(Json(subscription))
+}
+
+// Secrets are managed in the caller's workspace, or for the caller alone
+// when they have none, matching where their executions look them up
+fn secret_scope(principal: &Principal) -> Result<String, ServiceError> {
+    let (user_id, workspace_id) = principal.resolve_owner(None, None)?;
+    secrets::scope(Some(&user_id), workspace_id.as_deref())
+        .ok_or_else(|| ServiceError::InvalidRequest("Secrets need a user or workspace".to_string()))
+}
+
+#[utoipa::path(
+    put, path = "/v1/secrets/{name}", tag = "secrets",
+    params(("name" = String, Path, description = "Secret name")),
+    request_body = secrets::PutSecretRequest,
+    responses(
+        (status = 200, description = "Secret stored; the value is never returned", body = secrets::SecretMetadata),
+        (status = 400, description = "Invalid name or value", body = ErrorBody),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 409, description = "Secrets are not enabled", body = ErrorBody),
+        (status = 422, description = "Invalid field values", body = ErrorBody),
+    )
+)]
+async fn put_secret(
+    State(state): State<Arc<ServiceState>>,
+    Extension(principal): Extension<Principal>,
+    ApiPath(name): ApiPath<String>,
+    ApiJson(request): ApiJson<secrets::PutSecretRequest>,
+) -> Result<Json<secrets::SecretMetadata>, ServiceError> {
+    let scope = secret_scope(&principal)?;
+    let secret = state.put_secret(&scope, &name, &request.value).await?;
+    Ok(Json(secret))
+}
+
+#[utoipa::path(
+    get, path = "/v1/secrets", tag = "secrets",
+    responses(
+        (status = 200, description = "Stored secrets by name, without values", body = [secrets::SecretMetadata]),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+    )
+)]
+async fn list_secrets(
+    State(state): State<Arc<ServiceState>>,
+    Extension(principal): Extension<Principal>,
+) -> Result<Json<Vec<secrets::SecretMetadata>>, ServiceError> {
+    let scope = secret_scope(&principal)?;
+    Ok(Json(state.list_secrets(&scope).await?))
+}
+
+#[utoipa::path(
+    delete, path = "/v1/secrets/{name}", tag = "secrets",
+    params(("name" = String, Path, description = "Secret name")),
+    responses(
+        (status = 200, description = "The deleted secret", body = secrets::SecretMetadata),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 404, description = "Not found", body = ErrorBody),
+        (status = 422, description = "Invalid field values", body = ErrorBody),
+    )
+)]
+async fn delete_secret(
+    State(state): State<Arc<ServiceState>>,
+    Extension(principal): Extension<Principal>,
+    ApiPath(name): ApiPath<String>,
+) -> Result<Json<secrets::SecretMetadata>, ServiceError> {
+    let scope = secret_scope(&principal)?;
+    Ok(Json(state.delete_secret(&scope, &name).await?))
+}
+
+#[utoipa::path(
+    delete, path = "/v1/admin/users/{user_id}/executions", tag = "admin",
+    params(("user_id" = String, Path, description = "User whose executions are deleted")),
+    responses(
+        (status = 200, description = "What was deleted", body = state::PurgeSummary),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 422, description = "Invalid field values", body = ErrorBody),
+        (status = 403, description = "Not an admin", body = ErrorBody),
+    )
+)]
+async fn purge_user_executions(
+    State(state): State<Arc<ServiceState>>,
+    Extension(principal): Extension<Principal>,
+    ApiPath(user_id): ApiPath<String>,
+) -> Result<Json<state::PurgeSummary>, ServiceError> {
+    principal.require_admin()?;
+    let summary = state.purge_user_executions(&user_id).await?;
+    tracing::info!(
+        "Purged {} executions ({} archived) for user {}",
+        summary.purged_jobs,
+        summary.purged_archived,
+        user_id
+    );
+    Ok(Json(summary))
+}
+
+#[utoipa::path(
+    get, path = "/v1/admin/dlq", tag = "admin",
+    responses(
+        (status = 200, description = "Dead-lettered jobs, newest first", body = [dlq::DeadLetter]),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 403, description = "Not an admin", body = ErrorBody),
+    )
+)]
+async fn list_dead_letters(
+    State(state): State<Arc<ServiceState>>,
+    Extension(principal): Extension<Principal>,
+) -> Result<Json<Vec<dlq::DeadLetter>>, ServiceError> {
+    principal.require_admin()?;
+    Ok(Json(state.dlq.list().await?))
+}
+
+#[utoipa::path(
+    get, path = "/v1/admin/dlq/{id}", tag = "admin",
+    params(("id" = Uuid, Path, description = "Dead letter id")),
+    responses(
+        (status = 200, description = "The dead letter", body = dlq::DeadLetter),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 422, description = "Invalid field values", body = ErrorBody),
+        (status = 403, description = "Not an admin", body = ErrorBody),
+        (status = 404, description = "Not found", body = ErrorBody),
+    )
+)]
+async fn get_dead_letter(
+    State(state): State<Arc<ServiceState>>,
+    Extension(principal): Extension<Principal>,
+    ApiPath(id): ApiPath<Uuid>,
+) -> Result<Json<dlq::DeadLetter>, ServiceError> {
+    principal.require_admin()?;
+    let entry = state.dlq.get(id).await?.ok_or(ServiceError::NotFound)?;
+    Ok(Json(entry))
+}
+
+#[utoipa::path(
+    post, path = "/v1/admin/dlq/{id}/replay", tag = "admin",
+    params(("id" = Uuid, Path, description = "Dead letter id")),
+    responses(
+        (status = 200, description = "The requeued execution", body = models::ExecutionJob),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 422, description = "Invalid field values", body = ErrorBody),
+        (status = 403, description = "Not an admin", body = ErrorBody),
+        (status = 404, description = "Not found", body = ErrorBody),
+    )
+)]
+async fn replay_dead_letter(
+    State(state): State<Arc<ServiceState>>,
+    Extension(principal): Extension<Principal>,
+    ApiPath(id): ApiPath<Uuid>,
+) -> Result<Json<models::ExecutionJob>, ServiceError> {
+    principal.require_admin()?;
+    let job = state.replay_dead_letter(id).await?;
+    tracing::info!("Replayed dead letter {} as execution {}", id, job.id);
+    Ok(Json(job))
+}
+
+#[utoipa::path(
+    post, path = "/v1/admin/dlq/replay", tag = "admin",
+    request_body = dlq::DeadLetterSelection,
+    responses(
+        (status = 200, description = "Which dead letters were replayed", body = dlq::ReplaySummary),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 422, description = "Invalid field values", body = ErrorBody),
+        (status = 403, description = "Not an admin", body = ErrorBody),
+    )
+)]
+async fn replay_dead_letters(
+    State(state): State<Arc<ServiceState>>,
+    Extension(principal): Extension<Principal>,
+    ApiJson(selection): ApiJson<dlq::DeadLetterSelection>,
+) -> Result<Json<dlq::ReplaySummary>, ServiceError> {
+    principal.require_admin()?;
+    let summary = state.replay_dead_letters(&selection).await?;
+    tracing::info!(
+        "Replayed {} dead letters, skipped {}",
+        summary.replayed.len(),
+        summary.skipped.len()
+    );
+    Ok(Json(summary))
+}
+
+#[utoipa::path(
+    delete, path = "/v1/admin/dlq/{id}", tag = "admin",
+    params(("id" = Uuid, Path, description = "Dead letter id")),
+    responses(
+        (status = 200, description = "The dead letter was dropped", body = dlq::PurgedCount),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 422, description = "Invalid field values", body = ErrorBody),
+        (status = 403, description = "Not an admin", body = ErrorBody),
+        (status = 404, description = "Not found", body = ErrorBody),
+    )
+)]
+async fn delete_dead_letter(
+    State(state): State<Arc<ServiceState>>,
+    Extension(principal): Extension<Principal>,
+    ApiPath(id): ApiPath<Uuid>,
+) -> Result<Json<dlq::PurgedCount>, ServiceError> {
+    principal.require_admin()?;
+    let purged = state.dlq.remove(&[id]).await?;
+    if purged == 0 {
+        return Err(ServiceError::NotFound);
+    }
+    Ok(Json(dlq::PurgedCount { purged }))
+}
+
+#[utoipa::path(
+    post, path = "/v1/admin/dlq/purge", tag = "admin",
+    request_body = dlq::DeadLetterSelection,
+    responses(
+        (status = 200, description = "How many dead letters were dropped", body = dlq::PurgedCount),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 422, description = "Invalid field values", body = ErrorBody),
+        (status = 403, description = "Not an admin", body = ErrorBody),
+    )
+)]
+async fn purge_dead_letters(
+    State(state): State<Arc<ServiceState>>,
+    Extension(principal): Extension<Principal>,
+    ApiJson(selection): ApiJson<dlq::DeadLetterSelection>,
+) -> Result<Json<dlq::PurgedCount>, ServiceError> {
+    principal.require_admin()?;
+    let purged = state.purge_dead_letters(&selection).await?;
+    tracing::info!("Purged {} dead letters", purged);
+    Ok(Json(dlq::PurgedCount { purged }))
+}
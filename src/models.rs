@@ -1,43 +1,298 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use utoipa::ToSchema;
 use uuid::Uuid;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct CreateExecutionRequest {
     pub code: String,
     pub language: String,
     pub timeout_seconds: Option<u64>,
+    /// Passed to the program after its source file
     pub args: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub environment: Option<HashMap<String, String>>,
+    /// Environment variables filled from stored secrets, as variable name to
+    /// secret name. Secret values are redacted from the output.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secrets: Option<HashMap<String, String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_time_limit_seconds: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub test_cases: Option<Vec<TestCase>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_encoding: Option<OutputEncoding>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<Priority>,
+    /// Hold the job until this time instead of queueing it right away
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub not_before: Option<DateTime<Utc>>,
+    /// Alternative to the `Idempotency-Key` header; never stored
+    #[serde(default, skip_serializing)]
+    pub idempotency_key: Option<String>,
+    /// Reuse the result of an identical earlier request, and store this one's
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache: Option<bool>,
+    /// Run even when a cached result exists; the cache is refreshed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bypass_cache: Option<bool>,
+    /// POSTed a signed copy of the execution once it finishes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub callback_url: Option<String>,
+}
+
+// Batch submission: compile once, run against every test case
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct BatchExecutionRequest {
+    pub code: String,
+    pub language: String,
+    pub timeout_seconds: Option<u64>,
+    pub test_cases: Vec<TestCase>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub args: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub environment: Option<HashMap<String, String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secrets: Option<HashMap<String, String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<Priority>,
+    #[serde(default, skip_serializing)]
+    pub idempotency_key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bypass_cache: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub callback_url: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TestCase {
+    #[serde(default)]
+    pub stdin: String,
+    pub expected_stdout: String,
+    pub timeout_seconds: Option<u64>,
+    pub memory_mb: Option<u64>,
+}
+
+/// Scheduling class. Interactive jobs get the larger share of workers;
+/// within a class tenants take turns.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    #[default]
+    Interactive,
+    Batch,
+}
+
+impl Priority {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Priority::Interactive => "interactive",
+            Priority::Batch => "batch",
+        }
+    }
+}
+
+impl std::str::FromStr for Priority {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "interactive" => Ok(Priority::Interactive),
+            "batch" => Ok(Priority::Batch),
+            other => Err(format!("Unknown priority: {}", other)),
+        }
+    }
+}
+
+/// Where a queued job stands. `position` is 1 for the next job to run.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct QueuePosition {
+    pub position: u64,
+    pub estimated_wait_ms: u64,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct ExecutionJob {
     pub id: Uuid,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workspace_id: Option<String>,
     pub status: JobStatus,
     pub request: CreateExecutionRequest,
     pub created_at: DateTime<Utc>,
     pub started_at: Option<DateTime<Utc>>,
     pub completed_at: Option<DateTime<Utc>>,
     pub result: Option<ExecutionResult>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub batch: Option<BatchResult>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attempts: Vec<Attempt>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub webhook_deliveries: Vec<WebhookDelivery>,
+    /// Result was served from the result cache without running
+    #[serde(default)]
+    pub cache_hit: bool,
+    /// Filled in when a queued job is read back, never stored
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub queue: Option<QueuePosition>,
+}
+
+/// Who is to blame for a failed attempt. Only infrastructure failures are
+/// retried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureKind {
+    Infrastructure,
+    User,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// One run of a job. A job retried after infrastructure failures has one
+/// entry per try.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Attempt {
+    pub number: u32,
+    pub started_at: DateTime<Utc>,
+    pub completed_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failure: Option<FailureKind>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// One attempt to POST a finished job's webhook. Retries of the same
+/// delivery share its id.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct WebhookDelivery {
+    pub id: Uuid,
+    pub url: String,
+    pub event: String,
+    pub attempt: u32,
+    pub attempted_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status_code: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub delivered: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum JobStatus {
+    // Waiting for its `not_before` time
+    Pending,
     Queued,
     Running,
     Completed,
     Failed,
     Timeout,
+    Cancelled,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct ExecutionResult {
     pub exit_code: i32,
     pub stdout: String,
     pub stderr: String,
     pub duration_ms: u64,
+    /// Bytes written by the program; may exceed what was kept
+    #[serde(default)]
+    pub stdout_bytes: u64,
+    #[serde(default)]
+    pub stderr_bytes: u64,
+    #[serde(default)]
+    pub stdout_truncated: bool,
+    #[serde(default)]
+    pub stderr_truncated: bool,
+    #[serde(default)]
+    pub encoding: OutputEncoding,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub termination: Option<Termination>,
+}
+
+// How the program's process ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum Termination {
+    Exited { exit_code: i32 },
+    WallClockTimeout,
+    CpuTimeLimit,
+    OutOfMemory,
+    Signaled { signal: i32 },
+}
+
+// How captured stdout/stderr are represented in results. `base64` keeps
+// non-UTF-8 output intact.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputEncoding {
+    #[default]
+    Utf8,
+    Base64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct BatchResult {
+    // Present only for compiled languages
+    pub compile: Option<ExecutionResult>,
+    pub cases: Vec<TestCaseResult>,
+    pub passed: usize,
+    pub total: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TestCaseResult {
+    pub index: usize,
+    pub verdict: Verdict,
+    pub exit_code: i32,
+    pub stdout: String,
+    pub stderr: String,
+    pub duration_ms: u64,
+    pub diff: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Verdict {
+    Accepted,
+    WrongAnswer,
+    TimeLimitExceeded,
+    MemoryLimitExceeded,
+    RuntimeError,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CreateScheduleRequest {
+    // Standard five-field cron, or six fields with seconds first; UTC
+    pub cron: String,
+    pub execution: CreateExecutionRequest,
+}
+
+/// A recurring execution. Each time the cron expression fires, a new
+/// execution is created from `request`.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Schedule {
+    pub id: Uuid,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workspace_id: Option<String>,
+    pub cron: String,
+    pub request: CreateExecutionRequest,
+    pub created_at: DateTime<Utc>,
+    pub next_run_at: DateTime<Utc>,
+    pub last_run_at: Option<DateTime<Utc>>,
+    pub last_execution_id: Option<Uuid>,
 }
 
 // Database models for persistence
@@ -50,11 +305,14 @@ pub struct Execution {
     pub language: String,
     pub args: Option<Vec<String>>,
     pub environment: Option<HashMap<String, String>>,
+    // Variable name to secret name; values are never carried here
+    pub secrets: Option<HashMap<String, String>>,
     pub timeout_seconds: Option<i32>,
     pub status: ExecutionStatus,
     pub exit_code: Option<i32>,
     pub stdout: Option<String>,
     pub stderr: Option<String>,
+    pub termination: Option<Termination>,
     pub created_at: DateTime<Utc>,
     pub started_at: Option<DateTime<Utc>>,
     pub completed_at: Option<DateTime<Utc>>,
@@ -63,22 +321,167 @@ pub struct Execution {
 #[derive(Debug, Clone, PartialEq)]
 pub enum ExecutionStatus {
     Pending,
+    Queued,
     Running,
     Completed,
     Failed,
     Cancelled,
+    Timeout,
 }
 
 impl ExecutionJob {
     pub fn new(request: CreateExecutionRequest) -> Self {
         Self {
             id: Uuid::new_v4(),
+            user_id: None,
+            workspace_id: None,
             status: JobStatus::Queued,
             request,
             created_at: Utc::now(),
             started_at: None,
             completed_at: None,
             result: None,
+            batch: None,
+            attempts: vec![],
+            webhook_deliveries: vec![],
+            cache_hit: false,
+            queue: None,
+        }
+    }
+}
+
+impl JobStatus {
+    pub fn is_terminal(&self) -> bool {
+        !matches!(self, JobStatus::Pending | JobStatus::Queued | JobStatus::Running)
+    }
+}
+
+impl From<&JobStatus> for ExecutionStatus {
+    fn from(status: &JobStatus) -> Self {
+        match status {
+            JobStatus::Pending => ExecutionStatus::Pending,
+            JobStatus::Queued => ExecutionStatus::Queued,
+            JobStatus::Running => ExecutionStatus::Running,
+            JobStatus::Completed => ExecutionStatus::Completed,
+            JobStatus::Failed => ExecutionStatus::Failed,
+            JobStatus::Timeout => ExecutionStatus::Timeout,
+            JobStatus::Cancelled => ExecutionStatus::Cancelled,
+        }
+    }
+}
+
+impl ExecutionStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ExecutionStatus::Pending => "pending",
+            ExecutionStatus::Queued => "queued",
+            ExecutionStatus::Running => "running",
+            ExecutionStatus::Completed => "completed",
+            ExecutionStatus::Failed => "failed",
+            ExecutionStatus::Cancelled => "cancelled",
+            ExecutionStatus::Timeout => "timeout",
+        }
+    }
+}
+
+impl From<&ExecutionJob> for Execution {
+    fn from(job: &ExecutionJob) -> Self {
+        Self {
+            id: job.id,
+            user_id: job.user_id.clone().unwrap_or_default(),
+            workspace_id: job.workspace_id.clone(),
+            code: job.request.code.clone(),
+            language: job.request.language.clone(),
+            args: job.request.args.clone(),
+            environment: job.request.environment.clone(),
+            secrets: job.request.secrets.clone(),
+            timeout_seconds: job.request.timeout_seconds.map(|t| t as i32),
+            status: ExecutionStatus::from(&job.status),
+            exit_code: job.result.as_ref().map(|r| r.exit_code),
+            stdout: job.result.as_ref().map(|r| r.stdout.clone()),
+            stderr: job.result.as_ref().map(|r| r.stderr.clone()),
+            termination: job.result.as_ref().and_then(|r| r.termination),
+            created_at: job.created_at,
+            started_at: job.started_at,
+            completed_at: job.completed_at,
+        }
+    }
+}
+
+impl CreateExecutionRequest {
+    pub fn uses_secrets(&self) -> bool {
+        self.secrets.as_ref().is_some_and(|secrets| !secrets.is_empty())
+    }
+}
+
+impl From<BatchExecutionRequest> for CreateExecutionRequest {
+    fn from(request: BatchExecutionRequest) -> Self {
+        Self {
+            code: request.code,
+            language: request.language,
+            timeout_seconds: request.timeout_seconds,
+            args: request.args,
+            environment: request.environment,
+            secrets: request.secrets,
+            cpu_time_limit_seconds: None,
+            test_cases: Some(request.test_cases),
+            output_encoding: None,
+            priority: request.priority,
+            not_before: None,
+            idempotency_key: request.idempotency_key,
+            cache: request.cache,
+            bypass_cache: request.bypass_cache,
+            callback_url: request.callback_url,
+        }
+    }
+}
+
+impl ExecutionResult {
+    // Result recorded when the execution could not be carried out at all
+    pub fn from_error(message: String) -> Self {
+        Self {
+            exit_code: -1,
+            stdout: String::new(),
+            stderr: message,
+            duration_ms: 0,
+            stdout_bytes: 0,
+            stderr_bytes: 0,
+            stdout_truncated: false,
+            stderr_truncated: false,
+            encoding: OutputEncoding::Utf8,
+            termination: None,
+        }
+    }
+}
+
+impl Termination {
+    pub fn job_status(&self) -> JobStatus {
+        match self {
+            Termination::Exited { exit_code: 0 } => JobStatus::Completed,
+            Termination::WallClockTimeout | Termination::CpuTimeLimit => JobStatus::Timeout,
+            _ => JobStatus::Failed,
+        }
+    }
+    
+    /// Value for `ExecutionError.code`, or `None` on success.
+    pub fn error_code(&self) -> Option<&'static str> {
+        match self {
+            Termination::Exited { exit_code: 0 } => None,
+            Termination::Exited { .. } => Some("EXECUTION_FAILED"),
+            Termination::WallClockTimeout => Some("TIMEOUT"),
+            Termination::CpuTimeLimit => Some("CPU_TIME_LIMIT_EXCEEDED"),
+            Termination::OutOfMemory => Some("OUT_OF_MEMORY"),
+            Termination::Signaled { .. } => Some("KILLED_BY_SIGNAL"),
+        }
+    }
+    
+    pub fn describe(&self) -> String {
+        match self {
+            Termination::Exited { exit_code } => format!("Process exited with code {}", exit_code),
+            Termination::WallClockTimeout => "Execution exceeded its wall-clock timeout".to_string(),
+            Termination::CpuTimeLimit => "Execution exceeded its CPU time limit".to_string(),
+            Termination::OutOfMemory => "Execution was killed after exceeding its memory limit".to_string(),
+            Termination::Signaled { signal } => format!("Process was killed by signal {}", signal),
         }
     }
 }
\ No newline at end of file
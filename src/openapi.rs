@@ -0,0 +1,96 @@
+use axum::response::Html;
+use axum::Json;
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+use utoipa_rapidoc::RapiDoc;
+
+/// OpenAPI document for the REST API, generated from the handlers and
+/// models. `openapi.json` at the repository root is a checked-in copy for
+/// client generation; regenerate it with `make openapi`.
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Syla Execution Service", description = "Run untrusted code in sandboxed containers."),
+    paths(
+        crate::health_handler,
+        crate::liveness_handler,
+        crate::readiness_handler,
+        crate::create_execution,
+        crate::list_executions,
+        crate::create_batch_execution,
+        crate::get_execution,
+        crate::cancel_execution,
+        crate::stream_execution_events,
+        crate::create_schedule,
+        crate::list_schedules,
+        crate::delete_schedule,
+        crate::get_quota_usage,
+        crate::create_webhook,
+        crate::list_webhooks,
+        crate::delete_webhook,
+        crate::put_secret,
+        crate::list_secrets,
+        crate::delete_secret,
+        crate::purge_user_executions,
+        crate::list_dead_letters,
+        crate::replay_dead_letters,
+        crate::purge_dead_letters,
+        crate::get_dead_letter,
+        crate::delete_dead_letter,
+        crate::replay_dead_letter,
+    ),
+    modifiers(&BearerAuth),
+    security(("bearer" = [])),
+    tags(
+        (name = "executions", description = "Submit, follow and cancel executions"),
+        (name = "schedules", description = "Recurring executions"),
+        (name = "quotas", description = "Usage against quotas"),
+        (name = "webhooks", description = "Workspace webhook subscriptions"),
+        (name = "secrets", description = "Encrypted values injected into executions as environment variables"),
+        (name = "admin", description = "Operator endpoints; admin role required"),
+        (name = "health", description = "Liveness and readiness probes"),
+    )
+)]
+pub struct ApiDoc;
+
+struct BearerAuth;
+
+impl Modify for BearerAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).bearer_format("JWT").build()),
+        );
+    }
+}
+
+pub async fn openapi_json() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+// Interactive docs rendering `/openapi.json`
+pub async fn docs() -> Html<String> {
+    Html(RapiDoc::new("/openapi.json").to_html())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPEC_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/openapi.json");
+
+    // Run with UPDATE_OPENAPI=1 to rewrite the checked-in spec
+    #[test]
+    fn checked_in_spec_matches_code() {
+        let generated = ApiDoc::openapi().to_pretty_json().expect("spec serializes") + "\n";
+        if std::env::var_os("UPDATE_OPENAPI").is_some() {
+            std::fs::write(SPEC_PATH, &generated).expect("write openapi.json");
+            return;
+        }
+        let checked_in = std::fs::read_to_string(SPEC_PATH).unwrap_or_default();
+        assert!(
+            checked_in == generated,
+            "openapi.json is out of date with the REST handlers; run `make openapi` and commit the result"
+        );
+    }
+}
@@ -0,0 +1,147 @@
+use crate::error::ServiceError;
+use crate::models::{ExecutionJob, JobStatus};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD as BASE64, Engine};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+pub const DEFAULT_PAGE_SIZE: usize = 50;
+pub const MAX_PAGE_SIZE: usize = 100;
+
+/// Which executions a listing covers. Unset fields don't filter.
+#[derive(Debug, Clone, Default)]
+pub struct ExecutionFilter {
+    pub user_id: Option<String>,
+    pub workspace_id: Option<String>,
+    pub status: Option<JobStatus>,
+    pub language: Option<String>,
+    // Exclusive bounds on `created_at`
+    pub created_after: Option<DateTime<Utc>>,
+    pub created_before: Option<DateTime<Utc>>,
+}
+
+impl ExecutionFilter {
+    // The user and time bounds are applied through the index
+    pub fn matches(&self, job: &ExecutionJob) -> bool {
+        self.status.iter().all(|status| job.status == *status)
+            && self.language.iter().all(|language| job.request.language == *language)
+            && self.workspace_id.iter().all(|workspace_id| job.workspace_id.as_ref() == Some(workspace_id))
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ExecutionPage {
+    pub executions: Vec<ExecutionJob>,
+    // Absent on the last page
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_page_token: Option<String>,
+}
+
+/// Position after the last execution of a page. Listings run newest first,
+/// ties on creation time broken by descending id, so a position stays put
+/// however many executions are created after the listing began.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageToken {
+    pub created_at_ms: i64,
+    pub id: Uuid,
+}
+
+impl PageToken {
+    pub fn after(job: &ExecutionJob) -> Self {
+        Self {
+            created_at_ms: job.created_at.timestamp_millis(),
+            id: job.id,
+        }
+    }
+
+    pub fn encode(&self) -> String {
+        BASE64.encode(format!("{}:{}", self.created_at_ms, self.id))
+    }
+
+    pub fn decode(token: &str) -> Result<Self, ServiceError> {
+        let invalid = || ServiceError::InvalidRequest("Invalid page token".to_string());
+        let decoded = BASE64.decode(token).map_err(|_| invalid())?;
+        let decoded = String::from_utf8(decoded).map_err(|_| invalid())?;
+        let (created_at_ms, id) = decoded.split_once(':').ok_or_else(invalid)?;
+        Ok(Self {
+            created_at_ms: created_at_ms.parse().map_err(|_| invalid())?,
+            id: id.parse().map_err(|_| invalid())?,
+        })
+    }
+
+    /// Whether an index entry comes after this position.
+    pub fn precedes(&self, created_at_ms: i64, id: &str) -> bool {
+        created_at_ms < self.created_at_ms
+            || (created_at_ms == self.created_at_ms && id < self.id.to_string().as_str())
+    }
+}
+
+/// The requested page size, defaulted and capped.
+pub fn page_size(requested: Option<usize>) -> usize {
+    match requested {
+        Some(0) | None => DEFAULT_PAGE_SIZE,
+        Some(size) => size.min(MAX_PAGE_SIZE),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(created_at_ms: i64, id: &str) -> PageToken {
+        PageToken {
+            created_at_ms,
+            id: id.parse().unwrap(),
+        }
+    }
+
+    const LOW: &str = "11111111-1111-4111-8111-111111111111";
+    const HIGH: &str = "eeeeeeee-eeee-4eee-8eee-eeeeeeeeeeee";
+
+    #[test]
+    fn token_round_trips() {
+        let position = token(1_700_000_000_123, HIGH);
+        assert_eq!(PageToken::decode(&position.encode()).unwrap(), position);
+    }
+
+    #[test]
+    fn malformed_tokens_are_rejected() {
+        let malformed = [
+            String::new(),
+            "not base64!".to_string(),
+            BASE64.encode("123"),
+            BASE64.encode("abc:def"),
+            BASE64.encode(format!("x:{HIGH}")),
+        ];
+        for encoded in &malformed {
+            assert!(
+                matches!(PageToken::decode(encoded), Err(ServiceError::InvalidRequest(_))),
+                "{encoded:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn older_entries_follow_the_position() {
+        let position = token(1_000, HIGH);
+        assert!(position.precedes(999, HIGH));
+        assert!(!position.precedes(1_001, LOW));
+    }
+
+    #[test]
+    fn ties_on_creation_time_break_by_descending_id() {
+        let position = token(1_000, HIGH);
+        assert!(position.precedes(1_000, LOW));
+        assert!(!position.precedes(1_000, HIGH));
+        assert!(!token(1_000, LOW).precedes(1_000, HIGH));
+    }
+
+    #[test]
+    fn page_size_is_defaulted_and_capped() {
+        assert_eq!(page_size(None), DEFAULT_PAGE_SIZE);
+        assert_eq!(page_size(Some(0)), DEFAULT_PAGE_SIZE);
+        assert_eq!(page_size(Some(10)), 10);
+        assert_eq!(page_size(Some(MAX_PAGE_SIZE + 1)), MAX_PAGE_SIZE);
+    }
+}
@@ -1,37 +1,448 @@
 use anyhow::Result;
 use redis::aio::ConnectionManager;
-use redis::AsyncCommands;
+use redis::Script;
+use serde::{Deserialize, Serialize};
 use tokio::sync::Mutex;
-use uuid::Uuid;
 
+use crate::models::{ExecutionJob, Priority, QueuePosition};
+
+// Each class keeps, under `syla:queue:{class}`:
+//   :tenants          ring of tenants with queued jobs, in turn order
+//   :tenant:{tenant}  that tenant's job ids, FIFO
+//   :credits          picks left for the class in the current round
+const QUEUE_PREFIX: &str = "syla:queue";
+const AVG_DURATION_KEY: &str = "syla:queue:avg_duration_ms";
+// The single FIFO list jobs went to before priority classes (LPUSH in,
+// RPOP out). Only read to move what's left in it after an upgrade.
+const LEGACY_QUEUE_KEY: &str = "syla:execution:queue";
+
+// Used for wait estimates until a job has finished
+const DEFAULT_AVG_DURATION_MS: u64 = 1_000;
+// Weight of the newest duration in the moving average
+const DURATION_SMOOTHING: f64 = 0.2;
+
+const PUSH_SCRIPT: &str = r#"
+local len = redis.call('RPUSH', KEYS[1] .. ':tenant:' .. ARGV[1], ARGV[2])
+if len == 1 then
+    redis.call('RPUSH', KEYS[1] .. ':tenants', ARGV[1])
+end
+return len
+"#;
+
+// Weighted round-robin across classes, round-robin across the tenants of a
+// class. KEYS are the class prefixes, ARGV their weights, in the same order.
+const POP_SCRIPT: &str = r#"
+local function pop_class(base)
+    while true do
+        local tenant = redis.call('LPOP', base .. ':tenants')
+        if not tenant then
+            return nil
+        end
+        local list = base .. ':tenant:' .. tenant
+        local job = redis.call('LPOP', list)
+        if redis.call('LLEN', list) > 0 then
+            redis.call('RPUSH', base .. ':tenants', tenant)
+        end
+        if job then
+            return job
+        end
+    end
+end
+
+for round = 1, 2 do
+    for i, base in ipairs(KEYS) do
+        local credits = tonumber(redis.call('GET', base .. ':credits') or ARGV[i])
+        if credits > 0 and redis.call('LLEN', base .. ':tenants') > 0 then
+            local job = pop_class(base)
+            if job then
+                redis.call('SET', base .. ':credits', credits - 1)
+                return job
+            end
+        end
+    end
+    -- Every class with work has used its share; start a new round
+    for i, base in ipairs(KEYS) do
+        redis.call('SET', base .. ':credits', ARGV[i])
+    end
+end
+return false
+"#;
+
+// Number of jobs that will be picked before the given one. ARGV: index of
+// the job's class in KEYS, tenant, job id, then the class weights.
+const POSITION_SCRIPT: &str = r#"
+local mine = tonumber(ARGV[1])
+local base = KEYS[mine]
+local k = redis.call('LPOS', base .. ':tenant:' .. ARGV[2], ARGV[3])
+if not k then
+    return -1
+end
+
+-- Tenants ahead in the ring get one more turn than those behind
+local ahead = k
+local before = true
+for _, tenant in ipairs(redis.call('LRANGE', base .. ':tenants', 0, -1)) do
+    if tenant == ARGV[2] then
+        before = false
+    else
+        local turns = k
+        if before then
+            turns = k + 1
+        end
+        ahead = ahead + math.min(redis.call('LLEN', base .. ':tenant:' .. tenant), turns)
+    end
+end
+
+-- Other classes take their weighted share of the picks in between
+local in_class = ahead
+for i, other in ipairs(KEYS) do
+    if i ~= mine then
+        local queued = 0
+        for _, tenant in ipairs(redis.call('LRANGE', other .. ':tenants', 0, -1)) do
+            queued = queued + redis.call('LLEN', other .. ':tenant:' .. tenant)
+        end
+        local share = math.ceil((in_class + 1) * tonumber(ARGV[3 + i]) / tonumber(ARGV[3 + mine]))
+        ahead = ahead + math.min(queued, share)
+    end
+end
+return ahead
+"#;
+
+// Jobs waiting across every tenant of every class in KEYS
+const DEPTH_SCRIPT: &str = r#"
+local total = 0
+for _, base in ipairs(KEYS) do
+    for _, tenant in ipairs(redis.call('LRANGE', base .. ':tenants', 0, -1)) do
+        total = total + redis.call('LLEN', base .. ':tenant:' .. tenant)
+    end
+end
+return total
+"#;
+
+/// Relative share of pops each priority class gets while both have work.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QueueWeights {
+    pub interactive: u64,
+    pub batch: u64,
+}
+
+impl Default for QueueWeights {
+    fn default() -> Self {
+        Self { interactive: 4, batch: 1 }
+    }
+}
+
+/// Job queue with priority classes and per-tenant fairness. Classes are
+/// served by weighted round-robin so batch work can't be starved outright.
 pub struct RedisQueue {
     conn: Mutex<ConnectionManager>,
-    queue_key: String,
+    weights: Vec<(Priority, u64)>,
+    push_script: Script,
+    pop_script: Script,
+    position_script: Script,
+    depth_script: Script,
 }
 
 impl RedisQueue {
-    pub fn new(conn: ConnectionManager) -> Self {
+    pub fn new(conn: ConnectionManager, weights: QueueWeights) -> Self {
         Self {
             conn: Mutex::new(conn),
-            queue_key: "syla:execution:queue".to_string(),
+            weights: vec![
+                (Priority::Interactive, weights.interactive),
+                (Priority::Batch, weights.batch),
+            ],
+            push_script: Script::new(PUSH_SCRIPT),
+            pop_script: Script::new(POP_SCRIPT),
+            position_script: Script::new(POSITION_SCRIPT),
+            depth_script: Script::new(DEPTH_SCRIPT),
         }
     }
-    
-    pub async fn push_job(&self, job_id: Uuid) -> Result<()> {
+
+    fn class_key(priority: Priority) -> String {
+        format!("{}:{}", QUEUE_PREFIX, priority.as_str())
+    }
+
+    // Fairness is per workspace when there is one, otherwise per user
+    fn tenant(job: &ExecutionJob) -> &str {
+        job.workspace_id
+            .as_deref()
+            .or(job.user_id.as_deref())
+            .unwrap_or("anonymous")
+    }
+
+    pub async fn push_job(&self, job: &ExecutionJob) -> Result<()> {
         let mut conn = self.conn.lock().await;
-        let _: () = conn.lpush(&self.queue_key, job_id.to_string()).await?;
+        self.push_script
+            .key(Self::class_key(job.request.priority.unwrap_or_default()))
+            .arg(Self::tenant(job))
+            .arg(job.id.to_string())
+            .invoke_async::<_, ()>(&mut *conn)
+            .await?;
         Ok(())
     }
-    
-    pub async fn pop_job(&self) -> Result<Option<Uuid>> {
+
+    /// The next entry as stored, normally a job id. Parsing is left to the
+    /// caller so a malformed entry can be dead-lettered rather than lost.
+    pub async fn pop_job(&self) -> Result<Option<String>> {
         let mut conn = self.conn.lock().await;
-        let result: Option<String> = conn.rpop(&self.queue_key, None).await?;
-        result.map(|s| Uuid::parse_str(&s)).transpose().map_err(Into::into)
+        let mut invocation = self.pop_script.prepare_invoke();
+        for (priority, weight) in &self.weights {
+            invocation.key(Self::class_key(*priority)).arg(*weight);
+        }
+        let result: Option<String> = invocation.invoke_async(&mut *conn).await?;
+        Ok(result)
+    }
+
+    /// The oldest entry left in the pre-priority queue, removing it.
+    pub async fn pop_legacy(&self) -> Result<Option<String>> {
+        let mut conn = self.conn.lock().await;
+        let entry: Option<String> = redis::cmd("RPOP")
+            .arg(LEGACY_QUEUE_KEY)
+            .query_async(&mut *conn)
+            .await?;
+        Ok(entry)
+    }
+
+    /// Estimate where a queued job stands. `None` once it has left the queue.
+    pub async fn position(&self, job: &ExecutionJob) -> Result<Option<QueuePosition>> {
+        let priority = job.request.priority.unwrap_or_default();
+        let class_index = self.weights
+            .iter()
+            .position(|(p, _)| *p == priority)
+            .unwrap_or(0);
+
+        let mut conn = self.conn.lock().await;
+        let mut invocation = self.position_script.prepare_invoke();
+        invocation
+            .arg(class_index + 1)
+            .arg(Self::tenant(job))
+            .arg(job.id.to_string());
+        for (priority, weight) in &self.weights {
+            invocation.key(Self::class_key(*priority)).arg(*weight);
+        }
+        let ahead: i64 = invocation.invoke_async(&mut *conn).await?;
+        if ahead < 0 {
+            return Ok(None);
+        }
+
+        let avg_duration_ms: Option<f64> = redis::cmd("GET")
+            .arg(AVG_DURATION_KEY)
+            .query_async(&mut *conn)
+            .await?;
+        let avg_duration_ms = avg_duration_ms.map(|d| d as u64).unwrap_or(DEFAULT_AVG_DURATION_MS);
+
+        // Counting the job in progress, each one ahead is a full run away
+        let position = ahead as u64 + 1;
+        Ok(Some(QueuePosition {
+            position,
+            estimated_wait_ms: position * avg_duration_ms,
+        }))
+    }
+
+    /// Number of jobs waiting to be picked, across all classes.
+    pub async fn depth(&self) -> Result<u64> {
+        let mut conn = self.conn.lock().await;
+        let mut invocation = self.depth_script.prepare_invoke();
+        for (priority, _) in &self.weights {
+            invocation.key(Self::class_key(*priority));
+        }
+        let depth: u64 = invocation.invoke_async(&mut *conn).await?;
+        Ok(depth)
     }
-    
-    pub async fn get_queue_length(&self) -> Result<usize> {
+
+    /// Fold a finished job's run time into the average used for estimates.
+    pub async fn record_duration(&self, duration_ms: u64) -> Result<()> {
         let mut conn = self.conn.lock().await;
-        let len: usize = conn.llen(&self.queue_key).await?;
-        Ok(len)
+        let current: Option<f64> = redis::cmd("GET")
+            .arg(AVG_DURATION_KEY)
+            .query_async(&mut *conn)
+            .await?;
+        let updated = match current {
+            Some(avg) => avg + DURATION_SMOOTHING * (duration_ms as f64 - avg),
+            None => duration_ms as f64,
+        };
+        redis::cmd("SET")
+            .arg(AVG_DURATION_KEY)
+            .arg(updated)
+            .query_async::<_, ()>(&mut *conn)
+            .await?;
+        Ok(())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mlua::{Lua, Value};
+
+    // Just the list and string commands the scripts use, over a Lua table
+    const FAKE_REDIS: &str = r#"
+local store = {}
+redis = {}
+function redis.call(command, key, ...)
+    local args = {...}
+    local list = store[key] or {}
+    if command == 'RPUSH' then
+        for _, value in ipairs(args) do
+            table.insert(list, tostring(value))
+        end
+        store[key] = list
+        return #list
+    elseif command == 'LPOP' then
+        if #list == 0 then
+            return false
+        end
+        return table.remove(list, 1)
+    elseif command == 'LLEN' then
+        return #list
+    elseif command == 'LRANGE' then
+        assert(args[1] == 0 and args[2] == -1, 'only whole lists are supported')
+        return {unpack(list)}
+    elseif command == 'LPOS' then
+        for i, value in ipairs(list) do
+            if value == args[1] then
+                return i - 1
+            end
+        end
+        return false
+    elseif command == 'GET' then
+        return store[key] or false
+    elseif command == 'SET' then
+        store[key] = tostring(args[1])
+        return 'OK'
+    end
+    error('unsupported command ' .. command)
+end
+"#;
+
+    struct FakeQueue {
+        lua: Lua,
+        weights: Vec<(Priority, u64)>,
+    }
+
+    impl FakeQueue {
+        fn new(interactive: u64, batch: u64) -> Self {
+            let lua = Lua::new();
+            lua.load(FAKE_REDIS).exec().unwrap();
+            Self {
+                lua,
+                weights: vec![(Priority::Interactive, interactive), (Priority::Batch, batch)],
+            }
+        }
+
+        fn eval(&self, script: &str, keys: Vec<String>, args: Vec<String>) -> Value<'_> {
+            let globals = self.lua.globals();
+            globals.set("KEYS", keys).unwrap();
+            globals.set("ARGV", args).unwrap();
+            self.lua.load(script).eval().unwrap()
+        }
+
+        fn classes(&self) -> Vec<String> {
+            self.weights.iter().map(|(priority, _)| RedisQueue::class_key(*priority)).collect()
+        }
+
+        fn push(&self, priority: Priority, tenant: &str, id: &str) {
+            self.eval(PUSH_SCRIPT, vec![RedisQueue::class_key(priority)], vec![tenant.into(), id.into()]);
+        }
+
+        fn pop(&self) -> Option<String> {
+            let weights = self.weights.iter().map(|(_, weight)| weight.to_string()).collect();
+            match self.eval(POP_SCRIPT, self.classes(), weights) {
+                Value::String(id) => Some(id.to_str().unwrap().to_string()),
+                _ => None,
+            }
+        }
+
+        fn position(&self, priority: Priority, tenant: &str, id: &str) -> i64 {
+            let class = self.weights.iter().position(|(p, _)| *p == priority).unwrap() + 1;
+            let mut args = vec![class.to_string(), tenant.into(), id.into()];
+            args.extend(self.weights.iter().map(|(_, weight)| weight.to_string()));
+            match self.eval(POSITION_SCRIPT, self.classes(), args) {
+                Value::Integer(ahead) => ahead,
+                Value::Number(ahead) => ahead as i64,
+                other => panic!("unexpected reply {:?}", other),
+            }
+        }
+
+        fn depth(&self) -> i64 {
+            match self.eval(DEPTH_SCRIPT, self.classes(), vec![]) {
+                Value::Integer(depth) => depth,
+                Value::Number(depth) => depth as i64,
+                other => panic!("unexpected reply {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn classes_share_pops_by_weight() {
+        let queue = FakeQueue::new(4, 1);
+        for n in 0..10 {
+            queue.push(Priority::Interactive, "ws", &format!("i{}", n));
+            queue.push(Priority::Batch, "ws", &format!("b{}", n));
+        }
+
+        let picked: Vec<String> = (0..10).map(|_| queue.pop().unwrap()).collect();
+        assert_eq!(picked, ["i0", "i1", "i2", "i3", "b0", "i4", "i5", "i6", "i7", "b1"]);
+        assert_eq!(queue.depth(), 10);
+    }
+
+    #[test]
+    fn a_class_on_its_own_gets_every_pop() {
+        let queue = FakeQueue::new(4, 1);
+        for n in 0..3 {
+            queue.push(Priority::Batch, "ws", &format!("b{}", n));
+        }
+
+        let picked: Vec<String> = std::iter::from_fn(|| queue.pop()).collect();
+        assert_eq!(picked, ["b0", "b1", "b2"]);
+        assert_eq!(queue.depth(), 0);
+    }
+
+    #[test]
+    fn tenants_take_turns_within_a_class() {
+        let queue = FakeQueue::new(4, 1);
+        for id in ["a1", "a2", "a3"] {
+            queue.push(Priority::Interactive, "alice", id);
+        }
+        queue.push(Priority::Interactive, "bob", "b1");
+        queue.push(Priority::Interactive, "carol", "c1");
+
+        let picked: Vec<String> = std::iter::from_fn(|| queue.pop()).collect();
+        assert_eq!(picked, ["a1", "b1", "c1", "a2", "a3"]);
+    }
+
+    #[test]
+    fn positions_follow_the_pops() {
+        let queue = FakeQueue::new(4, 1);
+        queue.push(Priority::Interactive, "alice", "a1");
+        queue.push(Priority::Interactive, "alice", "a2");
+        queue.push(Priority::Interactive, "bob", "b1");
+
+        // Picked as a1, b1, a2
+        assert_eq!(queue.position(Priority::Interactive, "alice", "a1"), 0);
+        assert_eq!(queue.position(Priority::Interactive, "bob", "b1"), 1);
+        assert_eq!(queue.position(Priority::Interactive, "alice", "a2"), 2);
+
+        assert_eq!(queue.pop().as_deref(), Some("a1"));
+        assert_eq!(queue.position(Priority::Interactive, "alice", "a1"), -1);
+        assert_eq!(queue.position(Priority::Interactive, "bob", "b1"), 0);
+        assert_eq!(queue.position(Priority::Interactive, "alice", "a2"), 1);
+
+        assert_eq!(queue.pop().as_deref(), Some("b1"));
+        assert_eq!(queue.position(Priority::Interactive, "alice", "a2"), 0);
+    }
+
+    #[test]
+    fn positions_count_the_other_class_share() {
+        let queue = FakeQueue::new(4, 1);
+        for n in 0..3 {
+            queue.push(Priority::Interactive, "ws", &format!("i{}", n));
+        }
+        queue.push(Priority::Batch, "ws", "b0");
+
+        // Each batch pick comes after up to four interactive ones
+        assert_eq!(queue.position(Priority::Batch, "ws", "b0"), 3);
+        assert_eq!(queue.position(Priority::Interactive, "ws", "i2"), 3);
+    }
+}
@@ -0,0 +1,421 @@
+use crate::error::ServiceError;
+use crate::models::ExecutionJob;
+use chrono::{DateTime, Utc};
+use redis::aio::ConnectionManager;
+use redis::Script;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+// Suggested back-off when a tenant is at its concurrency limit; there's no
+// way to know when a running job will finish
+const CONCURRENCY_RETRY_AFTER_SECONDS: u64 = 5;
+
+// Executions run with one CPU and 512MB unless a test case asks for more
+const CPUS_PER_EXECUTION: f64 = 1.0;
+const DEFAULT_MEMORY_MB: u64 = 512;
+
+// How long an admitted job may take to be saved before its in-flight entry
+// counts as abandoned
+const IN_FLIGHT_GRACE_MS: i64 = 60_000;
+
+// Count a tenant's in-flight jobs, dropping ids whose job has finished, or
+// is still missing past the grace, so a lost release can't block the tenant
+// forever. Shared by the scripts below.
+macro_rules! in_flight_lua {
+    () => {
+        r#"
+local function in_flight(key, now, grace)
+    local running = 0
+    local entries = redis.call('ZRANGE', key, 0, -1, 'WITHSCORES')
+    for i = 1, #entries, 2 do
+        local json = redis.call('GET', 'job:' .. entries[i])
+        local live
+        if json then
+            local status = cjson.decode(json).status
+            live = status == 'pending' or status == 'queued' or status == 'running'
+        else
+            live = tonumber(entries[i + 1]) > now - grace
+        end
+        if live then
+            running = running + 1
+        else
+            redis.call('ZREM', key, entries[i])
+        end
+    end
+    return running
+end
+"#
+    };
+}
+
+// Check every tenant's limits and, only if all pass, count the job as in
+// flight, atomically so concurrent submissions can't all slip under a limit.
+// KEYS come in fours per tenant: minute counter, in-flight set, daily CPU and
+// memory. ARGV is the job id, now in ms, the grace, whether to track the job,
+// then each tenant's four limits, empty when unlimited. Returns the 1-based
+// tenant and the limit it hit, or 0.
+const ADMIT_SCRIPT: &str = concat!(
+    in_flight_lua!(),
+    r#"
+local now, grace = tonumber(ARGV[2]), tonumber(ARGV[3])
+for t = 0, #KEYS / 4 - 1 do
+    local rpm_key, in_flight_key, cpu_key, memory_key = KEYS[4 * t + 1], KEYS[4 * t + 2], KEYS[4 * t + 3], KEYS[4 * t + 4]
+    local rpm, concurrent = tonumber(ARGV[4 * t + 5]), tonumber(ARGV[4 * t + 6])
+    local cpu, memory = tonumber(ARGV[4 * t + 7]), tonumber(ARGV[4 * t + 8])
+    if rpm then
+        local count = redis.call('INCR', rpm_key)
+        redis.call('EXPIRE', rpm_key, 60)
+        if count > rpm then
+            return {t + 1, 'rpm'}
+        end
+    end
+    if concurrent and in_flight(in_flight_key, now, grace) >= concurrent then
+        return {t + 1, 'concurrent'}
+    end
+    if cpu and tonumber(redis.call('GET', cpu_key) or '0') >= cpu then
+        return {t + 1, 'cpu'}
+    end
+    if memory and tonumber(redis.call('GET', memory_key) or '0') >= memory then
+        return {t + 1, 'memory'}
+    end
+end
+if ARGV[4] == '1' then
+    for t = 0, #KEYS / 4 - 1 do
+        redis.call('ZADD', KEYS[4 * t + 2], now, ARGV[1])
+    end
+end
+return {0, ''}
+"#
+);
+
+const RUNNING_SCRIPT: &str = concat!(
+    in_flight_lua!(),
+    "return in_flight(KEYS[1], tonumber(ARGV[1]), tonumber(ARGV[2]))"
+);
+
+/// Limits applied to a single user or workspace. `None` means unlimited.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, ToSchema)]
+pub struct QuotaLimits {
+    pub requests_per_minute: Option<u64>,
+    pub max_concurrent: Option<u64>,
+    pub daily_cpu_seconds: Option<u64>,
+    pub daily_memory_mb_seconds: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum QuotaScope {
+    User,
+    Workspace,
+}
+
+impl QuotaScope {
+    fn as_str(&self) -> &'static str {
+        match self {
+            QuotaScope::User => "user",
+            QuotaScope::Workspace => "workspace",
+        }
+    }
+}
+
+/// Quotas for executions, enforced at submission and charged when a job
+/// finishes. A job counts against both its user and its workspace.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QuotaPolicy {
+    pub user: QuotaLimits,
+    pub workspace: QuotaLimits,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct QuotaUsage {
+    pub scope: QuotaScope,
+    pub id: String,
+    pub requests_this_minute: u64,
+    pub running: u64,
+    pub cpu_seconds_today: u64,
+    pub memory_mb_seconds_today: u64,
+    pub limits: QuotaLimits,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct UsageReport {
+    pub user: QuotaUsage,
+    pub workspace: Option<QuotaUsage>,
+}
+
+fn key(scope: QuotaScope, id: &str, counter: &str) -> String {
+    format!("quota:{}:{}:{}", scope.as_str(), id, counter)
+}
+
+fn minute_key(scope: QuotaScope, id: &str, now: DateTime<Utc>) -> String {
+    key(scope, id, &format!("rpm:{}", now.format("%Y%m%d%H%M")))
+}
+
+fn day_key(scope: QuotaScope, id: &str, counter: &str, now: DateTime<Utc>) -> String {
+    key(scope, id, &format!("{}:{}", counter, now.format("%Y%m%d")))
+}
+
+// Ids of a tenant's queued and running jobs, scored by admission time in ms
+fn in_flight_key(scope: QuotaScope, id: &str) -> String {
+    key(scope, id, "inflight")
+}
+
+fn seconds_until_next_minute(now: DateTime<Utc>) -> u64 {
+    60 - now.timestamp() as u64 % 60
+}
+
+fn seconds_until_midnight(now: DateTime<Utc>) -> u64 {
+    86_400 - now.timestamp() as u64 % 86_400
+}
+
+fn rejected(reason: String, retry_after: u64) -> ServiceError {
+    ServiceError::QuotaExceeded { reason, retry_after }
+}
+
+// The rejection for a tenant that hit `exceeded`, as named by ADMIT_SCRIPT
+fn rejection(scope: QuotaScope, id: &str, limits: &QuotaLimits, exceeded: &str, now: DateTime<Utc>) -> ServiceError {
+    let scope = scope.as_str();
+    match exceeded {
+        "rpm" => rejected(
+            format!("{} {} exceeded {} requests per minute", scope, id, limits.requests_per_minute.unwrap_or(0)),
+            seconds_until_next_minute(now),
+        ),
+        "concurrent" => rejected(
+            format!("{} {} already has {} executions in flight", scope, id, limits.max_concurrent.unwrap_or(0)),
+            CONCURRENCY_RETRY_AFTER_SECONDS,
+        ),
+        "cpu" => rejected(
+            format!("{} {} used its daily CPU-seconds budget", scope, id),
+            seconds_until_midnight(now),
+        ),
+        _ => rejected(
+            format!("{} {} used its daily memory budget", scope, id),
+            seconds_until_midnight(now),
+        ),
+    }
+}
+
+impl QuotaPolicy {
+    pub fn limits(&self, scope: QuotaScope) -> QuotaLimits {
+        match scope {
+            QuotaScope::User => self.user,
+            QuotaScope::Workspace => self.workspace,
+        }
+    }
+
+    fn tenants<'a>(&self, job: &'a ExecutionJob) -> Vec<(QuotaScope, &'a str)> {
+        let mut tenants = vec![];
+        if let Some(user_id) = &job.user_id {
+            tenants.push((QuotaScope::User, user_id.as_str()));
+        }
+        if let Some(workspace_id) = &job.workspace_id {
+            tenants.push((QuotaScope::Workspace, workspace_id.as_str()));
+        }
+        tenants
+    }
+
+    /// Count a submission against the job's tenants and reject it if any
+    /// limit is already reached. With `track`, an admitted job also counts
+    /// as in flight from here on.
+    pub async fn admit(
+        &self,
+        redis: &mut ConnectionManager,
+        job: &ExecutionJob,
+        track: bool,
+    ) -> Result<(), ServiceError> {
+        let now = Utc::now();
+        let tenants = self.tenants(job);
+
+        let script = Script::new(ADMIT_SCRIPT);
+        let mut invocation = script.prepare_invoke();
+        invocation
+            .arg(job.id.to_string())
+            .arg(now.timestamp_millis())
+            .arg(IN_FLIGHT_GRACE_MS)
+            .arg(if track { "1" } else { "0" });
+        for &(scope, id) in &tenants {
+            let limits = self.limits(scope);
+            invocation
+                .key(minute_key(scope, id, now))
+                .key(in_flight_key(scope, id))
+                .key(day_key(scope, id, "cpu", now))
+                .key(day_key(scope, id, "memory", now));
+            for limit in [
+                limits.requests_per_minute,
+                limits.max_concurrent,
+                limits.daily_cpu_seconds,
+                limits.daily_memory_mb_seconds,
+            ] {
+                invocation.arg(limit.map(|limit| limit.to_string()).unwrap_or_default());
+            }
+        }
+
+        let (tenant, exceeded): (usize, String) = invocation.invoke_async(redis).await?;
+        match tenant.checked_sub(1).and_then(|index| tenants.get(index)) {
+            Some(&(scope, id)) => Err(rejection(scope, id, &self.limits(scope), &exceeded, now)),
+            None => Ok(()),
+        }
+    }
+
+    /// Record a job as in flight for its tenants, e.g. a scheduled one whose
+    /// time has come.
+    pub fn track(&self, pipe: &mut redis::Pipeline, job: &ExecutionJob) {
+        let now = Utc::now().timestamp_millis();
+        for (scope, id) in self.tenants(job) {
+            pipe.cmd("ZADD").arg(in_flight_key(scope, id)).arg(now).arg(job.id.to_string()).ignore();
+        }
+    }
+
+    /// Release a finished job and charge the compute it used, cancelled
+    /// running jobs included.
+    pub async fn release(&self, redis: &mut ConnectionManager, job: &ExecutionJob) -> Result<(), ServiceError> {
+        let now = Utc::now();
+        let mut pipe = redis::pipe();
+        for (scope, id) in self.tenants(job) {
+            pipe.cmd("ZREM").arg(in_flight_key(scope, id)).arg(job.id.to_string()).ignore();
+        }
+
+        if let Some((cpu_seconds, memory_mb_seconds)) = compute_used(job) {
+            for (scope, id) in self.tenants(job) {
+                for (counter, amount) in [("cpu", cpu_seconds), ("memory", memory_mb_seconds)] {
+                    let usage_key = day_key(scope, id, counter, now);
+                    pipe.cmd("INCRBY").arg(&usage_key).arg(amount).ignore()
+                        .cmd("EXPIRE").arg(&usage_key).arg(2 * 86_400).ignore();
+                }
+            }
+        }
+
+        pipe.query_async::<_, ()>(redis).await?;
+        Ok(())
+    }
+
+    pub async fn usage(
+        &self,
+        redis: &mut ConnectionManager,
+        scope: QuotaScope,
+        id: &str,
+    ) -> Result<QuotaUsage, ServiceError> {
+        let now = Utc::now();
+        let running = running(redis, scope, id).await?;
+        let (requests, cpu, memory): (Option<u64>, Option<u64>, Option<u64>) = redis::pipe()
+            .cmd("GET").arg(minute_key(scope, id, now))
+            .cmd("GET").arg(day_key(scope, id, "cpu", now))
+            .cmd("GET").arg(day_key(scope, id, "memory", now))
+            .query_async(redis)
+            .await?;
+
+        Ok(QuotaUsage {
+            scope,
+            id: id.to_string(),
+            requests_this_minute: requests.unwrap_or(0),
+            running,
+            cpu_seconds_today: cpu.unwrap_or(0),
+            memory_mb_seconds_today: memory.unwrap_or(0),
+            limits: self.limits(scope),
+        })
+    }
+}
+
+async fn running(redis: &mut ConnectionManager, scope: QuotaScope, id: &str) -> Result<u64, ServiceError> {
+    Ok(Script::new(RUNNING_SCRIPT)
+        .key(in_flight_key(scope, id))
+        .arg(Utc::now().timestamp_millis())
+        .arg(IN_FLIGHT_GRACE_MS)
+        .invoke_async(redis)
+        .await?)
+}
+
+// CPU seconds and MB-seconds a job held its container for, from start to
+// finish. Jobs that never started cost nothing.
+fn compute_used(job: &ExecutionJob) -> Option<(u64, u64)> {
+    let (started, completed) = (job.started_at?, job.completed_at?);
+    let seconds = (completed - started).num_milliseconds().max(0) as f64 / 1000.0;
+    let memory_mb = job.request.test_cases
+        .iter()
+        .flatten()
+        .filter_map(|case| case.memory_mb)
+        .max()
+        .unwrap_or(DEFAULT_MEMORY_MB);
+    let cpu_seconds = (seconds * CPUS_PER_EXECUTION).ceil() as u64;
+    let memory_mb_seconds = (seconds * memory_mb as f64).ceil() as u64;
+    Some((cpu_seconds, memory_mb_seconds))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::JobStatus;
+    use chrono::TimeZone;
+
+    fn at(hour: u32, minute: u32, second: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 3, 9, hour, minute, second).unwrap()
+    }
+
+    fn retry_after(error: ServiceError) -> (String, u64) {
+        match error {
+            ServiceError::QuotaExceeded { reason, retry_after } => (reason, retry_after),
+            other => panic!("unexpected error {:?}", other),
+        }
+    }
+
+    #[test]
+    fn counters_are_keyed_per_tenant_and_window() {
+        let now = at(13, 7, 42);
+        assert_eq!(minute_key(QuotaScope::User, "alice", now), "quota:user:alice:rpm:202403091307");
+        assert_eq!(day_key(QuotaScope::Workspace, "ws", "cpu", now), "quota:workspace:ws:cpu:20240309");
+        assert_eq!(in_flight_key(QuotaScope::User, "alice"), "quota:user:alice:inflight");
+    }
+
+    #[test]
+    fn windows_reset_on_the_boundary() {
+        assert_eq!(seconds_until_next_minute(at(13, 7, 42)), 18);
+        assert_eq!(seconds_until_next_minute(at(13, 7, 0)), 60);
+        assert_eq!(seconds_until_midnight(at(23, 59, 30)), 30);
+        assert_eq!(seconds_until_midnight(at(0, 0, 0)), 86_400);
+    }
+
+    #[test]
+    fn rejections_name_the_limit_and_when_to_retry() {
+        let limits = QuotaLimits {
+            requests_per_minute: Some(10),
+            max_concurrent: Some(2),
+            ..Default::default()
+        };
+        let now = at(23, 59, 30);
+        let rejection = |exceeded| retry_after(rejection(QuotaScope::User, "alice", &limits, exceeded, now));
+
+        assert_eq!(rejection("rpm"), ("user alice exceeded 10 requests per minute".to_string(), 30));
+        assert_eq!(
+            rejection("concurrent"),
+            ("user alice already has 2 executions in flight".to_string(), CONCURRENCY_RETRY_AFTER_SECONDS)
+        );
+        assert_eq!(rejection("cpu"), ("user alice used its daily CPU-seconds budget".to_string(), 30));
+        assert_eq!(rejection("memory"), ("user alice used its daily memory budget".to_string(), 30));
+    }
+
+    #[test]
+    fn jobs_count_against_their_user_and_workspace() {
+        let mut job = ExecutionJob::new(serde_json::from_value(serde_json::json!({"code": "", "language": "python"})).unwrap());
+        assert!(QuotaPolicy::default().tenants(&job).is_empty());
+
+        job.user_id = Some("alice".to_string());
+        job.workspace_id = Some("ws".to_string());
+        assert_eq!(
+            QuotaPolicy::default().tenants(&job),
+            vec![(QuotaScope::User, "alice"), (QuotaScope::Workspace, "ws")]
+        );
+    }
+
+    #[test]
+    fn cancelled_running_jobs_are_charged_for_the_time_they_ran() {
+        let mut job = ExecutionJob::new(serde_json::from_value(serde_json::json!({"code": "", "language": "python"})).unwrap());
+        job.status = JobStatus::Cancelled;
+        job.completed_at = Some(at(13, 0, 10));
+        assert_eq!(compute_used(&job), None, "never started, nothing to charge");
+
+        job.started_at = Some(at(13, 0, 0));
+        assert!(job.result.is_none());
+        assert_eq!(compute_used(&job), Some((10, 10 * DEFAULT_MEMORY_MB)));
+    }
+}
@@ -0,0 +1,189 @@
+use crate::docker::{DockerClient, ManagedContainer, WORKSPACE_PREFIX};
+use crate::error::ServiceError;
+use crate::models::JobStatus;
+use crate::state::ServiceState;
+use std::fs::FileType;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+/// Periodically remove service-owned containers that are past their
+/// deadline or whose job is unknown or already finished.
+pub async fn run_reaper(state: Arc<ServiceState>, interval: u64) {
+    info!("Starting container reaper (every {}s)", interval);
+
+    let docker = match DockerClient::new().await {
+        Ok(docker) => docker,
+        Err(e) => {
+            error!("Container reaper disabled: {}", e);
+            return;
+        }
+    };
+
+    let mut ticker = tokio::time::interval(Duration::from_secs(interval));
+    loop {
+        ticker.tick().await;
+        if let Err(e) = reap_containers(&state, &docker).await {
+            error!("Container reaper failed: {}", e);
+        }
+    }
+}
+
+async fn reap_containers(state: &ServiceState, docker: &DockerClient) -> anyhow::Result<()> {
+    let now = chrono::Utc::now().timestamp();
+
+    for container in docker.list_managed_containers().await? {
+        let reason = match stale_reason(state, &container, now).await {
+            Some(reason) => reason,
+            None => continue,
+        };
+
+        warn!("Reaping container {} ({})", container.name, reason);
+        if let Err(e) = docker.remove_container(&container.name).await {
+            error!("Failed to reap container {}: {}", container.name, e);
+        }
+    }
+
+    Ok(())
+}
+
+async fn stale_reason(
+    state: &ServiceState,
+    container: &ManagedContainer,
+    now: i64,
+) -> Option<&'static str> {
+    if past_deadline(container, now) {
+        return Some("past deadline");
+    }
+
+    let job_id = match container.job_id {
+        Some(job_id) => job_id,
+        None => return Some("no job id"),
+    };
+
+    let lookup = state.get_execution(job_id).await.map(|job| job.status);
+    if let Err(e) = &lookup {
+        if !matches!(e, ServiceError::NotFound) {
+            warn!("Failed to look up job {} for container {}: {}", job_id, container.name, e);
+        }
+    }
+    job_verdict(lookup)
+}
+
+// A missing or unreadable deadline proves nothing; whether the job is still
+// live decides instead
+fn past_deadline(container: &ManagedContainer, now: i64) -> bool {
+    container.deadline.is_some_and(|deadline| deadline < now)
+}
+
+fn job_verdict(lookup: Result<JobStatus, ServiceError>) -> Option<&'static str> {
+    match lookup {
+        Ok(JobStatus::Pending | JobStatus::Queued | JobStatus::Running) => None,
+        Ok(_) => Some("job already finished"),
+        Err(ServiceError::NotFound) => Some("unknown job"),
+        // Can't tell; leave it for the next pass
+        Err(_) => None,
+    }
+}
+
+/// Remove workspace directories left behind by a previous run. Anything
+/// else under `root` is left alone, since the root may be shared. Only
+/// call this before any execution has started.
+pub fn cleanup_workspaces(root: &Path) {
+    let entries = match std::fs::read_dir(root) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return,
+        Err(e) => {
+            error!("Failed to read workspace root {}: {}", root.display(), e);
+            return;
+        }
+    };
+
+    for entry in entries.flatten() {
+        let is_workspace = entry
+            .file_type()
+            .map(|file_type| is_workspace(&entry.file_name().to_string_lossy(), file_type))
+            .unwrap_or(false);
+        if !is_workspace {
+            continue;
+        }
+        let path = entry.path();
+        match std::fs::remove_dir_all(&path) {
+            Ok(()) => info!("Removed orphaned workspace {}", path.display()),
+            Err(e) => warn!("Failed to remove orphaned workspace {}: {}", path.display(), e),
+        }
+    }
+}
+
+// Workspaces are plain directories named by `docker::create_workspace`;
+// symlinks are never followed
+fn is_workspace(name: &str, file_type: FileType) -> bool {
+    file_type.is_dir() && name.starts_with(WORKSPACE_PREFIX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::docker;
+
+    fn container(deadline: Option<i64>) -> ManagedContainer {
+        ManagedContainer {
+            name: "execution-test".to_string(),
+            job_id: Some(uuid::Uuid::new_v4()),
+            deadline,
+        }
+    }
+
+    #[test]
+    fn only_a_passed_deadline_counts_as_expired() {
+        assert!(past_deadline(&container(Some(99)), 100));
+        assert!(!past_deadline(&container(Some(100)), 100));
+        assert!(!past_deadline(&container(Some(101)), 100));
+        assert!(!past_deadline(&container(None), 100));
+    }
+
+    #[test]
+    fn live_jobs_keep_their_containers() {
+        for status in [JobStatus::Pending, JobStatus::Queued, JobStatus::Running] {
+            assert_eq!(job_verdict(Ok(status)), None);
+        }
+    }
+
+    #[test]
+    fn finished_and_unknown_jobs_are_reaped() {
+        for status in [JobStatus::Completed, JobStatus::Failed, JobStatus::Timeout, JobStatus::Cancelled] {
+            assert_eq!(job_verdict(Ok(status)), Some("job already finished"));
+        }
+        assert_eq!(job_verdict(Err(ServiceError::NotFound)), Some("unknown job"));
+    }
+
+    #[test]
+    fn failed_lookups_leave_the_container() {
+        let error = ServiceError::Internal(anyhow::anyhow!("redis down"));
+        assert_eq!(job_verdict(Err(error)), None);
+    }
+
+    #[test]
+    fn cleanup_only_removes_workspaces() {
+        let root = tempfile::tempdir().unwrap();
+        let handle = docker::create_workspace(root.path()).unwrap();
+        let workspace = handle.path().to_path_buf();
+        std::fs::write(workspace.join("main.py"), "print(1)").unwrap();
+        let unrelated_dir = root.path().join("someone-elses");
+        std::fs::create_dir(&unrelated_dir).unwrap();
+        let unrelated_file = root.path().join(format!("{}notes.txt", WORKSPACE_PREFIX));
+        std::fs::write(&unrelated_file, "keep").unwrap();
+
+        cleanup_workspaces(root.path());
+
+        assert!(!workspace.exists());
+        assert!(unrelated_dir.exists());
+        assert!(unrelated_file.exists());
+    }
+
+    #[test]
+    fn cleanup_tolerates_a_missing_root() {
+        cleanup_workspaces(Path::new("/nonexistent/syla-workspaces"));
+    }
+}
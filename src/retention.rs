@@ -0,0 +1,73 @@
+use crate::models::ExecutionJob;
+use crate::state::{ServiceState, EXECUTION_INDEX_KEY};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, info};
+
+const DEFAULT_JOB_TTL_SECONDS: u64 = 7 * 24 * 60 * 60; // 7 days
+
+/// How long finished executions stay in Redis. Queued and running jobs
+/// never expire; the TTL starts once a job reaches a terminal status.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetentionPolicy {
+    pub job_ttl_seconds: u64,
+    // Keyed by workspace id or user id; a workspace override wins
+    pub overrides: HashMap<String, u64>,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        Self {
+            job_ttl_seconds: DEFAULT_JOB_TTL_SECONDS,
+            overrides: HashMap::new(),
+        }
+    }
+}
+
+impl RetentionPolicy {
+    pub fn ttl_for(&self, job: &ExecutionJob) -> u64 {
+        job.workspace_id
+            .as_ref()
+            .and_then(|ws| self.overrides.get(ws))
+            .or_else(|| job.user_id.as_ref().and_then(|user| self.overrides.get(user)))
+            .copied()
+            .unwrap_or(self.job_ttl_seconds)
+    }
+
+    /// The longest any job can be retained; index entries older than this
+    /// can only point at expired jobs.
+    pub fn max_ttl(&self) -> u64 {
+        self.overrides
+            .values()
+            .copied()
+            .chain(std::iter::once(self.job_ttl_seconds))
+            .max()
+            .unwrap_or(self.job_ttl_seconds)
+    }
+}
+
+/// Periodically drop index entries that can only reference expired jobs.
+pub async fn run_sweeper(state: Arc<ServiceState>) {
+    let mut ticker = tokio::time::interval(Duration::from_secs(60 * 60));
+    loop {
+        ticker.tick().await;
+
+        let cutoff = chrono::Utc::now().timestamp_millis() - state.retention.max_ttl() as i64 * 1000;
+        let mut redis = state.redis.lock().await;
+        let removed: Result<u64, _> = redis::cmd("ZREMRANGEBYSCORE")
+            .arg(EXECUTION_INDEX_KEY)
+            .arg("-inf")
+            .arg(cutoff)
+            .query_async(&mut *redis)
+            .await;
+
+        match removed {
+            Ok(0) => {}
+            Ok(n) => info!("Pruned {} expired executions from the index", n),
+            Err(e) => error!("Retention sweep failed: {}", e),
+        }
+    }
+}
@@ -0,0 +1,142 @@
+use crate::error::ServiceError;
+use crate::models::Schedule;
+use crate::state::ServiceState;
+use chrono::{DateTime, Utc};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+const SCHEDULER_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Parse a cron expression. Standard five-field expressions are accepted
+/// alongside the six- and seven-field forms with seconds (and years).
+pub fn parse_cron(expression: &str) -> Result<cron::Schedule, ServiceError> {
+    let expression = expression.trim();
+    let normalized = if expression.split_whitespace().count() == 5 {
+        format!("0 {}", expression)
+    } else {
+        expression.to_string()
+    };
+    cron::Schedule::from_str(&normalized)
+        .map_err(|e| ServiceError::InvalidRequest(format!("Invalid cron expression: {}", e)))
+}
+
+/// The first time the schedule fires after `after`.
+pub fn next_run(cron: &cron::Schedule, after: DateTime<Utc>) -> Result<DateTime<Utc>, ServiceError> {
+    cron.after(&after)
+        .next()
+        .ok_or_else(|| ServiceError::InvalidRequest("Cron expression never fires".to_string()))
+}
+
+/// Release delayed executions and fire recurring schedules when they're due.
+/// Stops once the instance starts draining, leaving them to the others.
+pub async fn run_scheduler(state: Arc<ServiceState>) {
+    info!("Starting scheduler");
+
+    let mut ticker = tokio::time::interval(SCHEDULER_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = state.lifecycle.draining() => break,
+        }
+
+        match state.release_due_executions().await {
+            Ok(0) => {}
+            Ok(n) => info!("Queued {} delayed executions", n),
+            Err(e) => error!("Failed to release delayed executions: {}", e),
+        }
+
+        match state.claim_due_schedules().await {
+            Ok(schedules) => {
+                for schedule in schedules {
+                    if let Err(e) = fire(&state, schedule).await {
+                        error!("Failed to run schedule: {}", e);
+                    }
+                }
+            }
+            Err(e) => error!("Failed to load due schedules: {}", e),
+        }
+    }
+    info!("Scheduler stopped");
+}
+
+// Create this run's execution and re-arm the schedule. Runs missed while the
+// service was down are skipped rather than replayed.
+async fn fire(state: &ServiceState, mut schedule: Schedule) -> Result<(), ServiceError> {
+    // Draining started since the claim: put the schedule back as it was for
+    // another instance to run
+    if state.lifecycle.is_draining() {
+        return rearm(state, &schedule).await;
+    }
+
+    let now = Utc::now();
+    let mut request = schedule.request.clone();
+    request.not_before = None;
+
+    match state
+        .create_execution(request, schedule.user_id.clone(), schedule.workspace_id.clone())
+        .await
+    {
+        Ok(job) => {
+            info!("Schedule {} started execution {}", schedule.id, job.id);
+            schedule.last_execution_id = Some(job.id);
+        }
+        // A rejected run (e.g. over quota) doesn't cancel the schedule
+        Err(e) => warn!("Schedule {} could not start an execution: {}", schedule.id, e),
+    }
+    schedule.last_run_at = Some(now);
+    schedule.next_run_at = next_run(&parse_cron(&schedule.cron)?, now)?;
+    rearm(state, &schedule).await
+}
+
+async fn rearm(state: &ServiceState, schedule: &Schedule) -> Result<(), ServiceError> {
+    // Don't resurrect a schedule deleted while this run was starting
+    match state.get_schedule(schedule.id).await {
+        Ok(_) => state.save_schedule(schedule).await,
+        Err(ServiceError::NotFound) => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(hour: u32, minute: u32, second: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 3, 9, hour, minute, second).unwrap()
+    }
+
+    #[test]
+    fn five_field_expressions_fire_on_the_minute() {
+        let cron = parse_cron("*/15 * * * *").unwrap();
+        assert_eq!(next_run(&cron, at(13, 7, 42)).unwrap(), at(13, 15, 0));
+    }
+
+    #[test]
+    fn six_field_expressions_keep_their_seconds() {
+        let cron = parse_cron("30 * * * * *").unwrap();
+        assert_eq!(next_run(&cron, at(13, 7, 42)).unwrap(), at(13, 8, 30));
+    }
+
+    #[test]
+    fn next_run_is_strictly_after() {
+        let cron = parse_cron("  0 9 * * *  ").unwrap();
+        assert_eq!(next_run(&cron, at(9, 0, 0)).unwrap(), Utc.with_ymd_and_hms(2024, 3, 10, 9, 0, 0).unwrap());
+        assert_eq!(next_run(&cron, at(8, 59, 59)).unwrap(), at(9, 0, 0));
+    }
+
+    #[test]
+    fn invalid_expressions_are_rejected() {
+        for expression in ["", "* * *", "61 * * * *", "not a cron"] {
+            assert!(matches!(parse_cron(expression), Err(ServiceError::InvalidRequest(_))), "{expression:?}");
+        }
+    }
+
+    #[test]
+    fn schedules_in_the_past_never_fire() {
+        let cron = parse_cron("0 0 0 1 1 * 2000").unwrap();
+        assert!(matches!(next_run(&cron, at(0, 0, 0)), Err(ServiceError::InvalidRequest(_))));
+    }
+}
@@ -0,0 +1,268 @@
+use crate::docker::{CapturedOutput, ExecutionResult};
+use crate::error::ServiceError;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use anyhow::Context;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+// Written over every occurrence of a secret value in program output
+pub const REDACTED: &[u8] = b"[REDACTED]";
+
+const MAX_NAME_LENGTH: usize = 128;
+const MAX_VALUE_BYTES: usize = 64 * 1024;
+const NONCE_LEN: usize = 12;
+
+/// Secrets belong to the workspace a job runs in, or to its user when it
+/// has none.
+pub fn scope(user_id: Option<&str>, workspace_id: Option<&str>) -> Option<String> {
+    match (workspace_id, user_id) {
+        (Some(workspace_id), _) => Some(format!("workspace:{}", workspace_id)),
+        (None, Some(user_id)) => Some(format!("user:{}", user_id)),
+        (None, None) => None,
+    }
+}
+
+// Hash of secret name -> `StoredSecret`
+pub fn secrets_key(scope: &str) -> String {
+    format!("secrets:{}", scope)
+}
+
+pub fn validate_name(name: &str) -> Result<(), String> {
+    if name.is_empty() || name.len() > MAX_NAME_LENGTH {
+        return Err(format!("Secret names must be 1 to {} characters", MAX_NAME_LENGTH));
+    }
+    if !name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.')) {
+        return Err("Secret names may contain only letters, digits, `_`, `-` and `.`".to_string());
+    }
+    Ok(())
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct PutSecretRequest {
+    pub value: String,
+}
+
+/// A stored secret as clients see it; values are never returned.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct SecretMetadata {
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StoredSecret {
+    // Base64 of nonce followed by AES-256-GCM ciphertext
+    pub ciphertext: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Encrypts secret values for storage under the configured key.
+/// Each value is bound to its scope and name, so ciphertexts can't be
+/// moved between secrets.
+pub struct SecretCipher {
+    // Secrets are disabled when no key is configured
+    cipher: Option<Aes256Gcm>,
+}
+
+impl SecretCipher {
+    /// `key` is 32 bytes encoded as base64.
+    pub fn new(key: Option<&str>) -> anyhow::Result<Self> {
+        let Some(encoded) = key else {
+            return Ok(Self { cipher: None });
+        };
+        let key = BASE64.decode(encoded.trim()).context("secrets.encryption_key is not valid base64")?;
+        let cipher = Aes256Gcm::new_from_slice(&key)
+            .map_err(|_| anyhow::anyhow!("secrets.encryption_key must decode to 32 bytes"))?;
+        Ok(Self { cipher: Some(cipher) })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.cipher.is_some()
+    }
+
+    fn cipher(&self) -> Result<&Aes256Gcm, ServiceError> {
+        self.cipher
+            .as_ref()
+            .ok_or_else(|| ServiceError::Conflict("Secrets are not enabled on this server".to_string()))
+    }
+
+    pub fn encrypt(&self, scope: &str, name: &str, value: &str) -> Result<String, ServiceError> {
+        if value.len() > MAX_VALUE_BYTES {
+            return Err(ServiceError::InvalidRequest(format!(
+                "Secret values must be at most {} bytes",
+                MAX_VALUE_BYTES
+            )));
+        }
+        let aad = format!("{}/{}", scope, name);
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher()?
+            .encrypt(&nonce, Payload { msg: value.as_bytes(), aad: aad.as_bytes() })
+            .map_err(|_| ServiceError::Internal(anyhow::anyhow!("Failed to encrypt secret")))?;
+
+        let mut stored = nonce.to_vec();
+        stored.extend_from_slice(&ciphertext);
+        Ok(BASE64.encode(stored))
+    }
+
+    pub fn decrypt(&self, scope: &str, name: &str, stored: &str) -> Result<String, ServiceError> {
+        let undecryptable = || ServiceError::Conflict(format!("Secret `{}` can't be decrypted with the current key", name));
+        let stored = BASE64.decode(stored).map_err(|_| undecryptable())?;
+        if stored.len() < NONCE_LEN {
+            return Err(undecryptable());
+        }
+        let (nonce, ciphertext) = stored.split_at(NONCE_LEN);
+        let aad = format!("{}/{}", scope, name);
+        let plaintext = self
+            .cipher()?
+            .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: aad.as_bytes() })
+            .map_err(|_| undecryptable())?;
+        String::from_utf8(plaintext).map_err(|_| undecryptable())
+    }
+}
+
+/// Scrubs secret values from program output.
+#[derive(Debug, Default)]
+pub struct Redactor {
+    // Longest first, so overlapping secrets are covered by the longer one
+    values: Vec<Vec<u8>>,
+}
+
+impl Redactor {
+    pub fn new<'a>(values: impl IntoIterator<Item = &'a String>) -> Self {
+        let mut values: Vec<Vec<u8>> = values
+            .into_iter()
+            .filter(|value| !value.is_empty())
+            .map(|value| value.as_bytes().to_vec())
+            .collect();
+        values.sort_by_key(|value| std::cmp::Reverse(value.len()));
+        values.dedup();
+        Self { values }
+    }
+
+    /// Redact `data`, stopping early at any point where the rest of it could
+    /// be the beginning of a secret when `more_coming`. Returns the redacted
+    /// output and how many bytes of `data` it covers; the remainder should be
+    /// retried once more data has arrived.
+    pub fn redact_until(&self, data: &[u8], more_coming: bool) -> (Vec<u8>, usize) {
+        if self.values.is_empty() {
+            return (data.to_vec(), data.len());
+        }
+        let mut output = Vec::with_capacity(data.len());
+        let mut i = 0;
+        while i < data.len() {
+            let rest = &data[i..];
+            if more_coming && self.values.iter().any(|value| value.len() > rest.len() && value.starts_with(rest)) {
+                return (output, i);
+            }
+            match self.values.iter().find(|value| rest.starts_with(value)) {
+                Some(value) => {
+                    output.extend_from_slice(REDACTED);
+                    i += value.len();
+                }
+                None => {
+                    output.push(data[i]);
+                    i += 1;
+                }
+            }
+        }
+        (output, data.len())
+    }
+
+    // A truncated stream may end partway through a secret; that part is dropped
+    fn redact_output(&self, output: &mut CapturedOutput) {
+        if self.values.is_empty() {
+            return;
+        }
+        output.data = self.redact_until(&output.data, output.truncated).0;
+    }
+
+    pub fn redact_result(&self, result: &mut ExecutionResult) {
+        self.redact_output(&mut result.stdout);
+        self.redact_output(&mut result.stderr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cipher() -> SecretCipher {
+        SecretCipher::new(Some(&BASE64.encode([7u8; 32]))).unwrap()
+    }
+
+    fn redactor(values: &[&str]) -> Redactor {
+        let values: Vec<String> = values.iter().map(|value| value.to_string()).collect();
+        Redactor::new(&values)
+    }
+
+    #[test]
+    fn values_round_trip() {
+        let cipher = cipher();
+        let stored = cipher.encrypt("workspace:ws", "API_TOKEN", "s3cret").unwrap();
+        assert!(!stored.contains("s3cret"));
+        assert_eq!(cipher.decrypt("workspace:ws", "API_TOKEN", &stored).unwrap(), "s3cret");
+        // Fresh nonce every time
+        assert_ne!(cipher.encrypt("workspace:ws", "API_TOKEN", "s3cret").unwrap(), stored);
+    }
+
+    #[test]
+    fn ciphertexts_are_bound_to_their_scope_and_name() {
+        let cipher = cipher();
+        let stored = cipher.encrypt("workspace:ws", "API_TOKEN", "s3cret").unwrap();
+        for (scope, name) in [("workspace:other", "API_TOKEN"), ("user:ws", "API_TOKEN"), ("workspace:ws", "DB_PASSWORD")] {
+            assert!(matches!(cipher.decrypt(scope, name, &stored), Err(ServiceError::Conflict(_))), "{}/{}", scope, name);
+        }
+    }
+
+    #[test]
+    fn tampered_ciphertexts_are_rejected() {
+        let cipher = cipher();
+        let mut bytes = BASE64.decode(cipher.encrypt("user:alice", "TOKEN", "s3cret").unwrap()).unwrap();
+        *bytes.last_mut().unwrap() ^= 1;
+        assert!(cipher.decrypt("user:alice", "TOKEN", &BASE64.encode(&bytes)).is_err());
+        assert!(cipher.decrypt("user:alice", "TOKEN", "c2hvcnQ=").is_err());
+
+        let other = SecretCipher::new(Some(&BASE64.encode([8u8; 32]))).unwrap();
+        let stored = cipher.encrypt("user:alice", "TOKEN", "s3cret").unwrap();
+        assert!(other.decrypt("user:alice", "TOKEN", &stored).is_err());
+    }
+
+    #[test]
+    fn keys_must_be_32_bytes_of_base64() {
+        assert!(SecretCipher::new(Some(&BASE64.encode([7u8; 16]))).is_err());
+        assert!(SecretCipher::new(Some("not base64!")).is_err());
+        let disabled = SecretCipher::new(None).unwrap();
+        assert!(!disabled.is_enabled());
+        assert!(matches!(disabled.encrypt("user:alice", "TOKEN", "x"), Err(ServiceError::Conflict(_))));
+    }
+
+    #[test]
+    fn every_occurrence_is_redacted() {
+        let (output, consumed) = redactor(&["hunter2"]).redact_until(b"pw=hunter2, again hunter2", false);
+        assert_eq!(output, b"pw=[REDACTED], again [REDACTED]");
+        assert_eq!(consumed, 25);
+    }
+
+    #[test]
+    fn a_possible_secret_start_is_held_back() {
+        let redactor = redactor(&["hunter2"]);
+        let (output, consumed) = redactor.redact_until(b"pw=hunt", true);
+        assert_eq!((output.as_slice(), consumed), (&b"pw="[..], 3));
+
+        // Once the stream ends there is nothing left to wait for
+        let (output, consumed) = redactor.redact_until(b"pw=hunt", false);
+        assert_eq!((output.as_slice(), consumed), (&b"pw=hunt"[..], 7));
+    }
+
+    #[test]
+    fn longer_secrets_win_over_their_prefixes() {
+        let (output, _) = redactor(&["abc", "abcdef"]).redact_until(b"abcdef abc", false);
+        assert_eq!(output, b"[REDACTED] [REDACTED]");
+    }
+}
@@ -1,56 +1,1124 @@
+use crate::archive::ExecutionArchive;
+use crate::cache::{self, CachedResult, ResultCache};
+use crate::dlq::{DeadLetterQueue, DeadLetterSelection, ReplaySummary, SkippedDeadLetter};
 use crate::error::ServiceError;
-use crate::models::{CreateExecutionRequest, ExecutionJob};
+use crate::events::{self, EventKind, ExecutionEvent};
+use crate::idempotency::{self, IdempotencyPolicy, IdempotencyRecord};
+use crate::lifecycle::Lifecycle;
+use crate::models::{CreateExecutionRequest, ExecutionJob, JobStatus, Schedule, WebhookDelivery};
+use crate::pagination::{ExecutionFilter, ExecutionPage, PageToken};
+use crate::queue::RedisQueue;
+use crate::quota::{QuotaPolicy, QuotaScope, UsageReport};
+use crate::retention::RetentionPolicy;
+use crate::retry::RetryPolicy;
+use crate::scheduler;
+use crate::secrets::{self, SecretCipher, SecretMetadata, StoredSecret};
+use crate::validation::ValidationLimits;
+use crate::webhook::{self, WebhookNotifier, WebhookSubscription};
 use anyhow::Result;
+use chrono::{DateTime, Utc};
 use redis::aio::ConnectionManager;
+use redis::Script;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::Mutex;
+use tracing::{error, warn};
+use utoipa::ToSchema;
 use uuid::Uuid;
 
+// Sorted sets of execution ids scored by creation time (ms)
+pub const EXECUTION_INDEX_KEY: &str = "executions:index";
+
+// Delayed execution ids scored by their `not_before` (ms)
+const SCHEDULED_JOBS_KEY: &str = "executions:scheduled";
+// Schedule ids scored by their next run (ms)
+const SCHEDULES_DUE_KEY: &str = "schedules:due";
+
+// Most delayed jobs released per scheduler tick
+const RELEASE_BATCH_SIZE: usize = 100;
+// Index entries read at a time while building a page of executions
+const LIST_SCAN_BATCH_SIZE: usize = 200;
+
+fn user_index_key(user_id: &str) -> String {
+    format!("user:{}:executions", user_id)
+}
+
+fn schedule_key(id: Uuid) -> String {
+    format!("schedule:{}", id)
+}
+
+fn user_schedules_key(user_id: &str) -> String {
+    format!("user:{}:schedules", user_id)
+}
+
+fn webhook_key(id: Uuid) -> String {
+    format!("webhook:{}", id)
+}
+
+fn workspace_webhooks_key(workspace_id: &str) -> String {
+    format!("workspace:{}:webhooks", workspace_id)
+}
+
+// Set once a finished job's webhooks have been dispatched
+fn webhook_notified_key(job_id: Uuid) -> String {
+    format!("webhook:notified:{}", job_id)
+}
+
+// Store a job unless the stored copy is cancelled and this one isn't; a
+// cancellation is final. KEYS: the job key. ARGV: the job, whether it is
+// cancelled, its TTL (empty for none). Returns whether it was stored and the
+// previous copy.
+const SAVE_JOB_SCRIPT: &str = r#"
+local previous = redis.call('GET', KEYS[1])
+if previous and ARGV[2] == '0' and cjson.decode(previous).status == 'cancelled' then
+    return {0, previous}
+end
+if ARGV[3] == '' then
+    redis.call('SET', KEYS[1], ARGV[1])
+else
+    redis.call('SET', KEYS[1], ARGV[1], 'EX', ARGV[3])
+end
+return {1, previous or ''}
+"#;
+
+// Add a subscription unless its workspace already has `limit`. KEYS: the
+// subscription key, the workspace's set. ARGV: the subscription, its id,
+// the limit.
+const CREATE_WEBHOOK_SCRIPT: &str = r#"
+if redis.call('SCARD', KEYS[2]) >= tonumber(ARGV[3]) then
+    return 0
+end
+redis.call('SET', KEYS[1], ARGV[1])
+redis.call('SADD', KEYS[2], ARGV[2])
+return 1
+"#;
+
+// Enough of a stored job to tell what status it was in
+#[derive(Deserialize)]
+struct StoredStatus {
+    status: JobStatus,
+}
+
 pub struct ServiceState {
     pub redis: Arc<Mutex<ConnectionManager>>,
-    pub docker_executor: Arc<crate::docker::DockerExecutor>,
+    pub executor: Arc<crate::executor::DockerExecutor>,
+    pub queue: RedisQueue,
+    pub dlq: DeadLetterQueue,
+    pub retention: RetentionPolicy,
+    pub quotas: QuotaPolicy,
+    pub retry: RetryPolicy,
+    pub idempotency: IdempotencyPolicy,
+    pub validation: ValidationLimits,
+    pub cache: ResultCache,
+    pub webhooks: WebhookNotifier,
+    pub secrets: SecretCipher,
+    pub archive: Option<ExecutionArchive>,
+    pub lifecycle: Lifecycle,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct PurgeSummary {
+    pub user_id: String,
+    pub purged_jobs: u64,
+    pub purged_archived: u64,
 }
 
 impl ServiceState {
+    /// Create an execution unless `idempotency_key` was already used by this
+    /// user, in which case the original execution is returned. Reusing a key
+    /// for a different payload is a conflict.
+    pub async fn submit_execution(
+        &self,
+        request: CreateExecutionRequest,
+        user_id: String,
+        workspace_id: Option<String>,
+        idempotency_key: Option<String>,
+    ) -> Result<ExecutionJob, ServiceError> {
+        if self.lifecycle.is_draining() {
+            return Err(ServiceError::ShuttingDown);
+        }
+        self.validation.check_execution(&request)?;
+        let Some(key) = idempotency_key else {
+            return self.create_execution(request, Some(user_id), workspace_id).await;
+        };
+        idempotency::validate_key(&key).map_err(ServiceError::InvalidRequest)?;
+
+        let record_key = idempotency::record_key(&user_id, &key);
+        let payload_hash = idempotency::payload_hash(&request, workspace_id.as_deref())?;
+        let ttl = self.idempotency.ttl_seconds;
+
+        // Claim the key before creating anything so concurrent retries can't
+        // both get through
+        let pending = serde_json::to_string(&IdempotencyRecord {
+            payload_hash: payload_hash.clone(),
+            execution_id: None,
+        })?;
+        loop {
+            let mut redis = self.redis.lock().await;
+            let claimed: Option<String> = redis::cmd("SET")
+                .arg(&record_key)
+                .arg(&pending)
+                .arg("NX")
+                .arg("EX")
+                .arg(ttl)
+                .query_async(&mut *redis)
+                .await?;
+            if claimed.is_some() {
+                break;
+            }
+
+            // Gone if it expired since the SET; try to claim it again
+            let existing: Option<String> = redis::cmd("GET")
+                .arg(&record_key)
+                .query_async(&mut *redis)
+                .await?;
+            let Some(existing) = existing else {
+                continue;
+            };
+            drop(redis);
+
+            let existing: IdempotencyRecord = serde_json::from_str(&existing)?;
+            return self.get_execution(existing.reuse(&payload_hash)?).await;
+        }
+
+        match self.create_execution(request, Some(user_id), workspace_id).await {
+            Ok(job) => {
+                let record = IdempotencyRecord {
+                    payload_hash,
+                    execution_id: Some(job.id),
+                };
+                redis::cmd("SET")
+                    .arg(&record_key)
+                    .arg(serde_json::to_string(&record)?)
+                    .arg("EX")
+                    .arg(ttl)
+                    .query_async::<_, ()>(&mut *self.redis.lock().await)
+                    .await?;
+                Ok(job)
+            }
+            Err(e) => {
+                // Nothing was created, so a retry with the same key may go ahead
+                redis::cmd("DEL")
+                    .arg(&record_key)
+                    .query_async::<_, ()>(&mut *self.redis.lock().await)
+                    .await?;
+                Err(e)
+            }
+        }
+    }
+
     pub async fn create_execution(
         &self,
         request: CreateExecutionRequest,
+        user_id: Option<String>,
+        workspace_id: Option<String>,
     ) -> Result<ExecutionJob, ServiceError> {
-        let job = ExecutionJob::new(request);
-        
+        if let Some(url) = &request.callback_url {
+            self.webhooks.require_enabled()?;
+            webhook::validate_url(url).await?;
+        }
+        if request.uses_secrets() {
+            self.check_secrets_exist(&request, user_id.as_deref(), workspace_id.as_deref()).await?;
+        }
+
+        let mut job = ExecutionJob::new(request);
+        job.user_id = user_id;
+        job.workspace_id = workspace_id;
+
+        // A `not_before` in the past just runs now
+        let run_at = job.request.not_before.filter(|t| *t > Utc::now());
+        if run_at.is_some() {
+            job.status = JobStatus::Pending;
+        }
+
+        // Scheduled jobs aren't in flight until their time comes
+        self.quotas.admit(&mut *self.redis.lock().await, &job, run_at.is_none()).await?;
+
+        if run_at.is_none() && self.serve_from_cache(&mut job).await {
+            let mut pipe = redis::pipe();
+            self.index_execution(&mut pipe, &job);
+            pipe.query_async::<_, ()>(&mut *self.redis.lock().await).await?;
+            self.finish_execution(&job).await?;
+            return Ok(job);
+        }
+
         // Store job in Redis
+        self.save_execution(&job).await?;
+
         let mut redis = self.redis.lock().await;
-        let job_key = format!("job:{}", job.id);
-        let job_json = serde_json::to_string(&job)?;
-        
-        redis::cmd("SET")
-            .arg(&job_key)
-            .arg(&job_json)
-            .query_async::<_, ()>(&mut *redis)
-            .await?;
-        
-        // Add to queue
-        redis::cmd("RPUSH")
-            .arg("execution_queue")
+        let mut pipe = redis::pipe();
+        self.index_execution(&mut pipe, &job);
+        if let Some(run_at) = run_at {
+            pipe.cmd("ZADD").arg(SCHEDULED_JOBS_KEY).arg(run_at.timestamp_millis()).arg(job.id.to_string()).ignore();
+        }
+        pipe.query_async::<_, ()>(&mut *redis).await?;
+        drop(redis);
+
+        if run_at.is_none() {
+            // Admission already counted it as in flight
+            self.queue.push_job(&job).await?;
+        }
+
+        Ok(job)
+    }
+
+    // Complete the job from the result cache if it opted in and an identical
+    // request has run before. Cache trouble only costs a real run.
+    async fn serve_from_cache(&self, job: &mut ExecutionJob) -> bool {
+        if job.request.cache != Some(true) || job.request.bypass_cache == Some(true) || job.request.uses_secrets() {
+            return false;
+        }
+        let cached = match self.cache.key_for(&self.executor, &job.request).await {
+            Ok(key) => self.cache.get(&mut *self.redis.lock().await, &key).await,
+            Err(e) => Err(e),
+        };
+        let cached = match cached {
+            Ok(Some(cached)) => cached,
+            Ok(None) => return false,
+            Err(e) => {
+                warn!("Result cache lookup failed for {}: {}", job.id, e);
+                return false;
+            }
+        };
+
+        let now = Utc::now();
+        job.status = cached.status;
+        job.result = cached.result;
+        job.batch = cached.batch;
+        job.started_at = Some(now);
+        job.completed_at = Some(now);
+        job.cache_hit = true;
+        true
+    }
+
+    /// Remember a finished job's outcome for later identical requests.
+    pub async fn cache_result(&self, job: &ExecutionJob) -> Result<(), ServiceError> {
+        // Output depends on secret values, which can change under the same name
+        if job.request.cache != Some(true) || job.cache_hit || job.request.uses_secrets() || !cache::is_cacheable(job) {
+            return Ok(());
+        }
+        let key = self.cache.key_for(&self.executor, &job.request).await?;
+        let cached = CachedResult {
+            status: job.status.clone(),
+            result: job.result.clone(),
+            batch: job.batch.clone(),
+        };
+        self.cache.put(&mut *self.redis.lock().await, &key, &cached).await
+    }
+
+    // Add the job to the listing indexes, scored by creation time
+    fn index_execution(&self, pipe: &mut redis::Pipeline, job: &ExecutionJob) {
+        let score = job.created_at.timestamp_millis();
+        pipe.cmd("ZADD").arg(EXECUTION_INDEX_KEY).arg(score).arg(job.id.to_string()).ignore();
+        if let Some(user_id) = &job.user_id {
+            let user_key = user_index_key(user_id);
+            pipe.cmd("ZADD").arg(&user_key).arg(score).arg(job.id.to_string()).ignore()
+                .cmd("EXPIRE").arg(&user_key).arg(self.retention.max_ttl()).ignore();
+        }
+    }
+
+    // Hand a saved job to the workers and count it as in flight
+    async fn enqueue(&self, job: &ExecutionJob) -> Result<(), ServiceError> {
+        let mut pipe = redis::pipe();
+        self.quotas.track(&mut pipe, job);
+        pipe.query_async::<_, ()>(&mut *self.redis.lock().await).await?;
+
+        self.queue.push_job(job).await?;
+        Ok(())
+    }
+
+    /// Put a job back as pending to run again at `at`. It stays counted as
+    /// in flight for quota purposes meanwhile.
+    pub async fn retry_execution(&self, job: &mut ExecutionJob, at: DateTime<Utc>) -> Result<(), ServiceError> {
+        job.status = JobStatus::Pending;
+        job.started_at = None;
+        if !self.save_execution(job).await? {
+            return Ok(());
+        }
+
+        redis::cmd("ZADD")
+            .arg(SCHEDULED_JOBS_KEY)
+            .arg(at.timestamp_millis())
             .arg(job.id.to_string())
-            .query_async::<_, ()>(&mut *redis)
+            .query_async::<_, ()>(&mut *self.redis.lock().await)
             .await?;
-        
+        Ok(())
+    }
+
+    /// Run a dead-lettered job again from scratch and drop its entry. The
+    /// retry budget starts over, so earlier attempts are cleared.
+    pub async fn replay_dead_letter(&self, id: Uuid) -> Result<ExecutionJob, ServiceError> {
+        let entry = self.dlq.get(id).await?.ok_or(ServiceError::NotFound)?;
+        let job = entry.into_replay()?;
+        self.save_execution(&job).await?;
+
+        // The job may have outlived its retention and dropped out of the
+        // indexes. Its next finish is notified afresh.
+        let mut pipe = redis::pipe();
+        self.index_execution(&mut pipe, &job);
+        pipe.cmd("DEL").arg(webhook_notified_key(job.id)).ignore();
+        pipe.query_async::<_, ()>(&mut *self.redis.lock().await).await?;
+
+        self.enqueue(&job).await?;
+        self.dlq.remove(&[id]).await?;
         Ok(job)
     }
-    
+
+    pub async fn replay_dead_letters(&self, selection: &DeadLetterSelection) -> Result<ReplaySummary, ServiceError> {
+        let mut summary = ReplaySummary::default();
+        for id in self.dlq.resolve(selection).await? {
+            match self.replay_dead_letter(id).await {
+                Ok(_) => summary.replayed.push(id),
+                Err(ServiceError::NotFound) => summary.skipped.push(SkippedDeadLetter {
+                    id,
+                    reason: "Not found".to_string(),
+                }),
+                Err(ServiceError::InvalidRequest(reason)) => summary.skipped.push(SkippedDeadLetter { id, reason }),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(summary)
+    }
+
+    pub async fn purge_dead_letters(&self, selection: &DeadLetterSelection) -> Result<u64, ServiceError> {
+        let ids = self.dlq.resolve(selection).await?;
+        Ok(self.dlq.remove(&ids).await?)
+    }
+
+    /// Queue delayed executions whose `not_before` has passed. Returns how
+    /// many were released.
+    pub async fn release_due_executions(&self) -> Result<usize, ServiceError> {
+        let due: Vec<String> = {
+            let mut redis = self.redis.lock().await;
+            redis::cmd("ZRANGEBYSCORE")
+                .arg(SCHEDULED_JOBS_KEY)
+                .arg("-inf")
+                .arg(Utc::now().timestamp_millis())
+                .arg("LIMIT")
+                .arg(0)
+                .arg(RELEASE_BATCH_SIZE)
+                .query_async(&mut *redis)
+                .await?
+        };
+
+        let mut released = 0;
+        for id in due {
+            // Whoever removes the entry owns the release
+            let claimed: u64 = redis::cmd("ZREM")
+                .arg(SCHEDULED_JOBS_KEY)
+                .arg(&id)
+                .query_async(&mut *self.redis.lock().await)
+                .await?;
+            if claimed == 0 {
+                continue;
+            }
+
+            let Ok(id) = id.parse::<Uuid>() else {
+                continue;
+            };
+            let mut job = match self.get_execution(id).await {
+                Ok(job) => job,
+                Err(ServiceError::NotFound) => continue,
+                Err(e) => return Err(e),
+            };
+            if job.status != JobStatus::Pending {
+                continue;
+            }
+
+            job.status = JobStatus::Queued;
+            self.save_execution(&job).await?;
+            self.enqueue(&job).await?;
+            released += 1;
+        }
+        Ok(released)
+    }
+
     pub async fn get_execution(&self, id: Uuid) -> Result<ExecutionJob, ServiceError> {
         let mut redis = self.redis.lock().await;
         let job_key = format!("job:{}", id);
-        
+
         let job_json: Option<String> = redis::cmd("GET")
             .arg(&job_key)
             .query_async(&mut *redis)
             .await?;
-        
+
         match job_json {
             Some(json) => Ok(serde_json::from_str(&json)?),
             None => Err(ServiceError::NotFound),
         }
     }
-}
\ No newline at end of file
+
+    /// Attach the queue position and wait estimate to a queued job.
+    pub async fn with_queue_position(&self, mut job: ExecutionJob) -> Result<ExecutionJob, ServiceError> {
+        if matches!(job.status, JobStatus::Queued) {
+            job.queue = self.queue.position(&job).await?;
+        }
+        Ok(job)
+    }
+
+    /// Store a job, announcing any status change; finished jobs get the
+    /// retention TTL. Returns false, storing nothing, when the job has been
+    /// cancelled meanwhile.
+    pub async fn save_execution(&self, job: &ExecutionJob) -> Result<bool, ServiceError> {
+        let mut redis = self.redis.lock().await;
+        let job_json = serde_json::to_string(job)?;
+        let ttl = if job.status.is_terminal() {
+            self.retention.ttl_for(job).to_string()
+        } else {
+            String::new()
+        };
+
+        let (saved, previous): (bool, String) = Script::new(SAVE_JOB_SCRIPT)
+            .key(format!("job:{}", job.id))
+            .arg(job_json)
+            .arg(if job.status == JobStatus::Cancelled { "1" } else { "0" })
+            .arg(ttl)
+            .invoke_async(&mut *redis)
+            .await?;
+        if !saved {
+            return Ok(false);
+        }
+
+        let old_status = Some(previous)
+            .filter(|json| !json.is_empty())
+            .and_then(|json| serde_json::from_str::<StoredStatus>(&json).ok())
+            .map(|stored| stored.status);
+        if old_status.as_ref() != Some(&job.status) {
+            let event = EventKind::StatusChange {
+                old_status,
+                new_status: job.status.clone(),
+                message: String::new(),
+            };
+            self.push_event(&mut redis, job, event).await?;
+        }
+
+        Ok(true)
+    }
+
+    /// Append an event to the job's event stream.
+    pub async fn publish_event(&self, job: &ExecutionJob, event: EventKind) -> Result<(), ServiceError> {
+        let mut redis = self.redis.lock().await;
+        self.push_event(&mut redis, job, event).await
+    }
+
+    // The stream is kept as long as the job: its retention once finished,
+    // otherwise the longest retention any job can get
+    async fn push_event(&self, redis: &mut ConnectionManager, job: &ExecutionJob, event: EventKind) -> Result<(), ServiceError> {
+        let ttl = if job.status.is_terminal() {
+            self.retention.ttl_for(job)
+        } else {
+            self.retention.max_ttl()
+        };
+        let key = events::events_key(job.id);
+        redis::pipe()
+            .cmd("RPUSH").arg(&key).arg(serde_json::to_string(&ExecutionEvent::new(job.id, event))?).ignore()
+            .cmd("EXPIRE").arg(&key).arg(ttl).ignore()
+            .query_async::<_, ()>(redis)
+            .await?;
+        Ok(())
+    }
+
+    /// How many events the job has published, i.e. the latest sequence number.
+    pub async fn execution_event_count(&self, id: Uuid) -> Result<u64, ServiceError> {
+        let mut redis = self.redis.lock().await;
+        Ok(redis::cmd("LLEN").arg(events::events_key(id)).query_async(&mut *redis).await?)
+    }
+
+    /// The job's events with a sequence number above `after`, in order.
+    pub async fn execution_events(&self, id: Uuid, after: u64) -> Result<Vec<ExecutionEvent>, ServiceError> {
+        let mut redis = self.redis.lock().await;
+        let events: Vec<String> = redis::cmd("LRANGE")
+            .arg(events::events_key(id))
+            .arg(after)
+            .arg(-1)
+            .query_async(&mut *redis)
+            .await?;
+
+        events
+            .into_iter()
+            .zip(after + 1..)
+            .map(|(json, sequence)| {
+                let mut event: ExecutionEvent = serde_json::from_str(&json)?;
+                event.sequence = sequence;
+                Ok(event)
+            })
+            .collect()
+    }
+
+    /// Store a job that reached a terminal status, release its quota, copy
+    /// it to the archive and notify its webhooks. Returns false, doing
+    /// nothing, when the job has been cancelled meanwhile; the cancellation
+    /// already did all that.
+    pub async fn finish_execution(&self, job: &ExecutionJob) -> Result<bool, ServiceError> {
+        if !self.save_execution(job).await? {
+            return Ok(false);
+        }
+        self.quotas.release(&mut *self.redis.lock().await, job).await?;
+
+        if let Some(archive) = &self.archive {
+            // The hot copy is already saved; a failed archive write shouldn't
+            // fail the job
+            if let Err(e) = archive.archive(job).await {
+                error!("Failed to archive execution {}: {}", job.id, e);
+            }
+        }
+
+        self.webhooks.notify(job).await;
+        Ok(true)
+    }
+
+    /// Mark a job as cancelled unless it already finished, stopping its
+    /// container if it is running. Returns the job as it stands afterwards.
+    pub async fn cancel_execution(&self, id: Uuid) -> Result<ExecutionJob, ServiceError> {
+        let mut job = self.get_execution(id).await?;
+        if job.status.is_terminal() {
+            return Ok(job);
+        }
+
+        if job.status == JobStatus::Pending {
+            redis::cmd("ZREM")
+                .arg(SCHEDULED_JOBS_KEY)
+                .arg(job.id.to_string())
+                .query_async::<_, ()>(&mut *self.redis.lock().await)
+                .await?;
+        }
+
+        let was_running = job.status == JobStatus::Running;
+        job.status = JobStatus::Cancelled;
+        job.completed_at = Some(Utc::now());
+        self.finish_execution(&job).await?;
+
+        // Marked first so the worker's result can't replace the cancellation
+        if was_running {
+            if let Err(e) = self.executor.abort(job.id).await {
+                warn!("Failed to stop the container of cancelled execution {}: {}", job.id, e);
+            }
+        }
+        Ok(job)
+    }
+
+    /// One page of retained executions matching `filter`, newest first,
+    /// starting after `page_token`.
+    pub async fn list_executions(
+        &self,
+        filter: &ExecutionFilter,
+        page_size: usize,
+        page_token: Option<&str>,
+    ) -> Result<ExecutionPage, ServiceError> {
+        let mut position = page_token.map(PageToken::decode).transpose()?;
+        let index_key = match &filter.user_id {
+            Some(user_id) => user_index_key(user_id),
+            None => EXECUTION_INDEX_KEY.to_string(),
+        };
+
+        // Walk the index down from the token, keeping matches until there's
+        // one more than a page. The bound is pinned up front so jobs created
+        // meanwhile don't enter the scan.
+        let max = match (position, filter.created_before) {
+            (Some(position), _) => position.created_at_ms.to_string(),
+            (None, Some(before)) => format!("({}", before.timestamp_millis()),
+            (None, None) => Utc::now().timestamp_millis().to_string(),
+        };
+        let min = match filter.created_after {
+            Some(after) => format!("({}", after.timestamp_millis()),
+            None => "-inf".to_string(),
+        };
+        let before_ms = filter.created_before.map(|before| before.timestamp_millis());
+
+        let mut executions = Vec::new();
+        let mut offset = 0;
+        while executions.len() <= page_size {
+            let mut redis = self.redis.lock().await;
+            let entries: Vec<(String, i64)> = redis::cmd("ZREVRANGEBYSCORE")
+                .arg(&index_key)
+                .arg(&max)
+                .arg(&min)
+                .arg("WITHSCORES")
+                .arg("LIMIT")
+                .arg(offset)
+                .arg(LIST_SCAN_BATCH_SIZE)
+                .query_async(&mut *redis)
+                .await?;
+            if entries.is_empty() {
+                break;
+            }
+            offset += entries.len();
+
+            // Entries at or before the position were already covered, e.g.
+            // re-read because a late index write shifted the offsets
+            let mut ids = Vec::with_capacity(entries.len());
+            for (id, score) in entries {
+                let Ok(uuid) = id.parse::<Uuid>() else {
+                    continue;
+                };
+                if position.is_some_and(|position| !position.precedes(score, &id)) {
+                    continue;
+                }
+                position = Some(PageToken {
+                    created_at_ms: score,
+                    id: uuid,
+                });
+                if before_ms.iter().all(|&before| score < before) {
+                    ids.push(id);
+                }
+            }
+            if ids.is_empty() {
+                continue;
+            }
+
+            let keys: Vec<String> = ids.iter().map(|id| format!("job:{}", id)).collect();
+            let jobs: Vec<Option<String>> = redis::cmd("MGET")
+                .arg(&keys)
+                .query_async(&mut *redis)
+                .await?;
+            drop(redis);
+
+            // Jobs whose TTL already ran out are skipped
+            for json in jobs.into_iter().flatten() {
+                let job: ExecutionJob = serde_json::from_str(&json)?;
+                if filter.matches(&job) {
+                    executions.push(job);
+                }
+            }
+        }
+
+        let next_page_token = if executions.len() > page_size {
+            executions.truncate(page_size);
+            executions.last().map(|job| PageToken::after(job).encode())
+        } else {
+            None
+        };
+        Ok(ExecutionPage {
+            executions,
+            next_page_token,
+        })
+    }
+
+    pub async fn quota_usage(&self, user_id: &str, workspace_id: Option<&str>) -> Result<UsageReport, ServiceError> {
+        let mut redis = self.redis.lock().await;
+        let user = self.quotas.usage(&mut redis, QuotaScope::User, user_id).await?;
+        let workspace = match workspace_id {
+            Some(id) => Some(self.quotas.usage(&mut redis, QuotaScope::Workspace, id).await?),
+            None => None,
+        };
+        Ok(UsageReport { user, workspace })
+    }
+
+    pub async fn create_schedule(
+        &self,
+        cron: String,
+        request: CreateExecutionRequest,
+        user_id: Option<String>,
+        workspace_id: Option<String>,
+    ) -> Result<Schedule, ServiceError> {
+        self.validation.check_execution(&request).map_err(|e| e.nested_under("execution"))?;
+        let now = Utc::now();
+        let next_run_at = scheduler::next_run(&scheduler::parse_cron(&cron)?, now)?;
+        let schedule = Schedule {
+            id: Uuid::new_v4(),
+            user_id,
+            workspace_id,
+            cron,
+            request,
+            created_at: now,
+            next_run_at,
+            last_run_at: None,
+            last_execution_id: None,
+        };
+        self.save_schedule(&schedule).await?;
+        Ok(schedule)
+    }
+
+    pub async fn save_schedule(&self, schedule: &Schedule) -> Result<(), ServiceError> {
+        let mut redis = self.redis.lock().await;
+        let mut pipe = redis::pipe();
+        pipe.cmd("SET").arg(schedule_key(schedule.id)).arg(serde_json::to_string(schedule)?).ignore()
+            .cmd("ZADD").arg(SCHEDULES_DUE_KEY).arg(schedule.next_run_at.timestamp_millis()).arg(schedule.id.to_string()).ignore();
+        if let Some(user_id) = &schedule.user_id {
+            pipe.cmd("SADD").arg(user_schedules_key(user_id)).arg(schedule.id.to_string()).ignore();
+        }
+        pipe.query_async::<_, ()>(&mut *redis).await?;
+        Ok(())
+    }
+
+    pub async fn get_schedule(&self, id: Uuid) -> Result<Schedule, ServiceError> {
+        let mut redis = self.redis.lock().await;
+        let json: Option<String> = redis::cmd("GET")
+            .arg(schedule_key(id))
+            .query_async(&mut *redis)
+            .await?;
+
+        match json {
+            Some(json) => Ok(serde_json::from_str(&json)?),
+            None => Err(ServiceError::NotFound),
+        }
+    }
+
+    /// All schedules ordered by next run, optionally only one user's.
+    pub async fn list_schedules(&self, user_id: Option<&str>) -> Result<Vec<Schedule>, ServiceError> {
+        let mut redis = self.redis.lock().await;
+        let ids: Vec<String> = match user_id {
+            Some(user_id) => redis::cmd("SMEMBERS").arg(user_schedules_key(user_id)).query_async(&mut *redis).await?,
+            None => redis::cmd("ZRANGE").arg(SCHEDULES_DUE_KEY).arg(0).arg(-1).query_async(&mut *redis).await?,
+        };
+        if ids.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let keys: Vec<String> = ids.iter().map(|id| format!("schedule:{}", id)).collect();
+        let schedules: Vec<Option<String>> = redis::cmd("MGET")
+            .arg(&keys)
+            .query_async(&mut *redis)
+            .await?;
+
+        let mut schedules = schedules
+            .into_iter()
+            .flatten()
+            .map(|json| serde_json::from_str(&json).map_err(ServiceError::from))
+            .collect::<Result<Vec<Schedule>, _>>()?;
+        schedules.sort_by_key(|s| s.next_run_at);
+        Ok(schedules)
+    }
+
+    pub async fn delete_schedule(&self, schedule: &Schedule) -> Result<(), ServiceError> {
+        let mut redis = self.redis.lock().await;
+        let mut pipe = redis::pipe();
+        pipe.cmd("DEL").arg(schedule_key(schedule.id)).ignore()
+            .cmd("ZREM").arg(SCHEDULES_DUE_KEY).arg(schedule.id.to_string()).ignore();
+        if let Some(user_id) = &schedule.user_id {
+            pipe.cmd("SREM").arg(user_schedules_key(user_id)).arg(schedule.id.to_string()).ignore();
+        }
+        pipe.query_async::<_, ()>(&mut *redis).await?;
+        Ok(())
+    }
+
+    /// Claim schedules whose next run has passed. Each id is handed to
+    /// exactly one caller, which must save the schedule again to re-arm it.
+    pub async fn claim_due_schedules(&self) -> Result<Vec<Schedule>, ServiceError> {
+        let due: Vec<String> = {
+            let mut redis = self.redis.lock().await;
+            redis::cmd("ZRANGEBYSCORE")
+                .arg(SCHEDULES_DUE_KEY)
+                .arg("-inf")
+                .arg(Utc::now().timestamp_millis())
+                .query_async(&mut *redis)
+                .await?
+        };
+
+        let mut claimed = vec![];
+        for id in due {
+            let removed: u64 = redis::cmd("ZREM")
+                .arg(SCHEDULES_DUE_KEY)
+                .arg(&id)
+                .query_async(&mut *self.redis.lock().await)
+                .await?;
+            if removed == 0 {
+                continue;
+            }
+            let Ok(id) = id.parse::<Uuid>() else {
+                continue;
+            };
+            match self.get_schedule(id).await {
+                Ok(schedule) => claimed.push(schedule),
+                Err(ServiceError::NotFound) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(claimed)
+    }
+
+    /// URLs to notify about a finished job: its callback and its workspace's
+    /// subscriptions. Empty if the job was already notified, so a job
+    /// finished twice (e.g. cancelled while running) is only announced once.
+    pub async fn webhook_targets(&self, job: &ExecutionJob) -> Result<Vec<String>, ServiceError> {
+        let mut redis = self.redis.lock().await;
+        let claimed: Option<String> = redis::cmd("SET")
+            .arg(webhook_notified_key(job.id))
+            .arg(1)
+            .arg("NX")
+            .arg("EX")
+            .arg(self.retention.ttl_for(job))
+            .query_async(&mut *redis)
+            .await?;
+        if claimed.is_none() {
+            return Ok(vec![]);
+        }
+
+        let mut targets: Vec<String> = job.request.callback_url.iter().cloned().collect();
+        if let Some(workspace_id) = &job.workspace_id {
+            drop(redis);
+            for subscription in self.list_webhooks(workspace_id).await? {
+                if !targets.contains(&subscription.url) {
+                    targets.push(subscription.url);
+                }
+            }
+        }
+        Ok(targets)
+    }
+
+    /// Append a webhook delivery attempt to a finished job. Done under the
+    /// connection lock so concurrent deliveries don't drop each other's.
+    pub async fn record_webhook_delivery(&self, job_id: Uuid, delivery: WebhookDelivery) -> Result<(), ServiceError> {
+        let mut redis = self.redis.lock().await;
+        let job_key = format!("job:{}", job_id);
+        let json: Option<String> = redis::cmd("GET").arg(&job_key).query_async(&mut *redis).await?;
+        // Expired or purged meanwhile; nothing to attach it to
+        let Some(json) = json else {
+            return Ok(());
+        };
+
+        let mut job: ExecutionJob = serde_json::from_str(&json)?;
+        job.webhook_deliveries.push(delivery);
+        redis::cmd("SET")
+            .arg(&job_key)
+            .arg(serde_json::to_string(&job)?)
+            .arg("EX")
+            .arg(self.retention.ttl_for(&job))
+            .query_async::<_, ()>(&mut *redis)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn create_webhook(&self, workspace_id: String, url: String) -> Result<WebhookSubscription, ServiceError> {
+        self.webhooks.require_enabled()?;
+        webhook::validate_url(&url).await?;
+        let subscription = WebhookSubscription {
+            id: Uuid::new_v4(),
+            workspace_id,
+            url,
+            created_at: Utc::now(),
+        };
+
+        let limit = self.webhooks.max_subscriptions();
+        let created: bool = Script::new(CREATE_WEBHOOK_SCRIPT)
+            .key(webhook_key(subscription.id))
+            .key(workspace_webhooks_key(&subscription.workspace_id))
+            .arg(serde_json::to_string(&subscription)?)
+            .arg(subscription.id.to_string())
+            .arg(limit)
+            .invoke_async(&mut *self.redis.lock().await)
+            .await?;
+        if !created {
+            return Err(ServiceError::Conflict(format!(
+                "Workspace {} already has the maximum of {} webhooks",
+                subscription.workspace_id, limit
+            )));
+        }
+        Ok(subscription)
+    }
+
+    pub async fn get_webhook(&self, id: Uuid) -> Result<WebhookSubscription, ServiceError> {
+        let mut redis = self.redis.lock().await;
+        let json: Option<String> = redis::cmd("GET")
+            .arg(webhook_key(id))
+            .query_async(&mut *redis)
+            .await?;
+
+        match json {
+            Some(json) => Ok(serde_json::from_str(&json)?),
+            None => Err(ServiceError::NotFound),
+        }
+    }
+
+    /// A workspace's webhook subscriptions, oldest first.
+    pub async fn list_webhooks(&self, workspace_id: &str) -> Result<Vec<WebhookSubscription>, ServiceError> {
+        let mut redis = self.redis.lock().await;
+        let ids: Vec<String> = redis::cmd("SMEMBERS")
+            .arg(workspace_webhooks_key(workspace_id))
+            .query_async(&mut *redis)
+            .await?;
+        if ids.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let keys: Vec<String> = ids.iter().map(|id| format!("webhook:{}", id)).collect();
+        let subscriptions: Vec<Option<String>> = redis::cmd("MGET")
+            .arg(&keys)
+            .query_async(&mut *redis)
+            .await?;
+
+        let mut subscriptions = subscriptions
+            .into_iter()
+            .flatten()
+            .map(|json| serde_json::from_str(&json).map_err(ServiceError::from))
+            .collect::<Result<Vec<WebhookSubscription>, _>>()?;
+        subscriptions.sort_by_key(|s| s.created_at);
+        Ok(subscriptions)
+    }
+
+    pub async fn delete_webhook(&self, subscription: &WebhookSubscription) -> Result<(), ServiceError> {
+        let mut redis = self.redis.lock().await;
+        redis::pipe()
+            .cmd("DEL").arg(webhook_key(subscription.id)).ignore()
+            .cmd("SREM").arg(workspace_webhooks_key(&subscription.workspace_id)).arg(subscription.id.to_string()).ignore()
+            .query_async::<_, ()>(&mut *redis)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn put_secret(&self, scope: &str, name: &str, value: &str) -> Result<SecretMetadata, ServiceError> {
+        secrets::validate_name(name).map_err(ServiceError::InvalidRequest)?;
+        let ciphertext = self.secrets.encrypt(scope, name, value)?;
+        let key = secrets::secrets_key(scope);
+
+        let mut redis = self.redis.lock().await;
+        let existing: Option<String> = redis::cmd("HGET").arg(&key).arg(name).query_async(&mut *redis).await?;
+        let now = Utc::now();
+        let created_at = match existing {
+            Some(json) => serde_json::from_str::<StoredSecret>(&json)?.created_at,
+            None => now,
+        };
+        let stored = StoredSecret {
+            ciphertext,
+            created_at,
+            updated_at: now,
+        };
+        redis::cmd("HSET")
+            .arg(&key)
+            .arg(name)
+            .arg(serde_json::to_string(&stored)?)
+            .query_async::<_, ()>(&mut *redis)
+            .await?;
+
+        Ok(SecretMetadata {
+            name: name.to_string(),
+            created_at,
+            updated_at: now,
+        })
+    }
+
+    /// The secrets in a scope, by name. Values are not included.
+    pub async fn list_secrets(&self, scope: &str) -> Result<Vec<SecretMetadata>, ServiceError> {
+        let mut redis = self.redis.lock().await;
+        let stored: HashMap<String, String> = redis::cmd("HGETALL")
+            .arg(secrets::secrets_key(scope))
+            .query_async(&mut *redis)
+            .await?;
+
+        let mut secrets = stored
+            .into_iter()
+            .map(|(name, json)| {
+                let stored: StoredSecret = serde_json::from_str(&json)?;
+                Ok(SecretMetadata {
+                    name,
+                    created_at: stored.created_at,
+                    updated_at: stored.updated_at,
+                })
+            })
+            .collect::<Result<Vec<_>, ServiceError>>()?;
+        secrets.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(secrets)
+    }
+
+    pub async fn delete_secret(&self, scope: &str, name: &str) -> Result<SecretMetadata, ServiceError> {
+        let key = secrets::secrets_key(scope);
+        let mut redis = self.redis.lock().await;
+        let json: Option<String> = redis::cmd("HGET").arg(&key).arg(name).query_async(&mut *redis).await?;
+        let stored: StoredSecret = serde_json::from_str(&json.ok_or(ServiceError::NotFound)?)?;
+        redis::cmd("HDEL").arg(&key).arg(name).query_async::<_, ()>(&mut *redis).await?;
+        Ok(SecretMetadata {
+            name: name.to_string(),
+            created_at: stored.created_at,
+            updated_at: stored.updated_at,
+        })
+    }
+
+    // Fail the submission now rather than the run later
+    async fn check_secrets_exist(
+        &self,
+        request: &CreateExecutionRequest,
+        user_id: Option<&str>,
+        workspace_id: Option<&str>,
+    ) -> Result<(), ServiceError> {
+        if !self.secrets.is_enabled() {
+            return Err(ServiceError::Conflict("Secrets are not enabled on this server".to_string()));
+        }
+        let scope = secrets::scope(user_id, workspace_id)
+            .ok_or_else(|| ServiceError::InvalidRequest("Secrets need a user or workspace".to_string()))?;
+        let mut names: Vec<&String> = request.secrets.iter().flatten().map(|(_, name)| name).collect();
+        names.sort();
+        names.dedup();
+
+        let mut redis = self.redis.lock().await;
+        let found: Vec<Option<String>> = redis::cmd("HMGET")
+            .arg(secrets::secrets_key(&scope))
+            .arg(&names)
+            .query_async(&mut *redis)
+            .await?;
+        let missing: Vec<_> = names
+            .iter()
+            .zip(&found)
+            .filter(|(_, found)| found.is_none())
+            .map(|(name, _)| name.as_str())
+            .collect();
+        if !missing.is_empty() {
+            return Err(ServiceError::InvalidRequest(format!("Unknown secrets: {}", missing.join(", "))));
+        }
+        Ok(())
+    }
+
+    /// A job's secret environment variables with their decrypted values.
+    pub async fn resolve_secrets(&self, job: &ExecutionJob) -> Result<HashMap<String, String>, ServiceError> {
+        let Some(requested) = job.request.secrets.as_ref().filter(|secrets| !secrets.is_empty()) else {
+            return Ok(HashMap::new());
+        };
+        let scope = secrets::scope(job.user_id.as_deref(), job.workspace_id.as_deref())
+            .ok_or_else(|| ServiceError::InvalidRequest("Secrets need a user or workspace".to_string()))?;
+
+        let names: Vec<&String> = requested.values().collect();
+        let found: Vec<Option<String>> = redis::cmd("HMGET")
+            .arg(secrets::secrets_key(&scope))
+            .arg(&names)
+            .query_async(&mut *self.redis.lock().await)
+            .await?;
+
+        let mut resolved = HashMap::with_capacity(requested.len());
+        for ((variable, name), json) in requested.iter().zip(found) {
+            let json = json.ok_or_else(|| ServiceError::InvalidRequest(format!("Secret `{}` no longer exists", name)))?;
+            let stored: StoredSecret = serde_json::from_str(&json)?;
+            resolved.insert(variable.clone(), self.secrets.decrypt(&scope, name, &stored.ciphertext)?);
+        }
+        Ok(resolved)
+    }
+
+    /// Delete every execution belonging to `user_id`, hot and archived.
+    pub async fn purge_user_executions(&self, user_id: &str) -> Result<PurgeSummary, ServiceError> {
+        let purged_jobs = {
+            let mut redis = self.redis.lock().await;
+            let user_key = user_index_key(user_id);
+            let ids: Vec<String> = redis::cmd("ZRANGE")
+                .arg(&user_key)
+                .arg(0)
+                .arg(-1)
+                .query_async(&mut *redis)
+                .await?;
+
+            let mut purged = 0;
+            if !ids.is_empty() {
+                let keys: Vec<String> = ids.iter().map(|id| format!("job:{}", id)).collect();
+                purged = redis::cmd("DEL").arg(&keys).query_async(&mut *redis).await?;
+                let event_keys: Vec<String> = ids.iter().map(|id| format!("job:{}:events", id)).collect();
+                redis::cmd("DEL").arg(&event_keys).query_async::<_, ()>(&mut *redis).await?;
+                redis::cmd("ZREM")
+                    .arg(EXECUTION_INDEX_KEY)
+                    .arg(&ids)
+                    .query_async::<_, ()>(&mut *redis)
+                    .await?;
+            }
+            redis::cmd("DEL").arg(&user_key).query_async::<_, ()>(&mut *redis).await?;
+            purged
+        };
+
+        let purged_archived = match &self.archive {
+            Some(archive) => archive.purge_user(user_id).await?,
+            None => 0,
+        };
+
+        Ok(PurgeSummary {
+            user_id: user_id.to_string(),
+            purged_jobs,
+            purged_archived,
+        })
+    }
+}
@@ -0,0 +1,341 @@
+use crate::docker;
+use crate::error::{FieldViolation, ServiceError};
+use crate::models::{CreateExecutionRequest, TestCase};
+use crate::secrets;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Languages the executor has an image and command for.
+pub const SUPPORTED_LANGUAGES: &[&str] = &[
+    "python", "javascript", "typescript", "rust", "go", "java", "ruby", "php", "shell",
+];
+
+/// Bounds on what a submission may ask for. Checked before anything is
+/// stored, for REST and gRPC alike.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ValidationLimits {
+    pub max_code_bytes: usize,
+    pub max_timeout_seconds: u64,
+    pub max_args: usize,
+    pub max_arg_bytes: usize,
+    pub max_env_vars: usize,
+    // Keys and values together, per variable
+    pub max_env_var_bytes: usize,
+    pub max_test_cases: usize,
+    // Per test case, for stdin and expected stdout each
+    pub max_test_case_bytes: usize,
+}
+
+impl Default for ValidationLimits {
+    fn default() -> Self {
+        Self {
+            max_code_bytes: 256 * 1024,
+            max_timeout_seconds: 300,
+            max_args: 64,
+            max_arg_bytes: 4096,
+            max_env_vars: 64,
+            max_env_var_bytes: 4096,
+            max_test_cases: 100,
+            max_test_case_bytes: 1024 * 1024,
+        }
+    }
+}
+
+impl ValidationLimits {
+    /// Every problem with a submission at once, so clients can fix them in
+    /// one go.
+    pub fn check_execution(&self, request: &CreateExecutionRequest) -> Result<(), ServiceError> {
+        let mut violations = Vec::new();
+        self.check_code(&request.code, &request.language, &mut violations);
+        self.check_timeout("timeout_seconds", request.timeout_seconds, &mut violations);
+        self.check_timeout("cpu_time_limit_seconds", request.cpu_time_limit_seconds, &mut violations);
+        if let Some(args) = &request.args {
+            self.check_args(args, &mut violations);
+        }
+        if let Some(environment) = &request.environment {
+            self.check_environment(environment, &mut violations);
+        }
+        if let Some(secrets) = &request.secrets {
+            self.check_secrets(secrets, &request.environment, &mut violations);
+        }
+        if let Some(test_cases) = &request.test_cases {
+            self.check_test_cases(test_cases, &mut violations);
+        }
+        finish(violations)
+    }
+
+    fn check_code(&self, code: &str, language: &str, violations: &mut Vec<FieldViolation>) {
+        if code.trim().is_empty() {
+            violations.push(FieldViolation::new("code", "Must not be empty"));
+        } else if code.len() > self.max_code_bytes {
+            violations.push(FieldViolation::new(
+                "code",
+                format!("Is {} bytes; the limit is {}", code.len(), self.max_code_bytes),
+            ));
+        }
+
+        if language.is_empty() {
+            violations.push(FieldViolation::new("language", "Must be specified"));
+        } else if !SUPPORTED_LANGUAGES.contains(&language) {
+            violations.push(FieldViolation::new(
+                "language",
+                format!("Unsupported language `{}`; expected one of {}", language, SUPPORTED_LANGUAGES.join(", ")),
+            ));
+        }
+    }
+
+    fn check_timeout(&self, field: &str, seconds: Option<u64>, violations: &mut Vec<FieldViolation>) {
+        match seconds {
+            Some(0) => violations.push(FieldViolation::new(field, "Must be at least 1 second")),
+            Some(seconds) if seconds > self.max_timeout_seconds => violations.push(FieldViolation::new(
+                field,
+                format!("Must be at most {} seconds", self.max_timeout_seconds),
+            )),
+            _ => {}
+        }
+    }
+
+    fn check_args(&self, args: &[String], violations: &mut Vec<FieldViolation>) {
+        if args.len() > self.max_args {
+            violations.push(FieldViolation::new("args", format!("At most {} arguments are allowed", self.max_args)));
+        }
+        for (i, arg) in args.iter().enumerate() {
+            if arg.len() > self.max_arg_bytes {
+                violations.push(FieldViolation::new(
+                    format!("args[{}]", i),
+                    format!("Is {} bytes; the limit is {}", arg.len(), self.max_arg_bytes),
+                ));
+            }
+            if arg.contains('\0') {
+                violations.push(FieldViolation::new(format!("args[{}]", i), "Must not contain NUL characters"));
+            }
+        }
+    }
+
+    fn check_environment(&self, environment: &HashMap<String, String>, violations: &mut Vec<FieldViolation>) {
+        if environment.len() > self.max_env_vars {
+            violations.push(FieldViolation::new(
+                "environment",
+                format!("At most {} variables are allowed", self.max_env_vars),
+            ));
+        }
+        // Sorted so the violations come out in a stable order
+        let mut keys: Vec<_> = environment.keys().collect();
+        keys.sort();
+        for key in keys {
+            let value = &environment[key];
+            let field = format!("environment.{}", key);
+            if !is_env_name(key) {
+                violations.push(FieldViolation::new(
+                    field.clone(),
+                    "Names must start with a letter or underscore and contain only letters, digits and underscores",
+                ));
+            }
+            if value.contains('\0') {
+                violations.push(FieldViolation::new(field.clone(), "Must not contain NUL characters"));
+            }
+            if key.len() + value.len() > self.max_env_var_bytes {
+                violations.push(FieldViolation::new(
+                    field,
+                    format!("Name and value together must be at most {} bytes", self.max_env_var_bytes),
+                ));
+            }
+        }
+    }
+
+    // Secret variables share the environment's budget and namespace
+    fn check_secrets(
+        &self,
+        secrets: &HashMap<String, String>,
+        environment: &Option<HashMap<String, String>>,
+        violations: &mut Vec<FieldViolation>,
+    ) {
+        let variables = secrets.len() + environment.as_ref().map_or(0, HashMap::len);
+        if variables > self.max_env_vars {
+            violations.push(FieldViolation::new(
+                "secrets",
+                format!("At most {} variables are allowed, counting the environment", self.max_env_vars),
+            ));
+        }
+        let mut names: Vec<_> = secrets.keys().collect();
+        names.sort();
+        for name in names {
+            let field = format!("secrets.{}", name);
+            if !is_env_name(name) {
+                violations.push(FieldViolation::new(
+                    field.clone(),
+                    "Names must start with a letter or underscore and contain only letters, digits and underscores",
+                ));
+            } else if docker::is_client_env_name(name) {
+                // Secret values reach the container through the docker
+                // CLI's own environment, so nothing it reads can be used
+                violations.push(FieldViolation::new(field.clone(), "Name is reserved"));
+            }
+            if environment.as_ref().is_some_and(|environment| environment.contains_key(name)) {
+                violations.push(FieldViolation::new(field.clone(), "Also set in `environment`"));
+            }
+            if let Err(reason) = secrets::validate_name(&secrets[name]) {
+                violations.push(FieldViolation::new(field, reason));
+            }
+        }
+    }
+
+    fn check_test_cases(&self, test_cases: &[TestCase], violations: &mut Vec<FieldViolation>) {
+        if test_cases.is_empty() {
+            violations.push(FieldViolation::new("test_cases", "At least one test case is required"));
+        } else if test_cases.len() > self.max_test_cases {
+            violations.push(FieldViolation::new(
+                "test_cases",
+                format!("At most {} test cases are allowed", self.max_test_cases),
+            ));
+        }
+        for (i, case) in test_cases.iter().enumerate() {
+            let field = |name: &str| format!("test_cases[{}].{}", i, name);
+            self.check_timeout(&field("timeout_seconds"), case.timeout_seconds, violations);
+            if case.memory_mb == Some(0) {
+                violations.push(FieldViolation::new(field("memory_mb"), "Must be at least 1"));
+            }
+            for (name, text) in [("stdin", &case.stdin), ("expected_stdout", &case.expected_stdout)] {
+                if text.len() > self.max_test_case_bytes {
+                    violations.push(FieldViolation::new(
+                        field(name),
+                        format!("Is {} bytes; the limit is {}", text.len(), self.max_test_case_bytes),
+                    ));
+                }
+            }
+        }
+    }
+}
+
+// Passed to `docker run -e NAME=VALUE`, so `=` in particular can't appear
+fn is_env_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn finish(violations: Vec<FieldViolation>) -> Result<(), ServiceError> {
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(ServiceError::Validation(violations))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(extra: serde_json::Value) -> CreateExecutionRequest {
+        let mut value = serde_json::json!({"code": "print(1)", "language": "python"});
+        value.as_object_mut().unwrap().extend(extra.as_object().unwrap().clone());
+        serde_json::from_value(value).unwrap()
+    }
+
+    // Fields with violations, in order
+    fn violations(request: &CreateExecutionRequest) -> Vec<String> {
+        match ValidationLimits::default().check_execution(request) {
+            Ok(()) => vec![],
+            Err(ServiceError::Validation(violations)) => violations.into_iter().map(|v| v.field).collect(),
+            Err(e) => panic!("unexpected error {:?}", e),
+        }
+    }
+
+    #[test]
+    fn a_plain_request_is_valid() {
+        assert_eq!(violations(&request(serde_json::json!({}))), Vec::<String>::new());
+    }
+
+    #[test]
+    fn every_violation_is_reported_at_once() {
+        let request = request(serde_json::json!({"code": "  ", "language": "cobol", "timeout_seconds": 0}));
+        assert_eq!(violations(&request), vec!["code", "language", "timeout_seconds"]);
+    }
+
+    #[test]
+    fn timeouts_are_bounded() {
+        let max = ValidationLimits::default().max_timeout_seconds;
+        for (seconds, valid) in [(1, true), (max, true), (0, false), (max + 1, false)] {
+            let request = request(serde_json::json!({"cpu_time_limit_seconds": seconds}));
+            assert_eq!(violations(&request).is_empty(), valid, "{}", seconds);
+        }
+    }
+
+    #[test]
+    fn args_are_limited_in_count_size_and_content() {
+        let limits = ValidationLimits::default();
+        let too_many = vec!["a"; limits.max_args + 1];
+        assert_eq!(violations(&request(serde_json::json!({"args": too_many}))), vec!["args"]);
+
+        let args = ["ok", &"x".repeat(limits.max_arg_bytes + 1), "nul\0"];
+        assert_eq!(violations(&request(serde_json::json!({"args": args}))), vec!["args[1]", "args[2]"]);
+    }
+
+    #[test]
+    fn environment_names_must_be_plain_identifiers() {
+        for name in ["_PRIVATE", "lower_case", "A1"] {
+            assert!(is_env_name(name), "{}", name);
+        }
+        for name in ["", "1ABC", "A=B", "A-B", "A B", "ÄBC"] {
+            assert!(!is_env_name(name), "{}", name);
+        }
+
+        let request = request(serde_json::json!({"environment": {"A=B": "x", "NUL": "a\0b", "OK": "fine"}}));
+        assert_eq!(violations(&request), vec!["environment.A=B", "environment.NUL"]);
+    }
+
+    #[test]
+    fn test_cases_are_checked_one_by_one() {
+        let case = |extra: serde_json::Value| {
+            let mut case = serde_json::json!({"expected_stdout": "1"});
+            case.as_object_mut().unwrap().extend(extra.as_object().unwrap().clone());
+            case
+        };
+        assert_eq!(violations(&request(serde_json::json!({"test_cases": []}))), vec!["test_cases"]);
+
+        let cases = [
+            case(serde_json::json!({})),
+            case(serde_json::json!({"timeout_seconds": 0, "memory_mb": 0})),
+            case(serde_json::json!({"stdin": "x".repeat(ValidationLimits::default().max_test_case_bytes + 1)})),
+        ];
+        assert_eq!(
+            violations(&request(serde_json::json!({"test_cases": cases}))),
+            vec!["test_cases[1].timeout_seconds", "test_cases[1].memory_mb", "test_cases[2].stdin"]
+        );
+    }
+
+    #[test]
+    fn schedule_violations_are_nested_under_the_execution() {
+        let error = ValidationLimits::default()
+            .check_execution(&request(serde_json::json!({"language": ""})))
+            .unwrap_err()
+            .nested_under("execution");
+        match error {
+            ServiceError::Validation(violations) => assert_eq!(violations[0].field, "execution.language"),
+            other => panic!("unexpected error {:?}", other),
+        }
+    }
+
+    #[test]
+    fn secrets_cannot_use_names_the_docker_cli_reads() {
+        for name in ["PATH", "HOME", "http_proxy", "LD_PRELOAD", "DOCKER_HOST", "GODEBUG", "GOMAXPROCS", "SSL_CERT_FILE"] {
+            let request = request(serde_json::json!({"secrets": {name: "token"}}));
+            assert_eq!(violations(&request), vec![format!("secrets.{}", name)], "{}", name);
+        }
+    }
+
+    #[test]
+    fn ordinary_secret_names_are_accepted() {
+        let request = request(serde_json::json!({"secrets": {"API_TOKEN": "token", "db_password": "db"}}));
+        assert_eq!(violations(&request), Vec::<String>::new());
+    }
+
+    #[test]
+    fn only_the_go_runtime_variables_are_reserved() {
+        for name in ["GOOGLE_API_KEY", "GOOGLE_APPLICATION_CREDENTIALS", "GOPHER_TOKEN"] {
+            let request = request(serde_json::json!({"secrets": {name: "token"}}));
+            assert_eq!(violations(&request), Vec::<String>::new(), "{}", name);
+        }
+    }
+}
@@ -0,0 +1,420 @@
+use crate::config::Sensitive;
+use crate::error::ServiceError;
+use crate::models::{ExecutionJob, ExecutionStatus, WebhookDelivery};
+use crate::retry::RetryPolicy;
+use crate::state::ServiceState;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, Semaphore};
+use tracing::{error, info, warn};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+// `sha256=<hex>` HMAC of "{timestamp}.{body}" under the signing secret
+pub const SIGNATURE_HEADER: &str = "x-syla-signature";
+pub const TIMESTAMP_HEADER: &str = "x-syla-timestamp";
+pub const EVENT_HEADER: &str = "x-syla-event";
+// Same for every retry of one delivery so receivers can deduplicate
+pub const DELIVERY_HEADER: &str = "x-syla-delivery";
+
+const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+const DEFAULT_TIMEOUT_SECONDS: u64 = 10;
+const DEFAULT_MAX_CONCURRENT_DELIVERIES: usize = 16;
+const DEFAULT_MAX_SUBSCRIPTIONS: u64 = 20;
+
+// Finished jobs waiting for the dispatcher; finishing a job waits for room
+// once it is this far behind
+const NOTIFY_BACKLOG: usize = 1024;
+
+// Longest error message kept on a delivery record
+const MAX_ERROR_LEN: usize = 512;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebhookPolicy {
+    // Webhooks are disabled when unset; nothing is ever sent unsigned
+    pub signing_secret: Option<Sensitive>,
+    pub timeout_seconds: u64,
+    pub retry: RetryPolicy,
+    // Deliveries in progress at once, retries included; the rest wait
+    pub max_concurrent_deliveries: usize,
+    pub max_subscriptions_per_workspace: u64,
+}
+
+impl Default for WebhookPolicy {
+    fn default() -> Self {
+        Self {
+            signing_secret: None,
+            timeout_seconds: DEFAULT_TIMEOUT_SECONDS,
+            retry: RetryPolicy {
+                max_attempts: DEFAULT_MAX_ATTEMPTS,
+                ..RetryPolicy::default()
+            },
+            max_concurrent_deliveries: DEFAULT_MAX_CONCURRENT_DELIVERIES,
+            max_subscriptions_per_workspace: DEFAULT_MAX_SUBSCRIPTIONS,
+        }
+    }
+}
+
+/// A URL notified whenever an execution in the workspace finishes.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct WebhookSubscription {
+    pub id: Uuid,
+    pub workspace_id: String,
+    pub url: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CreateWebhookRequest {
+    pub url: String,
+    // Defaults to the caller's workspace
+    pub workspace_id: Option<String>,
+}
+
+// Body POSTed to webhook targets
+#[derive(Serialize)]
+struct WebhookEvent<'a> {
+    event: &'a str,
+    delivery_id: Uuid,
+    execution: &'a ExecutionJob,
+}
+
+/// Webhook targets must be absolute http(s) URLs whose host resolves only
+/// to public addresses, so tenants can't use them to reach the service's
+/// own network.
+pub async fn validate_url(url: &str) -> Result<(), ServiceError> {
+    let parsed = reqwest::Url::parse(url)
+        .map_err(|e| ServiceError::InvalidRequest(format!("Invalid webhook URL: {}", e)))?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return Err(ServiceError::InvalidRequest("Webhook URL must use http or https".to_string()));
+    }
+    let host = parsed
+        .host_str()
+        .ok_or_else(|| ServiceError::InvalidRequest("Webhook URL must have a host".to_string()))?;
+    // IPv6 literals come bracketed
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    resolve_public(host)
+        .await
+        .map(|_| ())
+        .map_err(|e| ServiceError::InvalidRequest(format!("Webhook URL not allowed: {}", e)))
+}
+
+// Resolve `host`, failing if any of its addresses is not public
+async fn resolve_public(host: &str) -> Result<Vec<SocketAddr>, String> {
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, 0))
+        .await
+        .map_err(|e| format!("cannot resolve {}: {}", host, e))?
+        .collect();
+    if addrs.is_empty() {
+        return Err(format!("{} has no addresses", host));
+    }
+    if let Some(addr) = addrs.iter().find(|addr| !is_public(addr.ip())) {
+        return Err(format!("{} resolves to non-public address {}", host, addr.ip()));
+    }
+    Ok(addrs)
+}
+
+// Loopback, private, link-local, shared, multicast and reserved ranges are
+// all off limits
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(mapped) => is_public_v4(mapped),
+            None => is_public_v6(ip),
+        },
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        || a == 0
+        // Carrier-grade NAT, 100.64.0.0/10
+        || (a == 100 && (b & 0xc0) == 64)
+        // Reserved, 240.0.0.0/4
+        || a >= 240)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        // Unique local, fc00::/7
+        || (first & 0xfe00) == 0xfc00
+        // Link-local, fe80::/10
+        || (first & 0xffc0) == 0xfe80)
+}
+
+// Connection-time check as well, since a name can resolve differently by
+// the time a delivery goes out
+struct PublicOnlyResolver;
+
+impl reqwest::dns::Resolve for PublicOnlyResolver {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        Box::pin(async move {
+            let addrs = resolve_public(name.as_str()).await?;
+            Ok(Box::new(addrs.into_iter()) as reqwest::dns::Addrs)
+        })
+    }
+}
+
+// Event name for a finished job, e.g. `execution.completed`
+fn event_name(job: &ExecutionJob) -> String {
+    format!("execution.{}", ExecutionStatus::from(&job.status).as_str())
+}
+
+fn sign(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(format!("{}.", timestamp).as_bytes());
+    mac.update(body);
+    format!("sha256={:x}", mac.finalize().into_bytes())
+}
+
+/// Hands finished jobs to the dispatcher, which delivers their webhooks in
+/// the background so finishing a job never waits on a receiver.
+pub struct WebhookNotifier {
+    policy: WebhookPolicy,
+    client: reqwest::Client,
+    sender: mpsc::Sender<ExecutionJob>,
+}
+
+impl WebhookNotifier {
+    pub fn new(policy: WebhookPolicy) -> (Self, mpsc::Receiver<ExecutionJob>) {
+        let (sender, receiver) = mpsc::channel(NOTIFY_BACKLOG);
+        let notifier = Self {
+            policy,
+            // Redirects and proxies would bypass the address check
+            client: reqwest::Client::builder()
+                .dns_resolver(Arc::new(PublicOnlyResolver))
+                .redirect(reqwest::redirect::Policy::none())
+                .no_proxy()
+                .build()
+                .expect("webhook HTTP client"),
+            sender,
+        };
+        (notifier, receiver)
+    }
+
+    pub fn require_enabled(&self) -> Result<(), ServiceError> {
+        match self.policy.signing_secret {
+            Some(_) => Ok(()),
+            None => Err(ServiceError::Conflict("Webhooks are not enabled on this server".to_string())),
+        }
+    }
+
+    pub fn max_subscriptions(&self) -> u64 {
+        self.policy.max_subscriptions_per_workspace
+    }
+
+    /// Queue webhooks for a job that reached a terminal status.
+    pub async fn notify(&self, job: &ExecutionJob) {
+        if self.policy.signing_secret.is_none() {
+            return;
+        }
+        // Only jobs with a callback or a workspace can have anyone to tell
+        if job.request.callback_url.is_none() && job.workspace_id.is_none() {
+            return;
+        }
+        if self.sender.send(job.clone()).await.is_err() {
+            warn!("Webhook dispatcher is gone; dropping notification for {}", job.id);
+        }
+    }
+
+    // POST one attempt. Anything but a 2xx counts as a failure.
+    async fn send(&self, url: &str, event: &str, delivery_id: Uuid, body: &[u8]) -> (Option<u16>, Option<String>) {
+        let Some(secret) = &self.policy.signing_secret else {
+            return (None, Some("Webhooks are not enabled on this server".to_string()));
+        };
+        // Literal IP hosts never reach the resolver, so check them here too
+        if let Err(e) = validate_url(url).await {
+            return (None, Some(e.to_string()));
+        }
+        let timestamp = Utc::now().timestamp();
+        let request = self.client
+            .post(url)
+            .timeout(Duration::from_secs(self.policy.timeout_seconds))
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(EVENT_HEADER, event)
+            .header(DELIVERY_HEADER, delivery_id.to_string())
+            .header(TIMESTAMP_HEADER, timestamp.to_string())
+            .header(SIGNATURE_HEADER, sign(secret.expose(), timestamp, body))
+            .body(body.to_vec());
+
+        match request.send().await {
+            Ok(response) if response.status().is_success() => (Some(response.status().as_u16()), None),
+            Ok(response) => {
+                let status = response.status();
+                (Some(status.as_u16()), Some(format!("Receiver responded with {}", status)))
+            }
+            Err(e) => {
+                let mut message = e.to_string();
+                message.truncate(MAX_ERROR_LEN);
+                (None, Some(message))
+            }
+        }
+    }
+}
+
+/// Deliver webhooks for finished jobs as they come in, at most
+/// `max_concurrent_deliveries` at a time.
+pub async fn run_dispatcher(state: Arc<ServiceState>, mut jobs: mpsc::Receiver<ExecutionJob>) {
+    if state.webhooks.require_enabled().is_err() {
+        info!("No webhook signing secret configured; webhooks are disabled");
+        return;
+    }
+    info!("Starting webhook dispatcher");
+
+    let slots = Arc::new(Semaphore::new(state.webhooks.policy.max_concurrent_deliveries));
+    while let Some(job) = jobs.recv().await {
+        let targets = match state.webhook_targets(&job).await {
+            Ok(targets) => targets,
+            Err(e) => {
+                error!("Failed to look up webhooks for execution {}: {}", job.id, e);
+                continue;
+            }
+        };
+        for url in targets {
+            // Waiting here holds up the channel, and so eventually the
+            // jobs finishing, rather than piling up tasks
+            let slot = slots.clone().acquire_owned().await.expect("semaphore is never closed");
+            let state = state.clone();
+            let job = job.clone();
+            tokio::spawn(async move {
+                deliver(&state, &job, &url).await;
+                drop(slot);
+            });
+        }
+    }
+}
+
+// Send one job's event to one target, retrying with backoff, and record
+// every attempt on the execution
+async fn deliver(state: &ServiceState, job: &ExecutionJob, url: &str) {
+    let policy = &state.webhooks.policy;
+    let event = event_name(job);
+    let delivery_id = Uuid::new_v4();
+    let body = match serde_json::to_vec(&WebhookEvent {
+        event: &event,
+        delivery_id,
+        execution: job,
+    }) {
+        Ok(body) => body,
+        Err(e) => {
+            error!("Failed to encode webhook for execution {}: {}", job.id, e);
+            return;
+        }
+    };
+
+    for attempt in 1..=policy.retry.max_attempts.max(1) {
+        let attempted_at = Utc::now();
+        let (status_code, error) = state.webhooks.send(url, &event, delivery_id, &body).await;
+        let delivered = error.is_none();
+
+        let record = WebhookDelivery {
+            id: delivery_id,
+            url: url.to_string(),
+            event: event.clone(),
+            attempt,
+            attempted_at,
+            status_code,
+            error,
+            delivered,
+        };
+        if let Err(e) = state.record_webhook_delivery(job.id, record).await {
+            warn!("Failed to record webhook delivery for execution {}: {}", job.id, e);
+        }
+
+        if delivered {
+            return;
+        }
+        if attempt < policy.retry.max_attempts {
+            tokio::time::sleep(policy.retry.backoff(attempt)).await;
+        }
+    }
+    warn!(
+        "Giving up on webhook for execution {} to {} after {} attempts",
+        job.id, url, policy.retry.max_attempts
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn non_public_addresses_are_rejected() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "255.255.255.255",
+            "224.0.0.1",
+            "240.0.0.1",
+            "::1",
+            "::",
+            "fc00::1",
+            "fd12:3456::1",
+            "fe80::1",
+            "ff02::1",
+            "::ffff:127.0.0.1",
+            "::ffff:10.0.0.1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{} should not be public", ip);
+        }
+    }
+
+    #[test]
+    fn public_addresses_are_allowed() {
+        for ip in ["8.8.8.8", "1.1.1.1", "100.128.0.1", "2606:4700::1111", "::ffff:8.8.8.8"] {
+            assert!(is_public(ip.parse().unwrap()), "{} should be public", ip);
+        }
+    }
+
+    #[tokio::test]
+    async fn urls_to_internal_hosts_are_rejected() {
+        for url in [
+            "http://127.0.0.1/hook",
+            "http://localhost:8080/hook",
+            "https://169.254.169.254/latest/meta-data",
+            "http://[::1]/hook",
+            "http://10.0.0.5/hook",
+        ] {
+            assert!(validate_url(url).await.is_err(), "{} should be rejected", url);
+        }
+    }
+
+    #[tokio::test]
+    async fn urls_need_http_and_a_host() {
+        assert!(validate_url("ftp://8.8.8.8/hook").await.is_err());
+        assert!(validate_url("not a url").await.is_err());
+        assert!(validate_url("https://8.8.8.8/hook").await.is_ok());
+    }
+
+    #[test]
+    fn signature_covers_timestamp_and_body() {
+        let signature = sign("secret", 1_700_000_000, b"{}");
+        assert!(signature.starts_with("sha256="));
+        assert_eq!(signature, sign("secret", 1_700_000_000, b"{}"));
+        assert_ne!(signature, sign("secret", 1_700_000_001, b"{}"));
+        assert_ne!(signature, sign("secret", 1_700_000_000, b"[]"));
+        assert_ne!(signature, sign("other", 1_700_000_000, b"{}"));
+    }
+}
@@ -1,32 +1,36 @@
-use crate::models::{ExecutionJob, ExecutionResult, JobStatus};
+use crate::dlq::DeadLetter;
+use crate::error::ServiceError;
+use crate::events::EventKind;
+use crate::lifecycle::Lifecycle;
+use crate::models::{Attempt, ExecutionJob, ExecutionResult, FailureKind, JobStatus, OutputEncoding, OutputStream, TestCase};
+use crate::secrets::Redactor;
 use crate::state::ServiceState;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use std::collections::HashMap;
 use std::sync::Arc;
-use tracing::{error, info};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::{error, info, warn};
 
-pub async fn run_worker(state: Arc<ServiceState>) {
+/// Run queued jobs one at a time until draining starts. Returns once the
+/// job in hand, if any, has finished or been interrupted.
+pub async fn run_worker(state: Arc<ServiceState>, drain_timeout: Duration) {
     info!("Starting execution worker");
+    migrate_legacy_queue(&state).await;
     
-    loop {
-        // Get job from queue
-        let job_id = {
-            let mut redis = state.redis.lock().await;
-            let result: Result<Option<String>, _> = redis::cmd("LPOP")
-                .arg("execution_queue")
-                .query_async(&mut *redis)
-                .await;
-                
-            match result {
-                Ok(Some(id)) => id,
-                Ok(None) => {
-                    // No jobs, wait a bit
-                    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
-                    continue;
-                }
-                Err(e) => {
-                    error!("Redis error: {}", e);
-                    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
-                    continue;
-                }
+    while !state.lifecycle.is_draining() {
+        // Get the next job, by priority class and tenant turn
+        let job_id = match state.queue.pop_job().await {
+            Ok(Some(id)) => id,
+            Ok(None) => {
+                // No jobs, wait a bit
+                idle(&state.lifecycle, Duration::from_millis(100)).await;
+                continue;
+            }
+            Err(e) => {
+                error!("Queue error: {}", e);
+                idle(&state.lifecycle, Duration::from_secs(1)).await;
+                continue;
             }
         };
         
@@ -34,84 +38,449 @@ pub async fn run_worker(state: Arc<ServiceState>) {
         let job_id = match job_id.parse::<uuid::Uuid>() {
             Ok(id) => id,
             Err(e) => {
-                error!("Invalid job ID: {}", e);
+                error!("Invalid job ID {:?}: {}", job_id, e);
+                dead_letter_payload(&state, job_id, format!("Invalid job ID: {}", e)).await;
                 continue;
             }
         };
         
         // Process job
-        if let Err(e) = process_job(&state, job_id).await {
-            error!("Error processing job {}: {}", job_id, e);
+        state.lifecycle.set_busy(true);
+        run_job(&state, job_id, drain_timeout).await;
+        state.lifecycle.set_busy(false);
+    }
+    info!("Execution worker stopped");
+}
+
+// Jobs queued before an upgrade to priority classes would never be picked
+// from the old list. Requeue them, oldest first; entries that can't be
+// requeued are dead-lettered rather than dropped.
+async fn migrate_legacy_queue(state: &ServiceState) {
+    let mut moved = 0;
+    loop {
+        let payload = match state.queue.pop_legacy().await {
+            Ok(Some(payload)) => payload,
+            Ok(None) => break,
+            Err(e) => {
+                error!("Failed to read the legacy queue: {}", e);
+                break;
+            }
+        };
+        let job_id = match payload.parse::<uuid::Uuid>() {
+            Ok(id) => id,
+            Err(e) => {
+                dead_letter_payload(state, payload, format!("Invalid job ID: {}", e)).await;
+                continue;
+            }
+        };
+        let requeued = match state.get_execution(job_id).await {
+            // Anything else was cancelled or finished meanwhile
+            Ok(job) if job.status != JobStatus::Queued => Ok(()),
+            Ok(job) => {
+                let pushed = state.queue.push_job(&job).await;
+                if pushed.is_ok() {
+                    moved += 1;
+                }
+                pushed
+            }
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = requeued {
+            error!("Failed to requeue legacy job {}: {}", job_id, e);
+            dead_letter_payload(state, payload, format!("Legacy queue migration failed: {}", e)).await;
         }
     }
+    if moved > 0 {
+        info!("Moved {} jobs from the legacy queue", moved);
+    }
+}
+
+// Sleep, waking early if draining starts
+async fn idle(lifecycle: &Lifecycle, duration: Duration) {
+    tokio::select! {
+        _ = tokio::time::sleep(duration) => {}
+        _ = lifecycle.draining() => {}
+    }
+}
+
+// Once draining starts a job has `drain_timeout` to finish. After that its
+// container is killed and it's retried like any infrastructure failure, by
+// whichever instance gets to it next.
+async fn run_job(state: &ServiceState, job_id: uuid::Uuid, drain_timeout: Duration) {
+    let deadline = async {
+        state.lifecycle.draining().await;
+        tokio::time::sleep(drain_timeout).await;
+    };
+    tokio::select! {
+        result = process_job(state, job_id) => {
+            if let Err(e) = result {
+                error!("Error processing job {}: {}", job_id, e);
+            }
+        }
+        _ = deadline => {
+            warn!("Job {} didn't finish within the drain timeout; interrupting it", job_id);
+            if let Err(e) = interrupt_job(state, job_id).await {
+                error!("Failed to requeue interrupted job {}: {}", job_id, e);
+            }
+        }
+    }
+}
+
+async fn interrupt_job(state: &ServiceState, job_id: uuid::Uuid) -> anyhow::Result<()> {
+    if let Err(e) = state.executor.abort(job_id).await {
+        warn!("Failed to remove container of interrupted job {}: {}", job_id, e);
+    }
+    let job = state.get_execution(job_id).await?;
+    // It may have finished or been cancelled just as time ran out
+    if job.status != JobStatus::Running {
+        return Ok(());
+    }
+    handle_infrastructure_failure(state, job, "Interrupted by service shutdown".to_string()).await
 }
 
 async fn process_job(state: &ServiceState, job_id: uuid::Uuid) -> anyhow::Result<()> {
     info!("Processing job {}", job_id);
     
     // Get job details
-    let mut job = state.get_execution(job_id).await?;
+    let mut job = match state.get_execution(job_id).await {
+        Ok(job) => job,
+        Err(ServiceError::NotFound) => {
+            error!("Job {} was queued but its record is missing", job_id);
+            dead_letter_payload(state, job_id.to_string(), "Job record not found".to_string()).await;
+            return Ok(());
+        }
+        Err(e) => return Err(e.into()),
+    };
+    if !matches!(job.status, JobStatus::Queued) {
+        info!("Skipping job {} with status {:?}", job_id, job.status);
+        return Ok(());
+    }
     
     // Update status to running
     job.status = JobStatus::Running;
     job.started_at = Some(chrono::Utc::now());
-    update_job(state, &job).await?;
-    
-    // Execute
-    let result = state.docker_executor
-        .execute(
-            &job.request.code,
-            &job.request.language,
-            job.request.timeout_seconds.unwrap_or(30),
-        )
-        .await;
+    if !state.save_execution(&job).await? {
+        info!("Job {} was cancelled before it started", job_id);
+        return Ok(());
+    }
+    
+    // A secret deleted since submission is the user's problem; storage
+    // trouble is ours
+    let secrets = match state.resolve_secrets(&job).await {
+        Ok(secrets) => secrets,
+        Err(e) if !e.retryable() => return fail_job(state, job, e.public_message()).await,
+        Err(e) => return handle_infrastructure_failure(state, job, e.to_string()).await,
+    };
+    
+    if let Some(test_cases) = job.request.test_cases.clone() {
+        return process_batch_job(state, job, &test_cases, secrets).await;
+    }
+    
+    // Execute, publishing output as it is produced
+    let redactor = Redactor::new(secrets.values());
+    let (output, chunks) = mpsc::unbounded_channel();
+    let (result, ()) = tokio::join!(
+        state.executor.execute(job.id, &job.request, secrets, Some(output)),
+        publish_output(state, &job, &redactor, chunks),
+    );
     
-    // Update job with result
-    match result {
-        Ok(exec_result) => {
-            job.status = if exec_result.timed_out {
-                JobStatus::Timeout
-            } else if exec_result.exit_code == 0 {
-                JobStatus::Completed
-            } else {
-                JobStatus::Failed
-            };
-            
-            job.result = Some(ExecutionResult {
-                exit_code: exec_result.exit_code,
-                stdout: exec_result.stdout,
-                stderr: exec_result.stderr,
-                duration_ms: exec_result.duration_ms,
-            });
+    // Docker failing to start the program isn't the program's fault
+    let exec_result = match result {
+        Ok(mut exec_result) => {
+            redactor.redact_result(&mut exec_result);
+            match exec_result.infrastructure_error() {
+                Some(error) => return handle_infrastructure_failure(state, job, error).await,
+                None => exec_result,
+            }
         }
-        Err(e) => {
-            job.status = JobStatus::Failed;
-            job.result = Some(ExecutionResult {
-                exit_code: -1,
-                stdout: String::new(),
-                stderr: format!("Execution error: {}", e),
-                duration_ms: 0,
-            });
+        Err(e) => return handle_infrastructure_failure(state, job, e.to_string()).await,
+    };
+    
+    job.status = exec_result.termination.job_status();
+    let error = (job.status != JobStatus::Completed).then(|| exec_result.termination.describe());
+    let encoding = job.request.output_encoding.unwrap_or_default();
+    job.result = Some(exec_result.into_result(encoding));
+    record_attempt(&mut job, error);
+    
+    complete_job(state, &mut job).await?;
+    
+    info!("Job {} completed with status {:?}", job_id, job.status);
+    Ok(())
+}
+
+async fn process_batch_job(
+    state: &ServiceState,
+    mut job: ExecutionJob,
+    test_cases: &[TestCase],
+    secrets: HashMap<String, String>,
+) -> anyhow::Result<()> {
+    let result = state.executor
+        .execute_batch(job.id, &job.request, test_cases, secrets)
+        .await;
+    
+    let batch = match result {
+        Ok(batch) => batch,
+        Err(e) => return handle_infrastructure_failure(state, job, e.to_string()).await,
+    };
+    
+    // A failed compile leaves every case unrun
+    let compile_failed = batch.compile.as_ref().is_some_and(|c| c.exit_code != 0);
+    job.status = if compile_failed {
+        JobStatus::Failed
+    } else {
+        JobStatus::Completed
+    };
+    job.result = batch.compile.clone();
+    job.batch = Some(batch);
+    record_attempt(&mut job, compile_failed.then(|| "Compilation failed".to_string()));
+    
+    complete_job(state, &mut job).await?;
+    
+    info!("Batch job {} completed with status {:?}", job.id, job.status);
+    Ok(())
+}
+
+// Output of one stream not yet published: raw bytes that may be the start
+// of a secret, and redacted bytes ending partway through a UTF-8 sequence
+#[derive(Default)]
+struct PendingOutput {
+    raw: Vec<u8>,
+    text: Vec<u8>,
+}
+
+impl PendingOutput {
+    // Everything that can be published now, or all of it once the stream has
+    // ended
+    fn take(&mut self, redactor: &Redactor, encoding: OutputEncoding, ended: bool) -> String {
+        let (redacted, consumed) = redactor.redact_until(&self.raw, !ended);
+        self.raw.drain(..consumed);
+        match encoding {
+            OutputEncoding::Base64 => BASE64.encode(&redacted),
+            OutputEncoding::Utf8 => {
+                self.text.extend_from_slice(&redacted);
+                let complete = match std::str::from_utf8(&self.text) {
+                    Ok(_) => self.text.len(),
+                    Err(e) if e.error_len().is_none() && !ended => e.valid_up_to(),
+                    Err(_) => self.text.len(),
+                };
+                let rest = self.text.split_off(complete);
+                let data = String::from_utf8_lossy(&self.text).into_owned();
+                self.text = rest;
+                data
+            }
         }
     }
+}
+
+// Turn output chunks into events until the program's streams close. Secret
+// values are redacted, and anything that might turn out to be one, or a
+// split UTF-8 sequence, is held back until more output settles it.
+async fn publish_output(
+    state: &ServiceState,
+    job: &ExecutionJob,
+    redactor: &Redactor,
+    mut chunks: mpsc::UnboundedReceiver<(OutputStream, Vec<u8>)>,
+) {
+    let encoding = job.request.output_encoding.unwrap_or_default();
+    let mut pending: [PendingOutput; 2] = Default::default();
     
-    job.completed_at = Some(chrono::Utc::now());
-    update_job(state, &job).await?;
+    while let Some((stream, chunk)) = chunks.recv().await {
+        let buffer = &mut pending[stream as usize];
+        buffer.raw.extend_from_slice(&chunk);
+        let data = buffer.take(redactor, encoding, false);
+        publish_chunk(state, job, stream, data).await;
+    }
+    for stream in [OutputStream::Stdout, OutputStream::Stderr] {
+        let data = pending[stream as usize].take(redactor, encoding, true);
+        publish_chunk(state, job, stream, data).await;
+    }
+}
+
+async fn publish_chunk(state: &ServiceState, job: &ExecutionJob, stream: OutputStream, data: String) {
+    if data.is_empty() {
+        return;
+    }
+    if let Err(e) = state.publish_event(job, EventKind::Output { stream, data }).await {
+        warn!("Failed to publish output of job {}: {}", job.id, e);
+    }
+}
+
+// Keep a queue entry that can't be processed for inspection
+async fn dead_letter_payload(state: &ServiceState, payload: String, error: String) {
+    if let Err(e) = state.dlq.push(&DeadLetter::for_payload(payload, error)).await {
+        error!("Failed to dead-letter queue entry: {}", e);
+    }
+}
+
+// Add the run that just ended to the job's history. Runs that reach the
+// program are final, so any failure here is the user's.
+fn record_attempt(job: &mut ExecutionJob, error: Option<String>) {
+    push_attempt(job, error.is_some().then_some(FailureKind::User), error);
+}
+
+fn push_attempt(job: &mut ExecutionJob, failure: Option<FailureKind>, error: Option<String>) {
+    let now = chrono::Utc::now();
+    job.attempts.push(Attempt {
+        number: job.attempts.len() as u32 + 1,
+        started_at: job.started_at.unwrap_or(now),
+        completed_at: now,
+        failure,
+        error,
+    });
+}
+
+// End a job that can't run, without retrying
+async fn fail_job(state: &ServiceState, mut job: ExecutionJob, error: String) -> anyhow::Result<()> {
+    info!("Job {} failed before running: {}", job.id, error);
+    job.status = JobStatus::Failed;
+    job.result = Some(ExecutionResult::from_error(error.clone()));
+    record_attempt(&mut job, Some(error));
+    complete_job(state, &mut job).await
+}
+
+// Retry with backoff while attempts remain, otherwise fail the job and
+// dead-letter it
+async fn handle_infrastructure_failure(
+    state: &ServiceState,
+    mut job: ExecutionJob,
+    error: String,
+) -> anyhow::Result<()> {
+    push_attempt(&mut job, Some(FailureKind::Infrastructure), Some(error.clone()));
+    let attempts = job.attempts.len() as u32;
     
-    info!("Job {} completed with status {:?}", job_id, job.status);
+    let cancelled = state
+        .get_execution(job.id)
+        .await
+        .map(|current| current.status == JobStatus::Cancelled)
+        .unwrap_or(false);
+    
+    let retrying = state.retry.should_retry(attempts) && !cancelled;
+    let event = EventKind::Error {
+        code: "infrastructure_failure".to_string(),
+        message: error.clone(),
+        fatal: !retrying,
+    };
+    if let Err(e) = state.publish_event(&job, event).await {
+        warn!("Failed to publish failure of job {}: {}", job.id, e);
+    }
+    
+    if retrying {
+        let delay = state.retry.backoff(attempts);
+        warn!(
+            "Job {} hit an infrastructure failure (attempt {}/{}), retrying in {:?}: {}",
+            job.id, attempts, state.retry.max_attempts, delay, error
+        );
+        let retry_at = chrono::Utc::now() + chrono::Duration::from_std(delay)?;
+        state.retry_execution(&mut job, retry_at).await?;
+        return Ok(());
+    }
+    
+    job.status = JobStatus::Failed;
+    job.result = Some(ExecutionResult::from_error(format!(
+        "Infrastructure failure after {} attempts: {}",
+        attempts, error
+    )));
+    complete_job(state, &mut job).await?;
+    
+    if !cancelled {
+        error!("Job {} failed after {} attempts, moving to dead-letter queue", job.id, attempts);
+        state.dlq.push(&DeadLetter::for_job(&job, error)).await?;
+    }
     Ok(())
 }
 
-async fn update_job(state: &ServiceState, job: &ExecutionJob) -> anyhow::Result<()> {
-    let mut redis = state.redis.lock().await;
-    let job_key = format!("job:{}", job.id);
-    let job_json = serde_json::to_string(job)?;
+// Record a finished job. If it was cancelled while running the cancellation
+// stands and only the result is attached.
+async fn complete_job(state: &ServiceState, job: &mut ExecutionJob) -> anyhow::Result<()> {
+    job.completed_at = Some(chrono::Utc::now());
+    if !state.finish_execution(job).await? {
+        let current = state.get_execution(job.id).await?;
+        job.status = JobStatus::Cancelled;
+        job.completed_at = current.completed_at;
+        // Webhooks for the cancellation may already be under way
+        job.webhook_deliveries = current.webhook_deliveries;
+        state.save_execution(job).await?;
+        return Ok(());
+    }
     
-    redis::cmd("SET")
-        .arg(&job_key)
-        .arg(&job_json)
-        .query_async::<_, ()>(&mut *redis)
-        .await?;
-        
+    if let Err(e) = state.cache_result(job).await {
+        warn!("Failed to cache result of job {}: {}", job.id, e);
+    }
+    
+    if let (Some(started_at), Some(completed_at)) = (job.started_at, job.completed_at) {
+        let duration_ms = (completed_at - started_at).num_milliseconds().max(0) as u64;
+        if let Err(e) = state.queue.record_duration(duration_ms).await {
+            error!("Failed to record duration of job {}: {}", job.id, e);
+        }
+    }
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn redactor() -> Redactor {
+        Redactor::new(&["hunter2".to_string()])
+    }
+
+    // Feed chunks in, collecting what gets published after each one
+    fn publish(pending: &mut PendingOutput, chunks: &[&[u8]], encoding: OutputEncoding) -> Vec<String> {
+        let mut published: Vec<String> = chunks
+            .iter()
+            .map(|chunk| {
+                pending.raw.extend_from_slice(chunk);
+                pending.take(&redactor(), encoding, false)
+            })
+            .collect();
+        published.push(pending.take(&redactor(), encoding, true));
+        published
+    }
+
+    #[test]
+    fn secrets_split_across_chunks_are_redacted() {
+        let mut pending = PendingOutput::default();
+        let published = publish(&mut pending, &[b"pw=hun", b"ter2\n"], OutputEncoding::Utf8);
+        assert_eq!(published, ["pw=", "[REDACTED]\n", ""]);
+    }
+
+    #[test]
+    fn held_back_output_is_flushed_at_the_end() {
+        let mut pending = PendingOutput::default();
+        let published = publish(&mut pending, &[b"almost hunt"], OutputEncoding::Utf8);
+        assert_eq!(published, ["almost ", "hunt"]);
+        assert!(pending.raw.is_empty() && pending.text.is_empty());
+    }
+
+    #[test]
+    fn multibyte_characters_are_not_split() {
+        let mut pending = PendingOutput::default();
+        // "é€" split inside both characters
+        let published = publish(&mut pending, &[b"a\xc3", b"\xa9\xe2\x82", b"\xac"], OutputEncoding::Utf8);
+        assert_eq!(published, ["a", "é", "€", ""]);
+
+        // A sequence cut short by the end of the stream is replaced
+        let mut pending = PendingOutput::default();
+        let published = publish(&mut pending, &[b"b\xe2\x82"], OutputEncoding::Utf8);
+        assert_eq!(published, ["b", "\u{fffd}"]);
+    }
+
+    #[test]
+    fn base64_output_is_not_held_for_utf8() {
+        let mut pending = PendingOutput::default();
+        let published = publish(&mut pending, &[b"a\xc3"], OutputEncoding::Base64);
+        assert_eq!(published, [BASE64.encode(b"a\xc3"), String::new()]);
+    }
+
+    #[tokio::test]
+    async fn idling_ends_once_draining_starts() {
+        let lifecycle = Lifecycle::new();
+        let drained = tokio::time::timeout(Duration::from_secs(5), async {
+            tokio::join!(idle(&lifecycle, Duration::from_secs(60)), async { lifecycle.start_draining() })
+        })
+        .await;
+        assert!(drained.is_ok());
+        // The loop checks this before every pop
+        assert!(lifecycle.is_draining());
+    }
+}